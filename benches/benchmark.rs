@@ -1,21 +1,61 @@
 use criterion::Criterion;
 use rsmq::*;
-use futures::executor::block_on;
+use tokio::runtime::{Builder, Runtime};
+
+static MSG_BODY: &str =
+	"abcdefghijklmnopqrstuvxyzabcdefghijklmnopqrstuvxyzabcdefghijklmnopqrstuvxyzabcdefghijklmnopqrstuvxyzabcdefghijklmnopqrstuvxyzabcdefghijklmnopqrstuvxyzabcdefghijklmnopqrstuvxyz";
+
+fn runtime() -> Runtime {
+	Builder::new().basic_scheduler().enable_all().build().expect("Can't build a tokio runtime")
+}
+
+// What `send_message` used to do before every operation became a single script: fetch the queue attributes and the
+// server time in one round trip, then write the message in a second one.
+async fn two_round_trip_send(con: &mut redis::aio::Connection, id: u64) {
+	let ((_vt, delay, _maxsize), (secs, micros)): ((u64, u64, i64), (u64, u64)) = redis::pipe()
+		.atomic()
+		.cmd("HMGET").arg("rsmq:bench-queue:Q").arg("vt").arg("delay").arg("maxsize")
+		.cmd("TIME")
+		.query_async(con)
+		.await
+		.expect("no, did not get the queue");
+	let ts = (secs * 1_000_000 + micros) / 1_000;
+	let uid = format!("legacy{}", id);
+	redis::pipe()
+		.atomic()
+		.cmd("ZADD").arg("rsmq:bench-queue").arg(ts + delay * 1000).arg(&uid).ignore()
+		.cmd("HSET").arg("rsmq:bench-queue:Q").arg(&uid).arg(MSG_BODY).ignore()
+		.cmd("HINCRBY").arg("rsmq:bench-queue:Q").arg("totalsent").arg(1).ignore()
+		.query_async::<_, ()>(con)
+		.await
+		.expect("no, did not send that");
+}
 
 #[test]
 fn criterion_benchmark() {
-	let rsmq = block_on(Rsmq::new("redis://127.0.0.1/", "rsmq"))
+	let mut rt = runtime();
+	let rsmq = rt.block_on(Rsmq::new("redis://127.0.0.1/", "rsmq"))
 		.expect("Can't instantiate RSMQ");
 	let q = Queue::new("bench-queue", Some(60), Some(0), Some(1200));
-	block_on(rsmq.create_queue(q))
+	rt.block_on(rsmq.create_queue(q))
 		.expect("queue creation failed");
-	static MSG_BODY: &str =
-		"abcdefghijklmnopqrstuvxyzabcdefghijklmnopqrstuvxyzabcdefghijklmnopqrstuvxyzabcdefghijklmnopqrstuvxyzabcdefghijklmnopqrstuvxyzabcdefghijklmnopqrstuvxyzabcdefghijklmnopqrstuvxyz";
+
+	let mut con = rt.block_on(async {
+		redis::Client::open("redis://127.0.0.1/")?.get_async_connection().await
+	}).expect("Can't connect to Redis");
+	let mut legacy_id = 0;
+	Criterion::default().bench_function("send message (two round trips)", |b| {
+		b.iter(|| {
+			legacy_id += 1;
+			rt.block_on(two_round_trip_send(&mut con, legacy_id));
+		})
+	});
+
 	Criterion::default().bench_function("send message", |b| {
 		b.iter(|| {
-			let fut= rsmq
+			let fut = rsmq
 				.send_message("bench-queue", MSG_BODY, None);
-			block_on(fut)
+			rt.block_on(fut)
 				.expect("no, did not send that");
 		})
 	});
@@ -25,11 +65,30 @@ fn criterion_benchmark() {
 		b.iter(|| {
 			let fut = rsmq
 				.receive_message("bench-queue", None);
-			let w = block_on(fut).expect("no, did not receive that");
+			let w = rt.block_on(fut).expect("no, did not receive that");
 			work.push(w);
 		})
 	});
-	let qattrs = block_on(rsmq.get_queue_attributes("bench-queue"));
+
+	Criterion::default().bench_function("change message visibility", |b| {
+		let mut received = work.iter().cycle();
+		b.iter(|| {
+			let msg = received.next().expect("nothing was received");
+			let fut = rsmq
+				.change_message_visibility("bench-queue", &msg.id, 60);
+			rt.block_on(fut).expect("no, did not change that");
+		})
+	});
+
+	Criterion::default().bench_function("send and pop message", |b| {
+		b.iter(|| {
+			rt.block_on(rsmq.send_message("bench-queue", MSG_BODY, None))
+				.expect("no, did not send that");
+			rt.block_on(rsmq.pop_message("bench-queue"))
+				.expect("no, did not pop that");
+		})
+	});
+	let qattrs = rt.block_on(rsmq.get_queue_attributes("bench-queue"));
 	println!("Work to do: {}", work.len());
 	println!("Queue: {:?}", qattrs);
 }
@@ -0,0 +1,4 @@
+fn main() {
+	#[cfg(feature = "grpc")]
+	tonic_build::compile_protos("proto/rsmq.proto").expect("can't compile proto/rsmq.proto");
+}
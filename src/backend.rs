@@ -0,0 +1,84 @@
+use async_trait::async_trait;
+use failure::Error;
+
+use crate::{Message, Queue, Rsmq};
+
+/// The queue operations of [`Rsmq`], for code that should also run against another storage, e.g. a
+/// [`crate::MemoryBackend`] in tests that have no Redis server at hand.
+///
+/// Every implementation follows the semantics of the Redis one: times are in seconds, a received message stays hidden
+/// for `vt` seconds (or `hidefor`), `rc` counts the receives and `fr` is the time of the first one in milliseconds. An
+/// empty queue makes `receive_message` and `pop_message` fail with a `TryAgain` [`redis::RedisError`], an unknown queue
+/// with a `ResponseError` whose detail is `Queue not found`.
+///
+/// ```no_run
+/// use rsmq::QueueBackend;
+///
+/// async fn work(queue: &dyn QueueBackend) -> Result<(), failure::Error> {
+///    let msg = queue.receive_message("jobs", None).await?;
+///    // ...
+///    queue.delete_message("jobs", &msg.id).await?;
+///    Ok(())
+/// }
+/// ```
+#[async_trait]
+pub trait QueueBackend: Send + Sync {
+	/// Create a queue with the `vt`, `delay` and `maxsize` of `opts`. Returns `1` if it was created and `0` if it
+	/// already existed.
+	async fn create_queue(&self, opts: Queue) -> Result<u8, Error>;
+
+	async fn list_queues(&self) -> Result<Vec<String>, Error>;
+
+	/// Delete a queue and all its messages.
+	async fn delete_queue(&self, qname: &str) -> Result<(), Error>;
+
+	/// Delete all messages of a queue but keep the queue. Returns the number of deleted messages.
+	async fn purge_queue(&self, qname: &str) -> Result<u64, Error>;
+
+	/// Returns the id of the new message.
+	async fn send_message(&self, qname: &str, message: &str, delay: Option<u64>) -> Result<String, Error>;
+
+	async fn receive_message(&self, qname: &str, hidefor: Option<u64>) -> Result<Message, Error>;
+
+	/// Receive and delete a message.
+	async fn pop_message(&self, qname: &str) -> Result<Message, Error>;
+
+	/// Returns `false` if there was no such message.
+	async fn delete_message(&self, qname: &str, msgid: &str) -> Result<bool, Error>;
+
+	/// Hide a message for `hidefor` seconds from now. Returns the time, in milliseconds, it becomes visible again.
+	async fn change_message_visibility(&self, qname: &str, msgid: &str, hidefor: u64) -> Result<u64, Error>;
+
+	async fn get_queue_attributes(&self, qname: &str) -> Result<Queue, Error>;
+
+	async fn set_queue_attributes(&self, qname: &str, vt: Option<u64>, delay: Option<u64>, maxsize: Option<i64>) -> Result<Queue, Error>;
+}
+
+#[async_trait]
+impl QueueBackend for Rsmq {
+	async fn create_queue(&self, opts: Queue) -> Result<u8, Error> { Rsmq::create_queue(self, opts).await }
+
+	async fn list_queues(&self) -> Result<Vec<String>, Error> { Rsmq::list_queues(self).await }
+
+	async fn delete_queue(&self, qname: &str) -> Result<(), Error> { Rsmq::delete_queue(self, qname).await.map(|_| ()) }
+
+	async fn purge_queue(&self, qname: &str) -> Result<u64, Error> { Rsmq::purge_queue(self, qname).await }
+
+	async fn send_message(&self, qname: &str, message: &str, delay: Option<u64>) -> Result<String, Error> { Rsmq::send_message(self, qname, message, delay).await }
+
+	async fn receive_message(&self, qname: &str, hidefor: Option<u64>) -> Result<Message, Error> { Rsmq::receive_message(self, qname, hidefor).await }
+
+	async fn pop_message(&self, qname: &str) -> Result<Message, Error> { Rsmq::pop_message(self, qname).await }
+
+	async fn delete_message(&self, qname: &str, msgid: &str) -> Result<bool, Error> { Rsmq::delete_message(self, qname, msgid).await }
+
+	async fn change_message_visibility(&self, qname: &str, msgid: &str, hidefor: u64) -> Result<u64, Error> {
+		Rsmq::change_message_visibility(self, qname, msgid, hidefor).await
+	}
+
+	async fn get_queue_attributes(&self, qname: &str) -> Result<Queue, Error> { Rsmq::get_queue_attributes(self, qname).await }
+
+	async fn set_queue_attributes(&self, qname: &str, vt: Option<u64>, delay: Option<u64>, maxsize: Option<i64>) -> Result<Queue, Error> {
+		Rsmq::set_queue_attributes(self, qname, vt, delay, maxsize).await
+	}
+}
@@ -0,0 +1,5 @@
+//! Serve the queues of a Redis server over gRPC. See `proto/rsmq.proto` for the service.
+
+mod server;
+
+fn main() { server::main("rsmq-grpc", "A gRPC service for RSMQ queues", "127.0.0.1:50051", rsmq::grpc::serve) }
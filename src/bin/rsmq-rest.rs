@@ -0,0 +1,5 @@
+//! Serve the queues of a Redis server over HTTP, compatible with the JS rest-rsmq. See `rsmq::rest` for the routes.
+
+mod server;
+
+fn main() { server::main("rsmq-rest", "An HTTP API for RSMQ queues, compatible with rest-rsmq", "127.0.0.1:8101", rsmq::rest::serve) }
@@ -0,0 +1,5 @@
+//! Serve the queues of a Redis server over HTTP, with a subset of the AWS SQS API. See `rsmq::sqs` for the supported actions.
+
+mod server;
+
+fn main() { server::main("rsmq-sqs", "A subset of the AWS SQS API for RSMQ queues", "127.0.0.1:9324", rsmq::sqs::serve) }
@@ -0,0 +1,231 @@
+//! Administer RSMQ queues and their messages without knowing the Redis key layout.
+//!
+//! ```text
+//! rsmq --url redis://127.0.0.1/ create-queue jobs --vt 60
+//! echo '{"task": 1}' | rsmq send jobs
+//! rsmq --json receive jobs
+//! ```
+
+use clap::{Parser, Subcommand};
+use failure::Error;
+use rsmq::{is_no_messages, KeyLayout, Message, Queue, RsmqBuilder};
+use serde_json::json;
+use std::io::{BufRead, Read};
+use std::time::Duration;
+
+#[derive(Parser)]
+#[command(name = "rsmq", version, about = "Manage RSMQ queues and messages")]
+struct Cli {
+	/// The Redis server
+	#[arg(long, env = "RSMQ_URL", default_value = "redis://127.0.0.1/")]
+	url: String,
+	/// The namespace of the queues
+	#[arg(long, env = "RSMQ_NS", default_value = "rsmq")]
+	ns: String,
+	/// Use the hash tagged key layout of Redis Cluster deployments
+	#[arg(long)]
+	hash_tagged: bool,
+	/// Print JSON instead of text
+	#[arg(long)]
+	json: bool,
+	#[command(subcommand)]
+	command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+	/// Create a queue
+	CreateQueue {
+		qname: String,
+		/// Seconds a received message stays hidden
+		#[arg(long)]
+		vt: Option<u64>,
+		/// Seconds new messages are hidden for
+		#[arg(long)]
+		delay: Option<u64>,
+		/// Maximum message size in bytes, -1 for unlimited
+		#[arg(long, allow_negative_numbers = true)]
+		maxsize: Option<i64>,
+	},
+	/// List all queues
+	ListQueues,
+	/// Delete a queue and its messages
+	DeleteQueue { qname: String },
+	/// Delete all messages of a queue
+	PurgeQueue { qname: String },
+	/// Show the attributes and statistics of a queue
+	GetAttributes { qname: String },
+	/// Change the attributes of a queue
+	SetAttributes {
+		qname: String,
+		#[arg(long)]
+		vt: Option<u64>,
+		#[arg(long)]
+		delay: Option<u64>,
+		#[arg(long, allow_negative_numbers = true)]
+		maxsize: Option<i64>,
+	},
+	/// Send a message, read from stdin if not given
+	Send {
+		qname: String,
+		message: Option<String>,
+		/// Seconds the message is hidden for
+		#[arg(long)]
+		delay: Option<u64>,
+		/// Send every line read from stdin as a message
+		#[arg(long, conflicts_with = "message")]
+		lines: bool,
+		/// Send stdin as it is, without removing a trailing newline
+		#[arg(long, conflicts_with_all = ["message", "lines"])]
+		raw: bool,
+	},
+	/// Receive a message, hiding it for the visibility timeout
+	Receive {
+		qname: String,
+		/// Seconds to hide the message for instead of the queue's `vt`
+		#[arg(long)]
+		vt: Option<u64>,
+	},
+	/// Receive and delete a message
+	Pop { qname: String },
+	/// Delete a message
+	Delete { qname: String, id: String },
+	/// Hide a message for `vt` seconds from now
+	ChangeVisibility { qname: String, id: String, vt: u64 },
+}
+
+fn main() {
+	let cli = Cli::parse();
+	let mut runtime = tokio::runtime::Builder::new().basic_scheduler().enable_all().build().expect("can't start the runtime");
+	if let Err(e) = runtime.block_on(run(cli)) {
+		eprintln!("rsmq: {}", e);
+		std::process::exit(1);
+	}
+}
+
+async fn run(cli: Cli) -> Result<(), Error> {
+	let key_layout = if cli.hash_tagged { KeyLayout::HashTagged } else { KeyLayout::Plain };
+	let rsmq = RsmqBuilder::new(cli.url.as_str()).name_space(&cli.ns).key_layout(key_layout).max_size(1).connection_timeout(Duration::from_secs(5)).build().await?;
+	let json = cli.json;
+	match cli.command {
+		Command::CreateQueue { qname, vt, delay, maxsize } => {
+			let created = rsmq.create_queue(Queue::new(&qname, vt, delay, maxsize)).await? == 1;
+			print(json, json!({ "created": created }), || if created { format!("Created queue {}", qname) } else { format!("Queue {} already exists", qname) });
+		}
+		Command::ListQueues => {
+			let mut queues = rsmq.list_queues().await?;
+			queues.sort();
+			print(json, json!({ "queues": queues }), || queues.join("\n"));
+		}
+		Command::DeleteQueue { qname } => {
+			rsmq.delete_queue(&qname).await?;
+			print(json, json!({ "deleted": true }), || format!("Deleted queue {}", qname));
+		}
+		Command::PurgeQueue { qname } => {
+			let purged = rsmq.purge_queue(&qname).await?;
+			print(json, json!({ "purged": purged }), || format!("Deleted {} messages from {}", purged, qname));
+		}
+		Command::GetAttributes { qname } => {
+			let q = rsmq.get_queue_attributes(&qname).await?;
+			print_queue(json, &q);
+		}
+		Command::SetAttributes { qname, vt, delay, maxsize } => {
+			let q = rsmq.set_queue_attributes(&qname, vt, delay, maxsize).await?;
+			print_queue(json, &q);
+		}
+		Command::Send { qname, message, delay, lines, raw } => {
+			let messages = match message {
+				Some(message) => vec![message],
+				None if lines => std::io::stdin().lock().lines().collect::<Result<_, _>>()?,
+				None => {
+					let mut message = String::new();
+					std::io::stdin().read_to_string(&mut message)?;
+					if !raw {
+						strip_newline(&mut message);
+					}
+					vec![message]
+				}
+			};
+			let mut ids = Vec::with_capacity(messages.len());
+			for message in &messages {
+				ids.push(rsmq.send_message(&qname, message, delay).await?);
+			}
+			if lines {
+				print(json, json!({ "ids": ids }), || ids.join("\n"));
+			} else {
+				print(json, json!({ "id": ids[0] }), || ids[0].clone());
+			}
+		}
+		Command::Receive { qname, vt } => print_message(json, rsmq.receive_message(&qname, vt).await)?,
+		Command::Pop { qname } => print_message(json, rsmq.pop_message(&qname).await)?,
+		Command::Delete { qname, id } => {
+			let deleted = rsmq.delete_message(&qname, &id).await?;
+			print(json, json!({ "deleted": deleted }), || if deleted { format!("Deleted {}", id) } else { format!("No message {}", id) });
+		}
+		Command::ChangeVisibility { qname, id, vt } => {
+			let visible_at = rsmq.change_message_visibility(&qname, &id, vt).await?;
+			print(json, json!({ "visible_at": visible_at }), || format!("{} is hidden until {}", id, visible_at));
+		}
+	}
+	Ok(())
+}
+
+// Remove one trailing `\n` or `\r\n`, as added by `echo` and most editors.
+fn strip_newline(s: &mut String) {
+	if s.ends_with('\n') {
+		s.pop();
+		if s.ends_with('\r') {
+			s.pop();
+		}
+	}
+}
+
+fn print<F: FnOnce() -> String>(json: bool, value: serde_json::Value, text: F) {
+	if json {
+		println!("{}", value);
+	} else {
+		println!("{}", text());
+	}
+}
+
+fn print_queue(json: bool, q: &Queue) {
+	let value = json!({
+		"qname": q.qname,
+		"vt": q.vt,
+		"delay": q.delay,
+		"maxsize": q.maxsize,
+		"totalrecv": q.totalrecv,
+		"totalsent": q.totalsent,
+		"created": q.created,
+		"modified": q.modified,
+		"msgs": q.msgs,
+		"hiddenmsgs": q.hiddenmsgs,
+	});
+	print(json, value, || {
+		format!(
+			"qname:      {}\nvt:         {}\ndelay:      {}\nmaxsize:    {}\ntotalrecv:  {}\ntotalsent:  {}\ncreated:    {}\nmodified:   {}\nmsgs:       {}\nhiddenmsgs: {}",
+			q.qname, q.vt, q.delay, q.maxsize, q.totalrecv, q.totalsent, q.created, q.modified, q.msgs, q.hiddenmsgs
+		)
+	});
+}
+
+// An empty queue is not an error: print `{}` or nothing, like the JS RSMQ returns an empty object.
+fn print_message(json: bool, msg: Result<Message, Error>) -> Result<(), Error> {
+	let msg = match msg {
+		Ok(msg) => msg,
+		Err(e) if is_no_messages(&e) => {
+			if json {
+				println!("{{}}");
+			} else {
+				eprintln!("No messages to receive");
+			}
+			return Ok(());
+		}
+		Err(e) => return Err(e),
+	};
+	// In milliseconds like `fr`, as rest-rsmq reports it.
+	let sent = msg.sent / 1000;
+	let value = json!({ "id": msg.id, "message": msg.message, "rc": msg.rc, "fr": msg.fr, "sent": sent });
+	print(json, value, || format!("id:   {}\nrc:   {}\nfr:   {}\nsent: {}\n\n{}", msg.id, msg.rc, msg.fr, sent, msg.message));
+	Ok(())
+}
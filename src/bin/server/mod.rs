@@ -0,0 +1,52 @@
+//! The arguments and `main` shared by the server binaries.
+
+use clap::{CommandFactory, FromArgMatches, Parser};
+use failure::Error;
+use rsmq::{KeyLayout, QueueBackend, RsmqBuilder};
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+#[derive(Parser)]
+#[command(version)]
+struct Args {
+	/// The Redis server
+	#[arg(long, env = "RSMQ_URL", default_value = "redis://127.0.0.1/")]
+	url: String,
+	/// The namespace of the queues
+	#[arg(long, env = "RSMQ_NS", default_value = "rsmq")]
+	ns: String,
+	/// Use the hash tagged key layout of Redis Cluster deployments
+	#[arg(long)]
+	hash_tagged: bool,
+	/// The address to listen on
+	#[arg(long, env = "RSMQ_LISTEN")]
+	listen: SocketAddr,
+}
+
+/// Parse the arguments of the server binary `name`, connect to Redis and `serve` its queues on the address to listen on,
+/// `listen` by default.
+pub fn main<F, Fut>(name: &'static str, about: &'static str, listen: &'static str, serve: F)
+where
+	F: FnOnce(Arc<dyn QueueBackend>, SocketAddr) -> Fut,
+	Fut: Future<Output = Result<(), Error>>,
+{
+	let command = Args::command().name(name).about(about).mut_arg("listen", |a| a.default_value(listen).required(false));
+	let args = Args::from_arg_matches(&command.get_matches()).unwrap_or_else(|e| e.exit());
+	let mut runtime = tokio::runtime::Builder::new().threaded_scheduler().enable_all().build().expect("can't start the runtime");
+	if let Err(e) = runtime.block_on(run(name, args, serve)) {
+		eprintln!("{}: {}", name, e);
+		std::process::exit(1);
+	}
+}
+
+async fn run<F, Fut>(name: &str, args: Args, serve: F) -> Result<(), Error>
+where
+	F: FnOnce(Arc<dyn QueueBackend>, SocketAddr) -> Fut,
+	Fut: Future<Output = Result<(), Error>>,
+{
+	let key_layout = if args.hash_tagged { KeyLayout::HashTagged } else { KeyLayout::Plain };
+	let rsmq = RsmqBuilder::new(args.url.as_str()).name_space(&args.ns).key_layout(key_layout).build().await?;
+	eprintln!("{}: listening on http://{}", name, args.listen);
+	serve(Arc::new(rsmq), args.listen).await
+}
@@ -0,0 +1,72 @@
+use async_trait::async_trait;
+use failure::{format_err, Error};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::util::{message_id, now_us};
+
+/// Storage for message bodies too large for the queue, see [`RsmqBuilder::claim_check`](crate::RsmqBuilder::claim_check).
+///
+/// The message carries the reference returned by `put` instead of the body. Receiving the message resolves it with
+/// `get`, `delete_message` and `pop_message` `delete` it.
+#[async_trait]
+pub trait BlobStore: Send + Sync {
+	/// Store `data`, returning a reference to it of at most a few hundred printable characters.
+	async fn put(&self, data: &[u8]) -> Result<String, Error>;
+
+	async fn get(&self, reference: &str) -> Result<Vec<u8>, Error>;
+
+	/// Deleting a blob that doesn't exist isn't an error.
+	async fn delete(&self, reference: &str) -> Result<(), Error>;
+}
+
+/// A [`BlobStore`] keeping every blob in a file of a local directory, e.g. a volume shared by producers and consumers.
+///
+/// Files are read and written with `tokio::fs`, on the blocking thread pool of the runtime.
+#[derive(Clone, Debug)]
+pub struct FsBlobStore {
+	dir: PathBuf,
+}
+
+impl FsBlobStore {
+	/// Store blobs in `dir`, creating it if needed.
+	pub fn new<P: AsRef<Path>>(dir: P) -> Result<FsBlobStore, Error> {
+		std::fs::create_dir_all(dir.as_ref())?;
+		Ok(FsBlobStore { dir: dir.as_ref().into() })
+	}
+
+	// References come from received messages, only ones made by `put` may name a file.
+	fn path(&self, reference: &str) -> Result<PathBuf, Error> {
+		if reference.is_empty() || !reference.bytes().all(|b| b.is_ascii_alphanumeric()) {
+			return Err(format_err!("Invalid blob reference {:?}", reference));
+		}
+		Ok(self.dir.join(reference))
+	}
+}
+
+#[async_trait]
+impl BlobStore for FsBlobStore {
+	async fn put(&self, data: &[u8]) -> Result<String, Error> {
+		// Unique across processes sharing the directory, even for blobs stored in the same microsecond.
+		static COUNTER: AtomicU64 = AtomicU64::new(0);
+		let reference = format!("{}{:x}x{:x}", message_id(now_us()), std::process::id(), COUNTER.fetch_add(1, Ordering::Relaxed));
+		let path = self.path(&reference)?;
+		// Written under another name first, so a blob is never read half written.
+		let tmp = path.with_extension("tmp");
+		tokio::fs::write(&tmp, data).await?;
+		tokio::fs::rename(&tmp, &path).await?;
+		Ok(reference)
+	}
+
+	async fn get(&self, reference: &str) -> Result<Vec<u8>, Error> {
+		let path = self.path(reference)?;
+		tokio::fs::read(&path).await.map_err(|e| format_err!("Reading blob {:?} failed: {}", reference, e))
+	}
+
+	async fn delete(&self, reference: &str) -> Result<(), Error> {
+		match tokio::fs::remove_file(self.path(reference)?).await {
+			Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+			_ => Ok(()),
+		}
+	}
+}
@@ -0,0 +1,116 @@
+//! A synchronous [`Rsmq`] for code that does not run on an async executor.
+//!
+//! Every method blocks on the matching method of the async [`crate::Rsmq`], driven by a small runtime owned by the
+//! client, so callers don't need to set up tokio themselves. Don't use it from within an async context: blocking
+//! there stalls the executor.
+//!
+//! [`TypedQueue`](crate::TypedQueue), [`Delivery`](crate::Delivery) and
+//! [`IdempotentConsumer`](crate::IdempotentConsumer) handles are async-only; block on them with the client's
+//! [`runtime`](Rsmq::runtime) and [`as_async`](Rsmq::as_async).
+//!
+//! ```no_run
+//! use rsmq::{blocking::Rsmq, Queue};
+//!
+//! let rsmq = Rsmq::new("redis://127.0.0.1/", "rsmq").expect("Can't connect to Redis");
+//! rsmq.create_queue(Queue::new("my-queue", None, None, None)).expect("queue creation failed");
+//! let id = rsmq.send_message("my-queue", "hello", None).expect("no, did not send that");
+//! let msg = rsmq.receive_message("my-queue", None).expect("no, did not receive that");
+//! rsmq.delete_message("my-queue", &msg.id).expect("no, did not delete that");
+//! # let _ = id;
+//! ```
+
+use failure::Error;
+use redis::Value;
+use serde::Serialize;
+use tokio::runtime::{Builder, Runtime};
+
+use crate::{Backoff, Codec, Headers, KeyLayout, Message, Queue, RsmqBuilder};
+
+pub struct Rsmq {
+	// Declared before the runtime so the pool is dropped while the runtime is still around.
+	inner: crate::Rsmq,
+	runtime: Runtime,
+}
+
+impl std::fmt::Debug for Rsmq {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result { write!(f, "blocking {:?}", self.inner) }
+}
+
+impl Rsmq {
+	pub fn new<T: redis::IntoConnectionInfo>(params: T, name_space: &str) -> Result<Rsmq, Error> {
+		Rsmq::from_builder(RsmqBuilder::new(params).name_space(name_space))
+	}
+
+	/// See [`crate::Rsmq::new_cluster`].
+	pub fn new_cluster<T: redis::IntoConnectionInfo>(nodes: Vec<T>, name_space: &str) -> Result<Rsmq, Error> {
+		Rsmq::from_builder(RsmqBuilder::cluster(nodes).name_space(name_space))
+	}
+
+	/// See [`crate::Rsmq::new_sentinel`].
+	pub fn new_sentinel<T: redis::IntoConnectionInfo>(master_name: &str, sentinels: Vec<T>, name_space: &str) -> Result<Rsmq, Error> {
+		Rsmq::from_builder(RsmqBuilder::sentinel(master_name, sentinels).name_space(name_space))
+	}
+
+	/// Connect with all the options of an [`RsmqBuilder`]. Builders wrapping a multiplexed connection need the
+	/// connection's driver to run somewhere, which is hard without an executor; prefer a pool.
+	pub fn from_builder(builder: RsmqBuilder) -> Result<Rsmq, Error> {
+		let runtime = Builder::new().threaded_scheduler().core_threads(1).enable_all().build()?;
+		let inner = runtime.handle().block_on(builder.build())?;
+		Ok(Rsmq { inner, runtime })
+	}
+
+	/// The async client, e.g. to spawn work on its runtime with [`Rsmq::runtime`].
+	pub fn as_async(&self) -> &crate::Rsmq { &self.inner }
+
+	pub fn runtime(&self) -> &Runtime { &self.runtime }
+
+	pub fn default_queue(&self, qname: &str) -> Queue { self.inner.default_queue(qname) }
+
+	pub fn with_key_layout(mut self, key_layout: KeyLayout) -> Rsmq {
+		self.inner = self.inner.with_key_layout(key_layout);
+		self
+	}
+
+	pub fn key_layout(&self) -> KeyLayout { self.inner.key_layout() }
+
+	pub fn migrate_key_layout(&self, to: KeyLayout) -> Result<Vec<String>, Error> { self.block_on(self.inner.migrate_key_layout(to)) }
+
+	pub fn create_queue(&self, opts: Queue) -> Result<u8, Error> { self.block_on(self.inner.create_queue(opts)) }
+
+	pub fn delete_queue(&self, qname: &str) -> Result<Value, Error> { self.block_on(self.inner.delete_queue(qname)) }
+
+	pub fn purge_queue(&self, qname: &str) -> Result<u64, Error> { self.block_on(self.inner.purge_queue(qname)) }
+
+	pub fn list_queues(&self) -> Result<Vec<String>, Error> { self.block_on(self.inner.list_queues()) }
+
+	pub fn change_message_visibility(&self, qname: &str, msgid: &str, hidefor: u64) -> Result<u64, Error> {
+		self.block_on(self.inner.change_message_visibility(qname, msgid, hidefor))
+	}
+
+	pub fn send_message(&self, qname: &str, message: &str, delay: Option<u64>) -> Result<String, Error> { self.block_on(self.inner.send_message(qname, message, delay)) }
+
+	pub fn send_message_with_headers(&self, qname: &str, message: &str, delay: Option<u64>, headers: &Headers) -> Result<String, Error> {
+		self.block_on(self.inner.send_message_with_headers(qname, message, delay, headers))
+	}
+
+	/// See [`crate::Rsmq::send_encoded`].
+	pub fn send_encoded<C: Codec, T: Serialize + ?Sized>(&self, qname: &str, codec: &C, value: &T, delay: Option<u64>) -> Result<String, Error> {
+		self.block_on(self.inner.send_encoded(qname, codec, value, delay))
+	}
+
+	pub fn nack_message(&self, qname: &str, msgid: &str, backoff: &Backoff) -> Result<u64, Error> { self.block_on(self.inner.nack_message(qname, msgid, backoff)) }
+
+	pub fn delete_message(&self, qname: &str, msgid: &str) -> Result<bool, Error> { self.block_on(self.inner.delete_message(qname, msgid)) }
+
+	pub fn pop_message(&self, qname: &str) -> Result<Message, Error> { self.block_on(self.inner.pop_message(qname)) }
+
+	pub fn receive_message(&self, qname: &str, hidefor: Option<u64>) -> Result<Message, Error> { self.block_on(self.inner.receive_message(qname, hidefor)) }
+
+	pub fn get_queue_attributes(&self, qname: &str) -> Result<Queue, Error> { self.block_on(self.inner.get_queue_attributes(qname)) }
+
+	pub fn set_queue_attributes(&self, qname: &str, vt: Option<u64>, delay: Option<u64>, maxsize: Option<i64>) -> Result<Queue, Error> {
+		self.block_on(self.inner.set_queue_attributes(qname, vt, delay, maxsize))
+	}
+
+	fn block_on<F: std::future::Future>(&self, future: F) -> F::Output { self.runtime.handle().block_on(future) }
+}
@@ -0,0 +1,295 @@
+use bb8::{ManageConnection, Pool};
+use bb8_redis::RedisConnectionManager;
+use failure::Error;
+use redis::{aio::MultiplexedConnection, ConnectionInfo, IntoConnectionInfo, RedisResult};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::connection::RedisPool;
+use crate::metrics::Metrics;
+use crate::{BlobStore, ClusterConnectionManager, Compression, Encryption, KeyLayout, Queue, RetryPolicy, Rsmq, Scripts, SentinelConnectionManager};
+#[cfg(feature = "signing")]
+use crate::Signing;
+
+enum Target {
+	Single(ConnectionInfo),
+	Cluster(Vec<ConnectionInfo>),
+	Sentinel(SentinelConnectionManager),
+	Pool(Pool<RedisConnectionManager>),
+	Multiplexed(MultiplexedConnection),
+}
+
+/// Configures and connects an [`Rsmq`].
+///
+/// ```no_run
+/// # async fn run() -> Result<(), failure::Error> {
+/// use rsmq::{Backoff, RetryPolicy, RsmqBuilder};
+/// use std::time::Duration;
+///
+/// let rsmq = RsmqBuilder::new("redis://127.0.0.1/")
+///   .name_space("jobs")
+///   .max_size(32)
+///   .connection_timeout(Duration::from_secs(2))
+///   .realtime(true)
+///   .retry_policy(RetryPolicy::new(3, Backoff::Fixed(Duration::from_millis(100))))
+///   .build()
+///   .await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct RsmqBuilder {
+	target: RedisResult<Target>,
+	name_space: String,
+	key_layout: KeyLayout,
+	pool_options: PoolOptions,
+	realtime: bool,
+	default_queue: Queue,
+	retry_policy: RetryPolicy,
+	metrics: Option<Arc<Metrics>>,
+	compression: Option<(Compression, usize)>,
+	encryption: Option<Encryption>,
+	claim_check: Option<(Arc<dyn BlobStore>, usize)>,
+	#[cfg(feature = "signing")]
+	signing: Option<Signing>,
+	dead_letter_queue: Option<String>,
+}
+
+impl RsmqBuilder {
+	/// Connect to a single Redis server.
+	pub fn new<T: IntoConnectionInfo>(params: T) -> RsmqBuilder { RsmqBuilder::with_target(params.into_connection_info().map(Target::Single)) }
+
+	/// Connect to a Redis Cluster through any of its `nodes`. Queues always use the [`KeyLayout::HashTagged`] key layout.
+	pub fn cluster<T: IntoConnectionInfo>(nodes: Vec<T>) -> RsmqBuilder {
+		let nodes = nodes.into_iter().map(|n| n.into_connection_info()).collect::<RedisResult<Vec<_>>>();
+		let mut builder = RsmqBuilder::with_target(nodes.map(Target::Cluster));
+		builder.key_layout = KeyLayout::HashTagged;
+		builder
+	}
+
+	/// Connect to the master called `master_name` of a Redis Sentinel deployment, see [`SentinelConnectionManager`].
+	pub fn sentinel<T: IntoConnectionInfo>(master_name: &str, sentinels: Vec<T>) -> RsmqBuilder {
+		RsmqBuilder::with_target(SentinelConnectionManager::new(master_name, sentinels).map(Target::Sentinel))
+	}
+
+	/// Use a pool the application already built and shares with other code. The pool settings of this builder are
+	/// ignored.
+	pub fn pool(pool: Pool<RedisConnectionManager>) -> RsmqBuilder { RsmqBuilder::with_target(Ok(Target::Pool(pool))) }
+
+	/// Send all commands over a multiplexed connection the application already holds, instead of a pool. The pool
+	/// settings of this builder are ignored.
+	pub fn multiplexed(con: MultiplexedConnection) -> RsmqBuilder { RsmqBuilder::with_target(Ok(Target::Multiplexed(con))) }
+
+	fn with_target(target: RedisResult<Target>) -> RsmqBuilder {
+		RsmqBuilder {
+			target,
+			name_space: "rsmq".into(),
+			key_layout: KeyLayout::Plain,
+			pool_options: PoolOptions::default(),
+			realtime: false,
+			default_queue: Queue::default(),
+			retry_policy: RetryPolicy::default(),
+			metrics: None,
+			compression: None,
+			encryption: None,
+			claim_check: None,
+			#[cfg(feature = "signing")]
+			signing: None,
+			dead_letter_queue: None,
+		}
+	}
+
+	/// The prefix of all keys, `rsmq` by default.
+	pub fn name_space(mut self, name_space: &str) -> RsmqBuilder {
+		if !name_space.is_empty() {
+			self.name_space = name_space.into();
+		}
+		self
+	}
+
+	/// See [`KeyLayout`]. Ignored for Redis Cluster.
+	pub fn key_layout(mut self, key_layout: KeyLayout) -> RsmqBuilder {
+		if !matches!(self.target, Ok(Target::Cluster(_))) {
+			self.key_layout = key_layout;
+		}
+		self
+	}
+
+	/// The database to select on the master of a Redis Sentinel deployment, `0` by default. Ignored for other
+	/// deployments, whose database is part of the connection URL.
+	pub fn sentinel_db(mut self, db: i64) -> RsmqBuilder {
+		if let Ok(Target::Sentinel(manager)) = self.target {
+			self.target = Ok(Target::Sentinel(manager.db(db)));
+		}
+		self
+	}
+
+	/// The password of the master of a Redis Sentinel deployment, see [`SentinelConnectionManager::master_password`].
+	/// Ignored for other deployments, whose password is part of the connection URL.
+	pub fn sentinel_master_password(mut self, passwd: Option<String>) -> RsmqBuilder {
+		if let Ok(Target::Sentinel(manager)) = self.target {
+			self.target = Ok(Target::Sentinel(manager.master_password(passwd)));
+		}
+		self
+	}
+
+	/// The maximum number of pooled connections, 10 by default.
+	pub fn max_size(mut self, max_size: u32) -> RsmqBuilder {
+		self.pool_options.max_size = Some(max_size);
+		self
+	}
+
+	/// The number of idle connections the pool keeps open.
+	pub fn min_idle(mut self, min_idle: u32) -> RsmqBuilder {
+		self.pool_options.min_idle = Some(min_idle);
+		self
+	}
+
+	/// How long to wait for a pooled connection before giving up, 30 seconds by default.
+	pub fn connection_timeout(mut self, timeout: Duration) -> RsmqBuilder {
+		self.pool_options.connection_timeout = Some(timeout);
+		self
+	}
+
+	/// Close connections that were idle for longer than `timeout`, `None` keeps them open.
+	pub fn idle_timeout(mut self, timeout: Option<Duration>) -> RsmqBuilder {
+		self.pool_options.idle_timeout = Some(timeout);
+		self
+	}
+
+	/// Close connections older than `lifetime`, `None` keeps them open.
+	pub fn max_lifetime(mut self, lifetime: Option<Duration>) -> RsmqBuilder {
+		self.pool_options.max_lifetime = Some(lifetime);
+		self
+	}
+
+	/// Like the JS RSMQ `realtime` option: publish the number of messages in the queue on `{ns}:rt:{qname}` whenever a
+	/// message is sent.
+	pub fn realtime(mut self, realtime: bool) -> RsmqBuilder {
+		self.realtime = realtime;
+		self
+	}
+
+	/// The `vt`, `delay` and `maxsize` of queues made with [`Rsmq::default_queue`].
+	pub fn default_queue_options(mut self, vt: Option<u64>, delay: Option<u64>, maxsize: Option<i64>) -> RsmqBuilder {
+		self.default_queue = Queue::new("", vt, delay, maxsize);
+		self
+	}
+
+	/// How to retry checking out a connection, see [`RetryPolicy`].
+	pub fn retry_policy(mut self, retry_policy: RetryPolicy) -> RsmqBuilder {
+		self.retry_policy = retry_policy;
+		self
+	}
+
+	/// Record operations and pool wait times in `metrics`, see [`crate::metrics`].
+	pub fn metrics(mut self, metrics: Arc<Metrics>) -> RsmqBuilder {
+		self.metrics = Some(metrics);
+		self
+	}
+
+	/// Compress message bodies longer than `threshold` bytes, if that makes them shorter. Other RSMQ clients can't read
+	/// compressed messages, see [`Compression`].
+	pub fn compression(mut self, compression: Compression, threshold: usize) -> RsmqBuilder {
+		self.compression = Some((compression, threshold));
+		self
+	}
+
+	/// Encrypt message bodies with the keys of `encryption` and decrypt received messages. Other RSMQ clients can't read
+	/// encrypted messages.
+	pub fn encryption(mut self, encryption: Encryption) -> RsmqBuilder {
+		self.encryption = Some(encryption);
+		self
+	}
+
+	/// Store message bodies longer than `threshold` bytes, after compression and encryption, in `store` and send a
+	/// reference to them instead. Receiving resolves the reference, `delete_message` and `pop_message` delete the blob.
+	/// Blobs of messages removed otherwise, by `purge_queue`, `delete_queue` or `RSMQ` clients without the store, are
+	/// left in the store.
+	pub fn claim_check(mut self, store: Arc<dyn BlobStore>, threshold: usize) -> RsmqBuilder {
+		self.claim_check = Some((store, threshold));
+		self
+	}
+
+	/// Sign sent messages and verify received ones with the keys of `signing`. Received messages that fail verification
+	/// make `receive_message` and `pop_message` fail, unless they are quarantined; a popped message is lost then. Needs the
+	/// `signing` feature.
+	#[cfg(feature = "signing")]
+	pub fn signing(mut self, signing: Signing) -> RsmqBuilder {
+		self.signing = Some(signing);
+		self
+	}
+
+	/// The queue [`Delivery::dead_letter`](crate::Delivery::dead_letter) moves messages to, with a header naming the
+	/// queue they came from. With signing, its messages are verified as messages of the queue they came from.
+	pub fn dead_letter_queue(mut self, qname: &str) -> RsmqBuilder {
+		self.dead_letter_queue = Some(qname.into());
+		self
+	}
+
+	#[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all, fields(ns = %self.name_space, round_trips = tracing::field::Empty), err(level = "debug")))]
+	pub async fn build(self) -> Result<Rsmq, Error> {
+		let options = &self.pool_options;
+		let pool = match self.target? {
+			Target::Single(info) => RedisPool::Single(options.builder().build(RedisConnectionManager::new(info)?).await?),
+			Target::Cluster(nodes) => RedisPool::Cluster(options.builder().build(ClusterConnectionManager::new(nodes)?).await?),
+			Target::Sentinel(manager) => RedisPool::Sentinel(options.builder().build(manager).await?),
+			Target::Pool(pool) => RedisPool::Single(pool),
+			Target::Multiplexed(con) => RedisPool::Multiplexed(con),
+		};
+
+		let rsmq = Rsmq {
+			pool,
+			name_space: self.name_space,
+			key_layout: self.key_layout,
+			realtime: self.realtime,
+			default_queue: self.default_queue,
+			retry_policy: self.retry_policy,
+			metrics: self.metrics,
+			compression: self.compression,
+			encryption: self.encryption,
+			claim_check: self.claim_check,
+			#[cfg(feature = "signing")]
+			signing: self.signing,
+			dead_letter_queue: self.dead_letter_queue,
+			scripts: Scripts::new(),
+		};
+		crate::connection::count_round_trips(async {
+			let mut con = rsmq.connection().await?;
+			rsmq.scripts.load(&mut con).await.map_err(Error::from)
+		})
+		.await?;
+		Ok(rsmq)
+	}
+}
+
+// bb8 settings, `None` keeps the bb8 default.
+#[derive(Default)]
+struct PoolOptions {
+	max_size: Option<u32>,
+	min_idle: Option<u32>,
+	connection_timeout: Option<Duration>,
+	idle_timeout: Option<Option<Duration>>,
+	max_lifetime: Option<Option<Duration>>,
+}
+
+impl PoolOptions {
+	fn builder<M: ManageConnection>(&self) -> bb8::Builder<M> {
+		let mut builder = Pool::builder();
+		if let Some(max_size) = self.max_size {
+			builder = builder.max_size(max_size);
+		}
+		if self.min_idle.is_some() {
+			builder = builder.min_idle(self.min_idle);
+		}
+		if let Some(timeout) = self.connection_timeout {
+			builder = builder.connection_timeout(timeout);
+		}
+		if let Some(timeout) = self.idle_timeout {
+			builder = builder.idle_timeout(timeout);
+		}
+		if let Some(lifetime) = self.max_lifetime {
+			builder = builder.max_lifetime(lifetime);
+		}
+		builder
+	}
+}
@@ -0,0 +1,320 @@
+use async_trait::async_trait;
+use redis::{aio::{self, ConnectionLike}, Arg, Cmd, ConnectionAddr, ConnectionInfo, ErrorKind as RedisErrorKind, IntoConnectionInfo, Pipeline, RedisError, RedisFuture, RedisResult, Value};
+use std::collections::{BTreeMap, HashMap};
+
+const SLOT_COUNT: u16 = 16384;
+// How many MOVED/ASK redirections a single request follows before giving up.
+const MAX_REDIRECTS: usize = 5;
+
+/// A `bb8::ManageConnection` for Redis Cluster.
+///
+/// Every connection it hands out keeps its own slot map and one connection per master node. Requests are routed by the
+/// hash slot of their first key, which is enough for [`crate::Rsmq`]: with hash-tagged keys every pipeline and script
+/// only touches keys of a single queue and thus a single slot. `SCRIPT` commands go to every master, so a script loaded
+/// once, or again after a `NOSCRIPT` reply, can be run on whichever master serves the queue.
+#[derive(Clone, Debug)]
+pub struct ClusterConnectionManager {
+	initial_nodes: Vec<ConnectionInfo>,
+}
+
+impl ClusterConnectionManager {
+	/// Create a new `ClusterConnectionManager` from one or more cluster nodes. The rest of the cluster is discovered with
+	/// `CLUSTER SLOTS`.
+	pub fn new<T: IntoConnectionInfo>(initial_nodes: Vec<T>) -> RedisResult<ClusterConnectionManager> {
+		let initial_nodes = initial_nodes.into_iter().map(|n| n.into_connection_info()).collect::<RedisResult<Vec<_>>>()?;
+		if initial_nodes.is_empty() {
+			return Err(RedisError::from((RedisErrorKind::InvalidClientConfig, "No cluster nodes given")));
+		}
+		Ok(ClusterConnectionManager { initial_nodes })
+	}
+}
+
+#[async_trait]
+impl bb8::ManageConnection for ClusterConnectionManager {
+	type Connection = ClusterConnection;
+	type Error = RedisError;
+
+	async fn connect(&self) -> Result<Self::Connection, Self::Error> { ClusterConnection::connect(self.initial_nodes.clone()).await }
+
+	async fn is_valid(&self, mut conn: Self::Connection) -> Result<Self::Connection, Self::Error> {
+		redis::cmd("PING").query_async::<_, ()>(&mut conn).await?;
+		Ok(conn)
+	}
+
+	fn has_broken(&self, conn: &mut Self::Connection) -> bool { conn.broken }
+}
+
+/// A connection to a Redis Cluster, see [`ClusterConnectionManager`].
+pub struct ClusterConnection {
+	initial_nodes: Vec<ConnectionInfo>,
+	passwd: Option<String>,
+	// Last slot of each range -> (first slot of the range, "host:port" of the master serving it)
+	slots: BTreeMap<u16, (u16, String)>,
+	connections: HashMap<String, aio::Connection>,
+	broken: bool,
+}
+
+impl std::fmt::Debug for ClusterConnection {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result { write!(f, "ClusterConnection {{ nodes: {:?} }}", self.connections.keys().collect::<Vec<_>>()) }
+}
+
+impl ClusterConnection {
+	async fn connect(initial_nodes: Vec<ConnectionInfo>) -> RedisResult<ClusterConnection> {
+		let passwd = initial_nodes[0].passwd.clone();
+		let mut con = ClusterConnection { initial_nodes, passwd, slots: BTreeMap::new(), connections: HashMap::new(), broken: false };
+		con.refresh_slots().await?;
+		Ok(con)
+	}
+
+	/// Rebuild the slot map, asking the nodes we are already connected to first and the initial nodes after that.
+	async fn refresh_slots(&mut self) -> RedisResult<()> {
+		let mut last_err = RedisError::from((RedisErrorKind::ClusterDown, "No cluster node reachable"));
+		let mut candidates: Vec<String> = self.connections.keys().cloned().collect();
+		for info in &self.initial_nodes {
+			if let ConnectionAddr::Tcp(host, port) = &*info.addr {
+				candidates.push(format!("{}:{}", host, port));
+			}
+		}
+		for node in candidates {
+			let slots = match self.node(&node).await {
+				Ok(con) => redis::cmd("CLUSTER").arg("SLOTS").query_async(con).await,
+				Err(e) => Err(e),
+			};
+			match slots.and_then(|v| parse_slots(&v)) {
+				Ok(slots) => {
+					self.connections.retain(|addr, _| slots.values().any(|(_, master)| master == addr));
+					self.slots = slots;
+					return Ok(());
+				}
+				Err(e) => {
+					self.connections.remove(&node);
+					last_err = e;
+				}
+			}
+		}
+		Err(last_err)
+	}
+
+	async fn node(&mut self, addr: &str) -> RedisResult<&mut aio::Connection> {
+		if !self.connections.contains_key(addr) {
+			let info = self.connection_info(addr)?;
+			let con = aio::connect(&info).await?;
+			self.connections.insert(addr.to_string(), con);
+		}
+		Ok(self.connections.get_mut(addr).expect("connection was just inserted"))
+	}
+
+	fn connection_info(&self, addr: &str) -> RedisResult<ConnectionInfo> {
+		let mut parts = addr.rsplitn(2, ':');
+		let port = parts.next().and_then(|p| p.parse().ok());
+		match (parts.next(), port) {
+			(Some(host), Some(port)) => Ok(ConnectionInfo { addr: Box::new(ConnectionAddr::Tcp(host.to_string(), port)), db: 0, passwd: self.passwd.clone() }),
+			_ => Err(RedisError::from((RedisErrorKind::ClientError, "Invalid cluster node address", addr.to_string()))),
+		}
+	}
+
+	fn node_for_slot(&self, slot: Option<u16>) -> RedisResult<String> {
+		let entry = match slot {
+			Some(slot) => self.slots.range(slot..).next().filter(|(_, (start, _))| *start <= slot),
+			None => self.slots.iter().next(),
+		};
+		entry
+			.map(|(_, (_, addr))| addr.clone())
+			.ok_or_else(|| RedisError::from((RedisErrorKind::ClusterDown, "No node serves the hash slot")))
+	}
+
+	/// Send a request to every master in the slot map, returning the reply of the last one.
+	async fn broadcast(&mut self, request: Request<'_>) -> RedisResult<Response> {
+		let mut masters: Vec<String> = self.slots.values().map(|(_, addr)| addr.clone()).collect();
+		masters.sort();
+		masters.dedup();
+		let mut res = None;
+		for addr in masters {
+			match self.send(&addr, false, request).await {
+				Ok(v) => res = Some(v),
+				Err(err) => return Err(self.drop_if_broken(&addr, err)),
+			}
+		}
+		res.ok_or_else(|| RedisError::from((RedisErrorKind::ClusterDown, "No cluster node known")))
+	}
+
+	// Forget the connection to `addr` if `err` means it's gone, and mark this connection as broken.
+	fn drop_if_broken(&mut self, addr: &str, err: RedisError) -> RedisError {
+		if err.is_io_error() || err.is_connection_dropped() {
+			self.connections.remove(addr);
+			self.broken = true;
+		}
+		err
+	}
+
+	async fn send(&mut self, addr: &str, asking: bool, request: Request<'_>) -> RedisResult<Response> {
+		let con = self.node(addr).await?;
+		if asking {
+			redis::cmd("ASKING").query_async::<_, ()>(con).await?;
+		}
+		match request {
+			Request::Single(cmd) => con.req_packed_command(cmd).await.map(Response::Single),
+			Request::Pipeline(pipeline, offset, count) => con.req_packed_commands(pipeline, offset, count).await.map(Response::Pipeline),
+		}
+	}
+
+	/// Send a request to the node owning `slot`, following MOVED and ASK redirections.
+	async fn request(&mut self, slot: Option<u16>, request: Request<'_>) -> RedisResult<Response> {
+		let mut addr = self.node_for_slot(slot)?;
+		let mut asking = false;
+		for _ in 0..MAX_REDIRECTS {
+			let err = match self.send(&addr, asking, request).await {
+				Ok(v) => return Ok(v),
+				Err(e) => e,
+			};
+			match err.kind() {
+				RedisErrorKind::Moved => {
+					self.refresh_slots().await?;
+					addr = self.node_for_slot(slot)?;
+					asking = false;
+				}
+				RedisErrorKind::Ask => {
+					addr = match err.redirect_node() {
+						Some((host, port)) => format!("{}:{}", host, port),
+						None => return Err(err),
+					};
+					asking = true;
+				}
+				_ => return Err(self.drop_if_broken(&addr, err)),
+			}
+		}
+		Err(RedisError::from((RedisErrorKind::ClusterDown, "Too many cluster redirections")))
+	}
+}
+
+#[derive(Clone, Copy)]
+enum Request<'a> {
+	Single(&'a Cmd),
+	Pipeline(&'a Pipeline, usize, usize),
+}
+
+enum Response {
+	Single(Value),
+	Pipeline(Vec<Value>),
+}
+
+impl aio::ConnectionLike for ClusterConnection {
+	fn req_packed_command<'a>(&'a mut self, cmd: &'a Cmd) -> RedisFuture<'a, Value> {
+		Box::pin(async move {
+			let res = if is_script_command(cmd) {
+				self.broadcast(Request::Single(cmd)).await?
+			} else {
+				self.request(slot_for_command(cmd), Request::Single(cmd)).await?
+			};
+			match res {
+				Response::Single(v) => Ok(v),
+				Response::Pipeline(_) => unreachable!("a single command gets a single response"),
+			}
+		})
+	}
+
+	fn req_packed_commands<'a>(&'a mut self, pipeline: &'a Pipeline, offset: usize, count: usize) -> RedisFuture<'a, Vec<Value>> {
+		Box::pin(async move {
+			let res = if pipeline.cmd_iter().all(is_script_command) {
+				self.broadcast(Request::Pipeline(pipeline, offset, count)).await?
+			} else {
+				let slot = pipeline.cmd_iter().filter_map(slot_for_command).next();
+				self.request(slot, Request::Pipeline(pipeline, offset, count)).await?
+			};
+			match res {
+				Response::Pipeline(v) => Ok(v),
+				Response::Single(_) => unreachable!("a pipeline gets a pipeline response"),
+			}
+		})
+	}
+
+	fn get_db(&self) -> i64 { 0 }
+}
+
+// Parse a `CLUSTER SLOTS` reply: `[[start, end, [host, port, id], replicas...], ...]`
+fn parse_slots(v: &Value) -> RedisResult<BTreeMap<u16, (u16, String)>> {
+	let invalid = || RedisError::from((RedisErrorKind::TypeError, "Unexpected CLUSTER SLOTS reply"));
+	let mut slots = BTreeMap::new();
+	let ranges = match v {
+		Value::Bulk(ranges) => ranges,
+		_ => return Err(invalid()),
+	};
+	for range in ranges {
+		match range {
+			Value::Bulk(items) if items.len() >= 3 => {
+				let start: u16 = redis::from_redis_value(&items[0])?;
+				let end: u16 = redis::from_redis_value(&items[1])?;
+				let master = match &items[2] {
+					Value::Bulk(node) if node.len() >= 2 => {
+						let host: String = redis::from_redis_value(&node[0])?;
+						let port: u16 = redis::from_redis_value(&node[1])?;
+						format!("{}:{}", host, port)
+					}
+					_ => return Err(invalid()),
+				};
+				slots.insert(end, (start, master));
+			}
+			_ => return Err(invalid()),
+		}
+	}
+	if slots.is_empty() {
+		return Err(invalid());
+	}
+	Ok(slots)
+}
+
+// Whether `cmd` is a `SCRIPT` command, e.g. `SCRIPT LOAD`, which has to reach every master.
+fn is_script_command(cmd: &Cmd) -> bool {
+	match cmd.args_iter().next() {
+		Some(Arg::Simple(name)) => name.eq_ignore_ascii_case(b"SCRIPT"),
+		_ => false,
+	}
+}
+
+// The hash slot of the key a command operates on, `None` for keyless commands like TIME, SCRIPT LOAD or MULTI.
+fn slot_for_command(cmd: &Cmd) -> Option<u16> {
+	let args: Vec<&[u8]> = cmd
+		.args_iter()
+		.filter_map(|a| match a {
+			Arg::Simple(a) => Some(a),
+			Arg::Cursor => None,
+		})
+		.collect();
+	let name = args.first()?.to_ascii_uppercase();
+	let key = match &name[..] {
+		b"EVAL" | b"EVALSHA" => {
+			let numkeys: usize = std::str::from_utf8(args.get(2)?).ok()?.parse().ok()?;
+			if numkeys == 0 {
+				return None;
+			}
+			args.get(3)?
+		}
+		b"MULTI" | b"EXEC" | b"DISCARD" | b"TIME" | b"SCRIPT" | b"PING" | b"INFO" | b"CLUSTER" | b"ASKING" => return None,
+		_ => args.get(1)?,
+	};
+	Some(key_slot(key))
+}
+
+/// The hash slot of a key, honouring `{hash tags}`.
+fn key_slot(key: &[u8]) -> u16 {
+	let key = match key.iter().position(|b| *b == b'{') {
+		Some(open) => match key[open + 1..].iter().position(|b| *b == b'}') {
+			Some(len) if len > 0 => &key[open + 1..open + 1 + len],
+			_ => key,
+		},
+		None => key,
+	};
+	crc16(key) % SLOT_COUNT
+}
+
+// CRC16/XMODEM as specified by the Redis Cluster spec.
+fn crc16(data: &[u8]) -> u16 {
+	let mut crc: u16 = 0;
+	for byte in data {
+		crc ^= (*byte as u16) << 8;
+		for _ in 0..8 {
+			crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x1021 } else { crc << 1 };
+		}
+	}
+	crc
+}
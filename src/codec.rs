@@ -0,0 +1,140 @@
+use failure::{format_err, Error};
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::envelope;
+use crate::Message;
+
+/// A serialization format for the values sent with [`Rsmq::send_encoded`](crate::Rsmq::send_encoded).
+///
+/// The id of the codec is stored with every message, so [`Message::decode`] picks the codec each message was encoded
+/// with and a queue can switch formats while old messages are still in it. Values of binary formats are stored base64
+/// encoded.
+///
+/// ```no_run
+/// # #[cfg(feature = "msgpack")]
+/// # async fn run(rsmq: rsmq::Rsmq) -> Result<(), failure::Error> {
+/// use rsmq::MessagePack;
+///
+/// rsmq.send_encoded("orders", &MessagePack, &("order-1", 3), None).await?;
+///
+/// let (order, quantity): (String, u32) = rsmq.receive_message("orders", None).await?.decode()?;
+/// # Ok(())
+/// # }
+/// ```
+pub trait Codec {
+	/// The id stored with messages, unique among codecs.
+	fn id(&self) -> &'static str;
+
+	/// Whether encoded values are UTF-8 text, which is stored as is.
+	fn is_text(&self) -> bool { false }
+
+	fn encode<T: Serialize + ?Sized>(&self, value: &T) -> Result<Vec<u8>, Error>;
+
+	fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, Error>;
+}
+
+/// JSON. Needs the `json` feature.
+#[cfg(feature = "json")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Json;
+
+#[cfg(feature = "json")]
+impl Codec for Json {
+	fn id(&self) -> &'static str { "json" }
+
+	fn is_text(&self) -> bool { true }
+
+	fn encode<T: Serialize + ?Sized>(&self, value: &T) -> Result<Vec<u8>, Error> { Ok(serde_json::to_vec(value)?) }
+
+	fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, Error> { Ok(serde_json::from_slice(bytes)?) }
+}
+
+/// MessagePack, with structs as maps so fields can be added. Needs the `msgpack` feature.
+#[cfg(feature = "msgpack")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MessagePack;
+
+#[cfg(feature = "msgpack")]
+impl Codec for MessagePack {
+	fn id(&self) -> &'static str { "msgpack" }
+
+	fn encode<T: Serialize + ?Sized>(&self, value: &T) -> Result<Vec<u8>, Error> { Ok(rmp_serde::to_vec_named(value)?) }
+
+	fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, Error> { Ok(rmp_serde::from_slice(bytes)?) }
+}
+
+/// CBOR. Needs the `cbor` feature.
+#[cfg(feature = "cbor")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Cbor;
+
+#[cfg(feature = "cbor")]
+impl Codec for Cbor {
+	fn id(&self) -> &'static str { "cbor" }
+
+	fn encode<T: Serialize + ?Sized>(&self, value: &T) -> Result<Vec<u8>, Error> {
+		let mut bytes = Vec::new();
+		ciborium::into_writer(value, &mut bytes).map_err(|e| format_err!("{}", e))?;
+		Ok(bytes)
+	}
+
+	fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, Error> { ciborium::from_reader(bytes).map_err(|e| format_err!("{}", e)) }
+}
+
+/// bincode, compact but only readable into the exact type that was encoded. Needs the `bincode` feature.
+#[cfg(feature = "bincode")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Bincode;
+
+#[cfg(feature = "bincode")]
+impl Codec for Bincode {
+	fn id(&self) -> &'static str { "bincode" }
+
+	fn encode<T: Serialize + ?Sized>(&self, value: &T) -> Result<Vec<u8>, Error> { Ok(bincode::serialize(value)?) }
+
+	fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, Error> { Ok(bincode::deserialize(bytes)?) }
+}
+
+/// The body of a message carrying `value` encoded with `codec`.
+pub(crate) fn encode<C: Codec, T: Serialize + ?Sized>(codec: &C, value: &T) -> Result<String, Error> {
+	let bytes = codec.encode(value)?;
+	if codec.is_text() {
+		Ok(String::from_utf8(bytes)?)
+	} else {
+		Ok(envelope::base64_encode(&bytes))
+	}
+}
+
+impl Message {
+	/// The id of the codec the message was encoded with, `None` if it wasn't sent with [`Rsmq::send_encoded`](crate::Rsmq::send_encoded).
+	pub fn codec(&self) -> Option<&str> { self.headers.get(envelope::CODEC).map(String::as_str) }
+
+	/// Decode the value of a message sent with [`Rsmq::send_encoded`](crate::Rsmq::send_encoded), with whichever of the
+	/// codecs enabled by cargo features it was encoded with. With the `json` feature, messages sent without a codec are
+	/// read as JSON, e.g. those of other RSMQ clients.
+	pub fn decode<T: DeserializeOwned>(&self) -> Result<T, Error> {
+		match self.codec() {
+			#[cfg(feature = "json")]
+			Some("json") | None => self.decode_with(&Json),
+			#[cfg(feature = "msgpack")]
+			Some("msgpack") => self.decode_with(&MessagePack),
+			#[cfg(feature = "cbor")]
+			Some("cbor") => self.decode_with(&Cbor),
+			#[cfg(feature = "bincode")]
+			Some("bincode") => self.decode_with(&Bincode),
+			Some(id) => Err(format_err!("Unsupported codec {:?}, enable its cargo feature or use decode_with", id)),
+			#[allow(unreachable_patterns)]
+			None => Err(format_err!("Message {} wasn't sent with a codec", self.id)),
+		}
+	}
+
+	/// Decode the value of a message encoded with `codec`, e.g. one implemented by the application.
+	pub fn decode_with<C: Codec, T: DeserializeOwned>(&self, codec: &C) -> Result<T, Error> {
+		match self.codec() {
+			Some(id) if id != codec.id() => Err(format_err!("Message {} was encoded with codec {:?}, not {:?}", self.id, id, codec.id())),
+			None if !codec.is_text() => Err(format_err!("Message {} wasn't sent with a codec", self.id)),
+			_ if codec.is_text() => codec.decode(self.message.as_bytes()),
+			_ => codec.decode(&envelope::base64_decode(&self.message)?),
+		}
+	}
+}
@@ -0,0 +1,50 @@
+use failure::{format_err, Error};
+
+/// How [`RsmqBuilder::compression`](crate::RsmqBuilder::compression) compresses large message bodies.
+///
+/// Compressed bodies are stored base64 encoded, with a header naming the algorithm. Receiving decompresses them with
+/// any algorithm enabled by a cargo feature, whether or not the receiving client compresses itself.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Compression {
+	/// zstd at a level from 1 to 22, 3 is a good default. Needs the `zstd` feature.
+	#[cfg(feature = "zstd")]
+	Zstd(i32),
+	/// gzip at a level from 0 to 9, 6 is a good default. Needs the `gzip` feature.
+	#[cfg(feature = "gzip")]
+	Gzip(u32),
+}
+
+impl Compression {
+	/// The compressed `body` and the name of the algorithm.
+	#[allow(unused_variables)]
+	pub(crate) fn compress(self, body: &[u8]) -> Result<(Vec<u8>, &'static str), Error> {
+		match self {
+			#[cfg(feature = "zstd")]
+			Compression::Zstd(level) => Ok((zstd::encode_all(body, level)?, "zstd")),
+			#[cfg(feature = "gzip")]
+			Compression::Gzip(level) => {
+				use std::io::Write;
+				let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::new(level));
+				encoder.write_all(body)?;
+				Ok((encoder.finish()?, "gzip"))
+			}
+		}
+	}
+}
+
+/// Decompress a body compressed with the algorithm called `encoding`.
+#[allow(unused_variables)]
+pub(crate) fn decompress(encoding: &str, body: &[u8]) -> Result<Vec<u8>, Error> {
+	match encoding {
+		#[cfg(feature = "zstd")]
+		"zstd" => Ok(zstd::decode_all(body)?),
+		#[cfg(feature = "gzip")]
+		"gzip" => {
+			use std::io::Read;
+			let mut bytes = Vec::new();
+			flate2::read::GzDecoder::new(body).read_to_end(&mut bytes)?;
+			Ok(bytes)
+		}
+		_ => Err(format_err!("Unsupported message compression {:?}, enable its cargo feature", encoding)),
+	}
+}
@@ -0,0 +1,113 @@
+use bb8::{Pool, PooledConnection};
+use bb8_redis::RedisConnectionManager;
+use redis::{aio::{ConnectionLike, MultiplexedConnection}, ErrorKind as RedisErrorKind, RedisError, RedisFuture, Value};
+#[cfg(feature = "tracing")]
+use std::cell::Cell;
+use std::future::Future;
+
+use crate::{ClusterConnectionManager, SentinelConnectionManager};
+
+pub(crate) enum RedisPool {
+	Single(Pool<RedisConnectionManager>),
+	Cluster(Pool<ClusterConnectionManager>),
+	Sentinel(Pool<SentinelConnectionManager>),
+	// Multiplexed connections are shared, every caller gets a clone of the same connection.
+	Multiplexed(MultiplexedConnection),
+}
+
+impl RedisPool {
+	pub(crate) async fn get(&self) -> Result<Connection<'_>, failure::Error> {
+		let con = match self {
+			RedisPool::Single(pool) => PoolConnection::Single(pool.get().await?),
+			RedisPool::Cluster(pool) => PoolConnection::Cluster(pool.get().await?),
+			RedisPool::Sentinel(pool) => PoolConnection::Sentinel(pool.get().await?),
+			RedisPool::Multiplexed(con) => PoolConnection::Multiplexed(con.clone()),
+		};
+		Ok(Connection { con })
+	}
+}
+
+impl std::fmt::Debug for RedisPool {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		match self {
+			RedisPool::Single(pool) => write!(f, "{:?}", pool),
+			RedisPool::Cluster(pool) => write!(f, "cluster {:?}", pool),
+			RedisPool::Sentinel(pool) => write!(f, "sentinel {:?}", pool),
+			RedisPool::Multiplexed(_) => write!(f, "multiplexed connection"),
+		}
+	}
+}
+
+// A connection checked out of whichever pool `Rsmq` was built with.
+pub(crate) struct Connection<'a> {
+	con: PoolConnection<'a>,
+}
+
+// Commands and pipelines sent by the operation running in the task, over all the connections it checked out.
+#[cfg(feature = "tracing")]
+tokio::task_local! {
+	static ROUND_TRIPS: std::cell::Cell<u32>;
+}
+
+fn count_round_trip() {
+	#[cfg(feature = "tracing")]
+	let _ = ROUND_TRIPS.try_with(|n| n.set(n.get() + 1));
+}
+
+// Run `f`, recording the round trips it made in the current tracing span. They also count for the operation `f` is
+// part of, if any.
+pub(crate) async fn count_round_trips<F: Future>(f: F) -> F::Output {
+	#[cfg(feature = "tracing")]
+	{
+		let (res, round_trips) = ROUND_TRIPS.scope(Cell::new(0), async { (f.await, ROUND_TRIPS.with(Cell::get)) }).await;
+		tracing::Span::current().record("round_trips", round_trips);
+		let _ = ROUND_TRIPS.try_with(|n| n.set(n.get() + round_trips));
+		res
+	}
+	#[cfg(not(feature = "tracing"))]
+	f.await
+}
+
+enum PoolConnection<'a> {
+	Single(PooledConnection<'a, RedisConnectionManager>),
+	Cluster(PooledConnection<'a, ClusterConnectionManager>),
+	Sentinel(PooledConnection<'a, SentinelConnectionManager>),
+	Multiplexed(MultiplexedConnection),
+}
+
+impl ConnectionLike for Connection<'_> {
+	fn req_packed_command<'a>(&'a mut self, cmd: &'a redis::Cmd) -> RedisFuture<'a, Value> {
+		count_round_trip();
+		match &mut self.con {
+			PoolConnection::Single(con) => match con.as_mut() {
+				Some(con) => con.req_packed_command(cmd),
+				None => Box::pin(async { Err(RedisError::from((RedisErrorKind::IoError, "Unable to acquire connection"))) }),
+			},
+			PoolConnection::Cluster(con) => con.req_packed_command(cmd),
+			PoolConnection::Sentinel(con) => con.req_packed_command(cmd),
+			PoolConnection::Multiplexed(con) => con.req_packed_command(cmd),
+		}
+	}
+
+	fn req_packed_commands<'a>(&'a mut self, cmd: &'a redis::Pipeline, offset: usize, count: usize) -> RedisFuture<'a, Vec<Value>> {
+		count_round_trip();
+		match &mut self.con {
+			PoolConnection::Single(con) => match con.as_mut() {
+				Some(con) => con.req_packed_commands(cmd, offset, count),
+				None => Box::pin(async { Err(RedisError::from((RedisErrorKind::IoError, "Unable to acquire connection"))) }),
+			},
+			PoolConnection::Cluster(con) => con.req_packed_commands(cmd, offset, count),
+			PoolConnection::Sentinel(con) => con.req_packed_commands(cmd, offset, count),
+			PoolConnection::Multiplexed(con) => con.req_packed_commands(cmd, offset, count),
+		}
+	}
+
+	fn get_db(&self) -> i64 {
+		match &self.con {
+			PoolConnection::Single(con) => con.as_ref().map(|c| c.get_db()).unwrap_or(0),
+			PoolConnection::Cluster(con) => con.get_db(),
+			PoolConnection::Sentinel(con) => con.get_db(),
+			PoolConnection::Multiplexed(con) => con.get_db(),
+		}
+	}
+}
@@ -0,0 +1,109 @@
+use failure::Error;
+use std::ops::Deref;
+
+use crate::metrics::Operation;
+use crate::{Backoff, Message, QueueKeys, Rsmq};
+#[cfg(feature = "tracing")]
+use tracing::field::Empty;
+
+/// A received message that remembers its queue, see [`Rsmq::receive_delivery`].
+///
+/// Every delivery must be settled with [`ack`](Delivery::ack), [`nack`](Delivery::nack),
+/// [`nack_with`](Delivery::nack_with), [`dead_letter`](Delivery::dead_letter) or
+/// [`into_message`](Delivery::into_message). Dropping it unsettled, e.g. on an early return, logs a warning in debug
+/// builds with the `tracing` feature; the message becomes visible again once its visibility timeout expires.
+///
+/// ```no_run
+/// # async fn run(rsmq: rsmq::Rsmq) -> Result<(), failure::Error> {
+/// let delivery = rsmq.receive_delivery("jobs", None).await?;
+/// match delivery.message.parse::<u32>() {
+///   Ok(n) if n > 0 => delivery.ack().await?,
+///   Ok(_) => delivery.nack(10).await.map(|_| true)?,
+///   Err(_) => delivery.dead_letter().await?,
+/// };
+/// # Ok(())
+/// # }
+/// ```
+pub struct Delivery<'a> {
+	rsmq: &'a Rsmq,
+	keys: QueueKeys,
+	message: Message,
+	settled: bool,
+}
+
+impl Rsmq {
+	/// Like [`Rsmq::receive_message`], returning a [`Delivery`] to settle the message with.
+	#[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all, fields(ns = %self.name_space, qname = %qname, vt = ?hidefor, id = Empty, rc = Empty, round_trips = Empty, outcome = Empty)))]
+	pub async fn receive_delivery(&self, qname: &str, hidefor: Option<u64>) -> Result<Delivery<'_>, Error> {
+		let keys = self.queue_keys(qname);
+		let message = self.observe(Some(Operation::Receive), self.receive(&keys, hidefor)).await?;
+		Ok(Delivery { rsmq: self, keys, message, settled: false })
+	}
+}
+
+impl Delivery<'_> {
+	pub fn qname(&self) -> &str { &self.keys.qname }
+
+	/// Delete the message, `false` if it was already gone.
+	#[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all, fields(ns = %self.rsmq.name_space, qname = %self.keys.qname, id = %self.message.id, round_trips = Empty, outcome = Empty)))]
+	pub async fn ack(mut self) -> Result<bool, Error> {
+		self.settled = true;
+		self.rsmq.observe(Some(Operation::Delete), self.rsmq.delete(&self.keys, &self.message.id)).await
+	}
+
+	/// Give the message back, to be received again in `delay` seconds. Returns when it becomes visible, in milliseconds.
+	#[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all, fields(ns = %self.rsmq.name_space, qname = %self.keys.qname, id = %self.message.id, delay, round_trips = Empty, outcome = Empty)))]
+	pub async fn nack(mut self, delay: u64) -> Result<u64, Error> {
+		self.settled = true;
+		self.rsmq.observe(None, self.rsmq.change_visibility(&self.keys, &self.message.id, delay)).await
+	}
+
+	/// Give the message back, to be received again after `backoff`, see [`Rsmq::nack_message`].
+	#[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all, fields(ns = %self.rsmq.name_space, qname = %self.keys.qname, id = %self.message.id, backoff = ?backoff, round_trips = Empty, outcome = Empty)))]
+	pub async fn nack_with(mut self, backoff: &Backoff) -> Result<u64, Error> {
+		self.settled = true;
+		self.rsmq.observe(None, self.rsmq.nack(&self.keys, &self.message.id, backoff)).await
+	}
+
+	/// Keep the message hidden for another `hidefor` seconds from now, while it takes longer to process. Returns when
+	/// it becomes visible, in milliseconds.
+	#[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all, fields(ns = %self.rsmq.name_space, qname = %self.keys.qname, id = %self.message.id, vt = hidefor, round_trips = Empty, outcome = Empty)))]
+	pub async fn extend(&mut self, hidefor: u64) -> Result<u64, Error> {
+		self.rsmq.observe(None, self.rsmq.change_visibility(&self.keys, &self.message.id, hidefor)).await
+	}
+
+	/// Move the message to the dead letter queue, see [`RsmqBuilder::dead_letter_queue`](crate::RsmqBuilder::dead_letter_queue).
+	/// `false` if it was already gone.
+	#[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all, fields(ns = %self.rsmq.name_space, qname = %self.keys.qname, id = %self.message.id, round_trips = Empty, outcome = Empty)))]
+	pub async fn dead_letter(mut self) -> Result<bool, Error> {
+		self.settled = true;
+		self.rsmq.observe(None, self.rsmq.dead_letter(&self.keys, &self.message.id)).await
+	}
+
+	/// Settle the delivery without touching the message, e.g. to delete it later with [`Rsmq::delete_message`].
+	pub fn into_message(mut self) -> Message {
+		self.settled = true;
+		std::mem::take(&mut self.message)
+	}
+}
+
+impl Deref for Delivery<'_> {
+	type Target = Message;
+
+	fn deref(&self) -> &Message { &self.message }
+}
+
+impl std::fmt::Debug for Delivery<'_> {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		f.debug_struct("Delivery").field("qname", &self.keys.qname).field("message", &self.message).field("settled", &self.settled).finish()
+	}
+}
+
+#[cfg(all(debug_assertions, feature = "tracing"))]
+impl Drop for Delivery<'_> {
+	fn drop(&mut self) {
+		if !self.settled {
+			tracing::warn!(qname = %self.keys.qname, id = %self.message.id, "delivery dropped without ack, nack or dead_letter");
+		}
+	}
+}
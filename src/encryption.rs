@@ -0,0 +1,156 @@
+use failure::{format_err, Error};
+use std::collections::HashMap;
+
+/// The AEAD cipher of an encryption key, see [`Encryption`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Cipher {
+	/// AES-256 in Galois/Counter Mode. Needs the `aes-gcm` feature.
+	#[cfg(feature = "aes-gcm")]
+	Aes256Gcm,
+	/// ChaCha20-Poly1305, fast without AES hardware support. Needs the `chacha20poly1305` feature.
+	#[cfg(feature = "chacha20poly1305")]
+	ChaCha20Poly1305,
+}
+
+#[derive(Clone)]
+struct Key {
+	cipher: Cipher,
+	// Unused without a cipher feature, when there can't be keys.
+	#[allow(dead_code)]
+	key: Vec<u8>,
+}
+
+/// Encryption of message bodies at rest, see [`RsmqBuilder::encryption`](crate::RsmqBuilder::encryption).
+///
+/// Every key has an id that is stored with the messages it encrypted. To rotate keys, add the new key, encrypt with it
+/// and keep the old one until all messages encrypted with it were consumed.
+///
+/// ```no_run
+/// # #[cfg(feature = "aes-gcm")]
+/// # fn run(old_key: [u8; 32], new_key: [u8; 32]) -> Result<(), failure::Error> {
+/// use rsmq::{Cipher, Encryption};
+///
+/// let encryption = Encryption::new()
+///   .add_key("2024-01", Cipher::Aes256Gcm, &old_key)?
+///   .add_key("2024-06", Cipher::Aes256Gcm, &new_key)?
+///   // Encrypt the messages of the namespace with the new key...
+///   .encrypt_with("2024-06")?
+///   // ...but don't encrypt those of the "metrics" queue.
+///   .encrypt_queue_with("metrics", None)?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Default)]
+pub struct Encryption {
+	keys: HashMap<String, Key>,
+	default_key: Option<String>,
+	queue_keys: HashMap<String, Option<String>>,
+}
+
+impl std::fmt::Debug for Encryption {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		let mut keys: Vec<_> = self.keys.keys().collect();
+		keys.sort();
+		f.debug_struct("Encryption").field("keys", &keys).field("default_key", &self.default_key).field("queue_keys", &self.queue_keys).finish()
+	}
+}
+
+impl Encryption {
+	/// No keys, nothing is encrypted.
+	pub fn new() -> Encryption { Encryption::default() }
+
+	/// Add a 32 byte `key` for `cipher`, to decrypt messages encrypted with the key `id` and to encrypt with.
+	pub fn add_key(mut self, id: &str, cipher: Cipher, key: &[u8]) -> Result<Encryption, Error> {
+		if id.is_empty() || id.chars().any(|c| c.is_whitespace() || c.is_control()) {
+			return Err(format_err!("Invalid encryption key id {:?}", id));
+		}
+		if key.len() != 32 {
+			return Err(format_err!("Encryption keys must be 32 bytes long, {:?} is {}", id, key.len()));
+		}
+		self.keys.insert(id.into(), Key { cipher, key: key.into() });
+		Ok(self)
+	}
+
+	/// Encrypt the messages of every queue with the key `id`, unless set differently for the queue.
+	pub fn encrypt_with(mut self, id: &str) -> Result<Encryption, Error> {
+		self.default_key = Some(self.known(id)?);
+		Ok(self)
+	}
+
+	/// Encrypt the messages sent to `qname` with the key `id`, or leave them unencrypted with `None`.
+	pub fn encrypt_queue_with(mut self, qname: &str, id: Option<&str>) -> Result<Encryption, Error> {
+		let id = id.map(|id| self.known(id)).transpose()?;
+		self.queue_keys.insert(qname.into(), id);
+		Ok(self)
+	}
+
+	fn known(&self, id: &str) -> Result<String, Error> {
+		if self.keys.contains_key(id) {
+			Ok(id.into())
+		} else {
+			Err(format_err!("Unknown encryption key {:?}", id))
+		}
+	}
+
+	/// Encrypt `plaintext` for `qname`, returning the ciphertext and the id of the key, `None` if the queue's messages
+	/// aren't encrypted.
+	pub(crate) fn encrypt(&self, qname: &str, plaintext: &[u8]) -> Result<Option<(Vec<u8>, &str)>, Error> {
+		let id = match self.queue_keys.get(qname) {
+			Some(id) => id.as_ref(),
+			None => self.default_key.as_ref(),
+		};
+		match id {
+			Some(id) => Ok(Some((self.keys[id].seal(plaintext)?, id))),
+			None => Ok(None),
+		}
+	}
+
+	/// Decrypt `data` encrypted with the key `id`.
+	pub(crate) fn decrypt(&self, id: &str, data: &[u8]) -> Result<Vec<u8>, Error> {
+		self.keys.get(id).ok_or_else(|| format_err!("Unknown encryption key {:?}", id))?.open(data)
+	}
+}
+
+// The nonce is random and stored in front of the ciphertext; both ciphers use 96 bit nonces.
+const NONCE_LEN: usize = 12;
+
+impl Key {
+	#[allow(unused_variables)]
+	fn seal(&self, plaintext: &[u8]) -> Result<Vec<u8>, Error> {
+		match self.cipher {
+			#[cfg(feature = "aes-gcm")]
+			Cipher::Aes256Gcm => aead_seal::<aes_gcm::Aes256Gcm>(&self.key, plaintext),
+			#[cfg(feature = "chacha20poly1305")]
+			Cipher::ChaCha20Poly1305 => aead_seal::<chacha20poly1305::ChaCha20Poly1305>(&self.key, plaintext),
+		}
+	}
+
+	#[allow(unused_variables)]
+	fn open(&self, data: &[u8]) -> Result<Vec<u8>, Error> {
+		if data.len() < NONCE_LEN {
+			return Err(format_err!("Encrypted message is too short"));
+		}
+		match self.cipher {
+			#[cfg(feature = "aes-gcm")]
+			Cipher::Aes256Gcm => aead_open::<aes_gcm::Aes256Gcm>(&self.key, data),
+			#[cfg(feature = "chacha20poly1305")]
+			Cipher::ChaCha20Poly1305 => aead_open::<chacha20poly1305::ChaCha20Poly1305>(&self.key, data),
+		}
+	}
+}
+
+#[cfg(any(feature = "aes-gcm", feature = "chacha20poly1305"))]
+fn aead_seal<C: aead::Aead + aead::KeyInit + aead::AeadCore>(key: &[u8], plaintext: &[u8]) -> Result<Vec<u8>, Error> {
+	let cipher = C::new_from_slice(key).map_err(|_| format_err!("Invalid encryption key"))?;
+	let nonce = C::generate_nonce(&mut aead::OsRng);
+	let mut data = nonce.to_vec();
+	data.extend(cipher.encrypt(&nonce, plaintext).map_err(|_| format_err!("Encrypting the message failed"))?);
+	Ok(data)
+}
+
+#[cfg(any(feature = "aes-gcm", feature = "chacha20poly1305"))]
+fn aead_open<C: aead::Aead + aead::KeyInit>(key: &[u8], data: &[u8]) -> Result<Vec<u8>, Error> {
+	let cipher = C::new_from_slice(key).map_err(|_| format_err!("Invalid encryption key"))?;
+	let (nonce, ciphertext) = data.split_at(NONCE_LEN);
+	cipher.decrypt(aead::Nonce::<C>::from_slice(nonce), ciphertext).map_err(|_| format_err!("Decrypting the message failed, it was altered or the key is wrong"))
+}
@@ -0,0 +1,80 @@
+use base64::Engine;
+use failure::{format_err, Error};
+
+use crate::Headers;
+
+// Starts the bodies of messages with headers. Plain RSMQ messages can't be told apart from text starting with the
+// marker, but a control character followed by the marker is not something other clients send.
+const MARKER: &str = "\u{1}rsmq/1\n";
+
+// Headers starting with this prefix are set by `Rsmq` itself.
+const RESERVED_PREFIX: &str = "rsmq-";
+
+/// The compression of the body, see [`crate::Compression`].
+pub(crate) const CONTENT_ENCODING: &str = "rsmq-content-encoding";
+/// The id of the key the body is encrypted with, see [`crate::Encryption`].
+pub(crate) const KEY_ID: &str = "rsmq-key-id";
+/// The id of the codec the body was encoded with, see [`crate::Codec`].
+pub(crate) const CODEC: &str = "rsmq-codec";
+/// The reference of the blob holding the body, see [`crate::BlobStore`].
+pub(crate) const CLAIM_CHECK: &str = "rsmq-claim-check";
+/// The key id and HMAC of the message, see [`crate::Signing`].
+pub(crate) const SIGNATURE: &str = "rsmq-signature";
+/// Why a message was moved to the quarantine queue, see [`crate::Signing::quarantine`].
+#[cfg(feature = "signing")]
+pub(crate) const QUARANTINE_REASON: &str = "rsmq-quarantine-reason";
+/// The queue a message was dead lettered from, see [`crate::Delivery::dead_letter`].
+pub(crate) const DEAD_LETTERED_FROM: &str = "rsmq-dead-lettered-from";
+
+/// Fail if the application's `headers` use a name reserved for `Rsmq`.
+pub(crate) fn check_reserved(headers: &Headers) -> Result<(), Error> {
+	match headers.keys().find(|name| name.starts_with(RESERVED_PREFIX)) {
+		Some(name) => Err(format_err!("Message header {:?} is reserved, names starting with {:?} can't be sent", name, RESERVED_PREFIX)),
+		None => Ok(()),
+	}
+}
+
+/// Wrap `body` and `headers` into the body stored in the queue. Without headers the body is stored as is, so messages
+/// stay readable by other RSMQ clients.
+pub(crate) fn encode(headers: &Headers, body: &str) -> Result<String, Error> {
+	if headers.is_empty() {
+		return Ok(body.into());
+	}
+	let mut out = String::from(MARKER);
+	for (name, value) in headers {
+		if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.') {
+			return Err(format_err!("Invalid message header name {:?}", name));
+		}
+		if value.contains(['\n', '\r']) {
+			return Err(format_err!("Invalid value of message header {:?}", name));
+		}
+		out.push_str(name);
+		out.push(':');
+		out.push_str(value);
+		out.push('\n');
+	}
+	out.push('\n');
+	out.push_str(body);
+	Ok(out)
+}
+
+/// The headers and body of a stored body written by [`encode`] with headers, `None` for plain messages.
+pub(crate) fn decode(raw: &str) -> Option<(Headers, &str)> {
+	let mut rest = raw.strip_prefix(MARKER)?;
+	let mut headers = Headers::new();
+	loop {
+		let end = rest.find('\n')?;
+		let line = &rest[..end];
+		rest = &rest[end + 1..];
+		if line.is_empty() {
+			return Some((headers, rest));
+		}
+		let colon = line.find(':')?;
+		headers.insert(line[..colon].into(), line[colon + 1..].into());
+	}
+}
+
+/// Bodies that were compressed or encrypted are stored base64 encoded.
+pub(crate) fn base64_encode(bytes: &[u8]) -> String { base64::engine::general_purpose::STANDARD.encode(bytes) }
+
+pub(crate) fn base64_decode(s: &str) -> Result<Vec<u8>, Error> { Ok(base64::engine::general_purpose::STANDARD.decode(s)?) }
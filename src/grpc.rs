@@ -0,0 +1,190 @@
+//! A gRPC service for queues, defined in `proto/rsmq.proto`.
+//!
+//! Its `Receive` call streams messages to the client as they become visible. The service only takes a message from the
+//! queue when the client is ready to accept the next one, so a slow consumer doesn't let received messages time out in
+//! a buffer; a message is redelivered after its visibility timeout if the client goes away before getting it.
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), failure::Error> {
+//! use std::sync::Arc;
+//!
+//! let rsmq = rsmq::Rsmq::new("redis://127.0.0.1/", "rsmq").await?;
+//! rsmq::grpc::serve(Arc::new(rsmq), ([127, 0, 0, 1], 50051).into()).await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use failure::Error;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tonic::{Request, Response, Status};
+
+use crate::{is_no_messages, Queue, QueueBackend};
+use proto::rsmq_server::{Rsmq as RsmqRpc, RsmqServer};
+
+/// The messages and the client and server of the `rsmq.Rsmq` service, generated from `proto/rsmq.proto`.
+pub mod proto {
+	tonic::include_proto!("rsmq");
+}
+
+const DEFAULT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Implements the `rsmq.Rsmq` service on top of any [`QueueBackend`]; add it to a tonic server with
+/// [`RsmqService::into_server`], or use [`serve`].
+#[derive(Clone)]
+pub struct RsmqService {
+	backend: Arc<dyn QueueBackend>,
+}
+
+impl RsmqService {
+	pub fn new(backend: Arc<dyn QueueBackend>) -> RsmqService { RsmqService { backend } }
+
+	pub fn into_server(self) -> RsmqServer<RsmqService> { RsmqServer::new(self) }
+}
+
+/// Serve the service for `backend` on `addr` until the server fails.
+pub async fn serve(backend: Arc<dyn QueueBackend>, addr: SocketAddr) -> Result<(), Error> {
+	tonic::transport::Server::builder().add_service(RsmqService::new(backend).into_server()).serve(addr).await?;
+	Ok(())
+}
+
+fn status(e: &Error) -> Status {
+	match e.downcast_ref::<redis::RedisError>() {
+		Some(e) if e.detail() == Some("Queue not found") => Status::not_found("Queue not found"),
+		Some(e) if e.detail() == Some("Message is too long") => Status::invalid_argument("Message is too long"),
+		Some(e) if e.is_io_error() || e.is_connection_dropped() || e.is_connection_refusal() => Status::unavailable(e.to_string()),
+		_ => Status::internal(e.to_string()),
+	}
+}
+
+impl From<crate::Message> for proto::Message {
+	fn from(m: crate::Message) -> proto::Message { proto::Message { id: m.id, message: m.message, rc: m.rc, fr: m.fr, sent: m.sent, headers: m.headers.into_iter().collect() } }
+}
+
+impl From<Queue> for proto::QueueAttributes {
+	fn from(q: Queue) -> proto::QueueAttributes {
+		proto::QueueAttributes {
+			qname: q.qname,
+			vt: q.vt,
+			delay: q.delay,
+			maxsize: q.maxsize,
+			totalrecv: q.totalrecv,
+			totalsent: q.totalsent,
+			created: q.created,
+			modified: q.modified,
+			msgs: q.msgs,
+			hiddenmsgs: q.hiddenmsgs,
+		}
+	}
+}
+
+// A received message, or none if the queue was empty.
+#[allow(clippy::result_large_err)]
+fn optional_message(res: Result<crate::Message, Error>) -> Result<Response<proto::ReceiveMessageResponse>, Status> {
+	match res {
+		Ok(msg) => Ok(Response::new(proto::ReceiveMessageResponse { message: Some(msg.into()) })),
+		Err(e) if is_no_messages(&e) => Ok(Response::new(proto::ReceiveMessageResponse { message: None })),
+		Err(e) => Err(status(&e)),
+	}
+}
+
+#[tonic::async_trait]
+impl RsmqRpc for RsmqService {
+	async fn create_queue(&self, request: Request<proto::CreateQueueRequest>) -> Result<Response<proto::CreateQueueResponse>, Status> {
+		let req = request.into_inner();
+		let created = self.backend.create_queue(Queue::new(&req.qname, req.vt, req.delay, req.maxsize)).await.map_err(|e| status(&e))?;
+		Ok(Response::new(proto::CreateQueueResponse { created: created == 1 }))
+	}
+
+	async fn list_queues(&self, _: Request<proto::ListQueuesRequest>) -> Result<Response<proto::ListQueuesResponse>, Status> {
+		let queues = self.backend.list_queues().await.map_err(|e| status(&e))?;
+		Ok(Response::new(proto::ListQueuesResponse { queues }))
+	}
+
+	async fn delete_queue(&self, request: Request<proto::DeleteQueueRequest>) -> Result<Response<proto::DeleteQueueResponse>, Status> {
+		self.backend.delete_queue(&request.into_inner().qname).await.map_err(|e| status(&e))?;
+		Ok(Response::new(proto::DeleteQueueResponse {}))
+	}
+
+	async fn purge_queue(&self, request: Request<proto::PurgeQueueRequest>) -> Result<Response<proto::PurgeQueueResponse>, Status> {
+		let purged = self.backend.purge_queue(&request.into_inner().qname).await.map_err(|e| status(&e))?;
+		Ok(Response::new(proto::PurgeQueueResponse { purged }))
+	}
+
+	async fn get_queue_attributes(&self, request: Request<proto::GetQueueAttributesRequest>) -> Result<Response<proto::QueueAttributes>, Status> {
+		let q = self.backend.get_queue_attributes(&request.into_inner().qname).await.map_err(|e| status(&e))?;
+		Ok(Response::new(q.into()))
+	}
+
+	async fn set_queue_attributes(&self, request: Request<proto::SetQueueAttributesRequest>) -> Result<Response<proto::QueueAttributes>, Status> {
+		let req = request.into_inner();
+		let q = self.backend.set_queue_attributes(&req.qname, req.vt, req.delay, req.maxsize).await.map_err(|e| status(&e))?;
+		Ok(Response::new(q.into()))
+	}
+
+	async fn send_message(&self, request: Request<proto::SendMessageRequest>) -> Result<Response<proto::SendMessageResponse>, Status> {
+		let req = request.into_inner();
+		let id = self.backend.send_message(&req.qname, &req.message, req.delay).await.map_err(|e| status(&e))?;
+		Ok(Response::new(proto::SendMessageResponse { id }))
+	}
+
+	async fn receive_message(&self, request: Request<proto::ReceiveMessageRequest>) -> Result<Response<proto::ReceiveMessageResponse>, Status> {
+		let req = request.into_inner();
+		optional_message(self.backend.receive_message(&req.qname, req.vt).await)
+	}
+
+	type ReceiveStream = mpsc::Receiver<Result<proto::Message, Status>>;
+
+	async fn receive(&self, request: Request<proto::ReceiveRequest>) -> Result<Response<Self::ReceiveStream>, Status> {
+		let req = request.into_inner();
+		// Fail early for unknown queues instead of in the stream.
+		self.backend.get_queue_attributes(&req.qname).await.map_err(|e| status(&e))?;
+		let poll_interval = if req.poll_interval_ms == 0 { DEFAULT_POLL_INTERVAL } else { Duration::from_millis(req.poll_interval_ms) };
+		let backend = self.backend.clone();
+		let (mut tx, rx) = mpsc::channel(1);
+		tokio::spawn(async move {
+			let mut sent = 0;
+			while req.max_messages == 0 || sent < req.max_messages {
+				// Wait until the client can take a message; this fails once the call is cancelled or timed out.
+				if std::future::poll_fn(|cx| tx.poll_ready(cx)).await.is_err() {
+					break;
+				}
+				match backend.receive_message(&req.qname, req.vt).await {
+					Ok(msg) => {
+						if tx.send(Ok(msg.into())).await.is_err() {
+							break;
+						}
+						sent += 1;
+					}
+					Err(e) if is_no_messages(&e) => tokio::time::delay_for(poll_interval).await,
+					Err(e) => {
+						let _ = tx.send(Err(status(&e))).await;
+						break;
+					}
+				}
+			}
+		});
+		Ok(Response::new(rx))
+	}
+
+	async fn pop_message(&self, request: Request<proto::PopMessageRequest>) -> Result<Response<proto::ReceiveMessageResponse>, Status> {
+		optional_message(self.backend.pop_message(&request.into_inner().qname).await)
+	}
+
+	async fn delete_message(&self, request: Request<proto::DeleteMessageRequest>) -> Result<Response<proto::DeleteMessageResponse>, Status> {
+		let req = request.into_inner();
+		let deleted = self.backend.delete_message(&req.qname, &req.id).await.map_err(|e| status(&e))?;
+		Ok(Response::new(proto::DeleteMessageResponse { deleted }))
+	}
+
+	async fn change_message_visibility(
+		&self,
+		request: Request<proto::ChangeMessageVisibilityRequest>,
+	) -> Result<Response<proto::ChangeMessageVisibilityResponse>, Status> {
+		let req = request.into_inner();
+		let visible_at = self.backend.change_message_visibility(&req.qname, &req.id, req.vt).await.map_err(|e| status(&e))?;
+		Ok(Response::new(proto::ChangeMessageVisibilityResponse { visible_at }))
+	}
+}
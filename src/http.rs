@@ -0,0 +1,48 @@
+//! Helpers shared by the HTTP servers.
+
+#[cfg(feature = "rest")]
+use hyper::{Body, Response, StatusCode};
+#[cfg(feature = "rest")]
+use serde_json::Value as Json;
+
+#[cfg(feature = "rest")]
+pub(crate) fn json_response(status: StatusCode, body: Json) -> Response<Body> {
+	Response::builder()
+		.status(status)
+		.header("Content-Type", "application/json")
+		.body(Body::from(body.to_string()))
+		.expect("valid response")
+}
+
+/// Decode `%XX` escapes, leaving malformed ones as they are.
+pub(crate) fn percent_decode(segment: &str) -> String {
+	let bytes = segment.as_bytes();
+	let mut out = Vec::with_capacity(bytes.len());
+	let mut i = 0;
+	while i < bytes.len() {
+		let hex = bytes.get(i + 1..i + 3).and_then(|h| std::str::from_utf8(h).ok()).and_then(|h| u8::from_str_radix(h, 16).ok());
+		match (bytes[i], hex) {
+			(b'%', Some(b)) => {
+				out.push(b);
+				i += 3;
+			}
+			(b, _) => {
+				out.push(b);
+				i += 1;
+			}
+		}
+	}
+	String::from_utf8_lossy(&out).into_owned()
+}
+
+/// The pairs of an `application/x-www-form-urlencoded` body or query string.
+#[cfg(feature = "sqs")]
+pub(crate) fn form_pairs(form: &str) -> Vec<(String, String)> {
+	form.split('&')
+		.filter(|pair| !pair.is_empty())
+		.map(|pair| {
+			let (k, v) = pair.split_once('=').unwrap_or((pair, ""));
+			(percent_decode(&k.replace('+', " ")), percent_decode(&v.replace('+', " ")))
+		})
+		.collect()
+}
@@ -0,0 +1,124 @@
+use failure::Error;
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+use crate::connection;
+use crate::metrics::Operation;
+use crate::{claim_check_reference, Message, QueueKeys, Rsmq};
+#[cfg(feature = "tracing")]
+use tracing::field::Empty;
+
+/// Processes the messages of a queue once, even when they are delivered twice, see [`Rsmq::idempotent_consumer`].
+///
+/// The keys of processed messages, their ids unless set with [`dedup_key`](IdempotentConsumer::dedup_key), are kept
+/// in a sorted set next to the queue for `ttl`. A message is deleted in the same script that records its key, and a
+/// message whose key was recorded is deleted without processing it.
+///
+/// ```no_run
+/// # async fn run(rsmq: rsmq::Rsmq) -> Result<(), failure::Error> {
+/// use std::time::{Duration, Instant};
+///
+/// let payments = rsmq.idempotent_consumer("payments", Duration::from_secs(24 * 3600))
+///   // Producers retrying a send set the same key.
+///   .dedup_key(|m| m.headers.get("idempotency-key").cloned().unwrap_or_else(|| m.id.clone()));
+///
+/// let charged = payments.process(None, |message| async move {
+///   // Charge the payment in `message`...
+///   Ok(message.message.len())
+/// }).await?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// Processing that takes longer than the visibility timeout of the message can still run twice concurrently.
+pub struct IdempotentConsumer<'a> {
+	rsmq: &'a Rsmq,
+	keys: QueueKeys,
+	processed: String,
+	ttl: Duration,
+	dedup_key: Box<dyn Fn(&Message) -> String + Send + Sync>,
+}
+
+// The sorted set of processed keys of the queue whose messages are in `message_zset_key`, scored by when they expire.
+pub(crate) fn processed_key(message_zset_key: &str) -> String { format!("{}:processed", message_zset_key) }
+
+impl Rsmq {
+	/// A consumer of `qname` remembering processed messages for `ttl`, see [`IdempotentConsumer`].
+	pub fn idempotent_consumer(&self, qname: &str, ttl: Duration) -> IdempotentConsumer<'_> {
+		let keys = self.queue_keys(qname);
+		IdempotentConsumer { rsmq: self, processed: processed_key(&keys.zset), keys, ttl, dedup_key: Box::new(|m| m.id.clone()) }
+	}
+}
+
+impl IdempotentConsumer<'_> {
+	/// Tell duplicates apart by `key` instead of the message id, e.g. to skip messages that were sent twice.
+	pub fn dedup_key<F: Fn(&Message) -> String + Send + Sync + 'static>(mut self, key: F) -> Self {
+		self.dedup_key = Box::new(key);
+		self
+	}
+
+	/// Receive a message and process it with `f`, hiding it for `hidefor` seconds or the `vt` of the queue. The message
+	/// is deleted once `f` succeeded; if `f` fails it becomes visible again when it's no longer hidden. Returns `None`
+	/// for a duplicate, which is deleted without calling `f`.
+	#[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all, fields(ns = %self.rsmq.name_space, qname = %self.keys.qname, vt = ?hidefor, id = Empty, rc = Empty, duplicate = Empty, round_trips = Empty, outcome = Empty)))]
+	pub async fn process<T, F, Fut>(&self, hidefor: Option<u64>, f: F) -> Result<Option<T>, Error>
+	where
+		F: FnOnce(Message) -> Fut,
+		Fut: Future<Output = Result<T, Error>>,
+	{
+		// Three steps, each counting its round trips, so count them for the whole of `process` too.
+		connection::count_round_trips(async {
+			let m = self.rsmq.observe(Some(Operation::Receive), self.rsmq.receive(&self.keys, hidefor)).await?;
+			let (id, key) = (m.id.clone(), (self.dedup_key)(&m));
+			let start = Instant::now();
+			let duplicate = self.rsmq.observe(None, self.delete_if_processed(&id, &key)).await?;
+			trace_record!("duplicate", duplicate);
+			if duplicate {
+				// Only a duplicate is deleted by the check, so only then does it count as a delete.
+				if let Some(metrics) = &self.rsmq.metrics {
+					metrics.observe(Operation::Delete, start.elapsed(), &Ok::<_, Error>(()));
+				}
+				return Ok(None);
+			}
+			let value = f(m).await?;
+			self.rsmq.observe(Some(Operation::Delete), self.commit(&id, &key)).await?;
+			Ok(Some(value))
+		})
+		.await
+	}
+
+	async fn delete_if_processed(&self, msgid: &str, key: &str) -> Result<bool, Error> {
+		let mut con = self.rsmq.connection().await?;
+		let deleted: Vec<String> = self.rsmq.scripts.delete_if_processed
+			.key(&self.keys.zset)
+			.key(&self.keys.hash)
+			.key(&self.processed)
+			.arg(msgid)
+			.arg(key)
+			.invoke_async(&mut con)
+			.await?;
+		self.delete_blob(deleted.first().map(String::as_str)).await;
+		Ok(!deleted.is_empty())
+	}
+
+	async fn commit(&self, msgid: &str, key: &str) -> Result<(), Error> {
+		let mut con = self.rsmq.connection().await?;
+		let body: Option<String> = self.rsmq.scripts.commit_processed
+			.key(&self.keys.zset)
+			.key(&self.keys.hash)
+			.key(&self.processed)
+			.arg(msgid)
+			.arg(key)
+			.arg(self.ttl.as_millis().to_string())
+			.invoke_async(&mut con)
+			.await?;
+		self.delete_blob(body.as_deref()).await;
+		Ok(())
+	}
+
+	async fn delete_blob(&self, body: Option<&str>) {
+		if let Some(reference) = body.and_then(claim_check_reference) {
+			self.rsmq.delete_blob(&reference).await;
+		}
+	}
+}
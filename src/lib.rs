@@ -1,9 +1,88 @@
 use failure::{Error, format_err};
-use bb8::Pool;
-use bb8_redis::RedisConnectionManager;
-use std::{default::Default, ops::DerefMut};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::default::Default;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Instant;
+use serde::Serialize;
 use redis::{from_redis_value, RedisError, RedisResult, Value, ErrorKind as RedisErrorKind};
 
+// Record a field of the current tracing span, nothing without the `tracing` feature.
+macro_rules! trace_record {
+	($field:literal, $value:expr) => {
+		#[cfg(feature = "tracing")]
+		tracing::Span::current().record($field, $value);
+	};
+}
+
+#[cfg(feature = "blocking")]
+pub mod blocking;
+mod backend;
+mod blob;
+mod builder;
+mod cluster;
+mod codec;
+mod compression;
+mod connection;
+mod delivery;
+mod encryption;
+mod envelope;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+#[cfg(any(feature = "rest", feature = "sqs"))]
+mod http;
+mod idempotent;
+mod memory;
+pub mod metrics;
+#[cfg(feature = "rest")]
+pub mod rest;
+mod retry;
+mod sentinel;
+#[cfg(feature = "signing")]
+mod signing;
+#[cfg(feature = "sqs")]
+pub mod sqs;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+mod trace_context;
+mod typed_queue;
+mod util;
+
+pub use backend::QueueBackend;
+pub use blob::{BlobStore, FsBlobStore};
+pub use builder::RsmqBuilder;
+pub use cluster::{ClusterConnection, ClusterConnectionManager};
+pub use codec::Codec;
+#[cfg(feature = "bincode")]
+pub use codec::Bincode;
+#[cfg(feature = "cbor")]
+pub use codec::Cbor;
+#[cfg(feature = "json")]
+pub use codec::Json;
+#[cfg(feature = "msgpack")]
+pub use codec::MessagePack;
+pub use compression::Compression;
+pub use delivery::Delivery;
+pub use encryption::{Cipher, Encryption};
+pub use idempotent::IdempotentConsumer;
+pub use memory::MemoryBackend;
+pub use retry::{Backoff, RetryPolicy};
+pub use sentinel::{SentinelConnection, SentinelConnectionManager};
+#[cfg(feature = "signing")]
+pub use signing::Signing;
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteBackend;
+pub use trace_context::TraceContext;
+pub use typed_queue::{TypedMessage, TypedQueue};
+
+use connection::{Connection, RedisPool};
+#[cfg(feature = "tracing")]
+use tracing::field::Empty;
+use metrics::{Metrics, Operation};
+#[cfg(feature = "signing")]
+use signing::InvalidSignature;
+
 #[derive(Clone, Debug)]
 pub struct Queue {
 	pub qname: String,
@@ -48,6 +127,9 @@ impl Default for Queue {
 	}
 }
 
+/// Message headers, by name.
+pub type Headers = BTreeMap<String, String>;
+
 #[derive(Clone, Debug)]
 pub struct Message {
 	pub id: String,
@@ -57,6 +139,12 @@ pub struct Message {
 	pub fr: u64,
 	// First receive time
 	pub sent: u64,
+	/// The headers the message was sent with by [`Rsmq::send_message_with_headers`], empty for plain messages.
+	pub headers: Headers,
+}
+
+impl Default for Message {
+	fn default() -> Message { Message::new() }
 }
 
 impl Message {
@@ -67,6 +155,7 @@ impl Message {
 			sent: 0,
 			fr: 0,
 			rc: 0,
+			headers: Headers::new(),
 		}
 	}
 }
@@ -75,8 +164,8 @@ impl redis::FromRedisValue for Message {
 	fn from_redis_value(v: &Value) -> RedisResult<Message> {
 		match *v {
 			Value::Bulk(ref items) => {
-				if items.len() == 0 {
-					return Err(RedisError::from((RedisErrorKind::TryAgain, "No messages to receive")));
+				if items.is_empty() {
+					return Err(no_messages());
 				}
 				let mut m = Message::new();
 				m.id = from_redis_value(&items[0])?;
@@ -98,9 +187,278 @@ impl redis::FromRedisValue for Message {
 	}
 }
 
+// The blob a stored message body refers to, see `RsmqBuilder::claim_check`.
+fn claim_check_reference(body: &str) -> Option<String> { envelope::decode(body)?.0.remove(envelope::CLAIM_CHECK) }
+
+// The error for an empty queue, as returned by `receive_message` and `pop_message` of every backend.
+pub(crate) fn no_messages() -> RedisError { RedisError::from((RedisErrorKind::TryAgain, "No messages to receive")) }
+
+/// Whether `e` is the error [`Rsmq::receive_message`] and [`Rsmq::pop_message`] return for an empty queue.
+pub fn is_no_messages(e: &Error) -> bool { e.downcast_ref::<RedisError>().map(|e| e.kind()) == Some(RedisErrorKind::TryAgain) }
+
+// An error signalled by one of the scripts with `redis.error_reply("ERR ...")`, in the shape redis-rs parses it into, so
+// other backends can report the same errors.
+pub(crate) fn script_error(detail: &str) -> RedisError { RedisError::from((RedisErrorKind::ResponseError, "An error was signalled by the server", detail.to_string())) }
+
+// Shared prelude of every script: allow writes after reading the non-deterministic `TIME` (a no-op from Redis 5 on) and
+// fetch the current server time, both in microseconds (`now_us`) and milliseconds (`now`).
+const LUA_NOW: &str = r#"
+redis.replicate_commands()
+local t = redis.call("TIME")
+local now_us = tonumber(t[1]) * 1000000 + tonumber(t[2])
+local now = math.floor(now_us / 1000)
+"#;
+
+// KEYS[1]: messages zset, KEYS[2]: queue hash, KEYS[3]: processed keys zset
+// ARGV[1]: message id, ARGV[2]: key of the message
+// Deletes the message if its key was processed, returning its body in a table, an empty table otherwise.
+const DELETE_IF_PROCESSED_LUA: &str = r#"
+local expires_at = redis.call("ZSCORE", KEYS[3], ARGV[2])
+if not expires_at or tonumber(expires_at) <= now then
+	return {}
+end
+local body = redis.call("HGET", KEYS[2], ARGV[1])
+redis.call("ZREM", KEYS[1], ARGV[1])
+redis.call("HDEL", KEYS[2], ARGV[1], ARGV[1] .. ":rc", ARGV[1] .. ":fr")
+return {body or ""}
+"#;
+
+// KEYS[1]: messages zset, KEYS[2]: queue hash, KEYS[3]: processed keys zset
+// ARGV[1]: message id, ARGV[2]: key of the message, ARGV[3]: ms to remember the key for
+// Records the key as processed and deletes the message, returning its body.
+const COMMIT_PROCESSED_LUA: &str = r#"
+redis.call("ZREMRANGEBYSCORE", KEYS[3], "-inf", now)
+redis.call("ZADD", KEYS[3], string.format("%.0f", now + tonumber(ARGV[3])), ARGV[2])
+redis.call("PEXPIRE", KEYS[3], ARGV[3])
+local body = redis.call("HGET", KEYS[2], ARGV[1])
+redis.call("ZREM", KEYS[1], ARGV[1])
+redis.call("HDEL", KEYS[2], ARGV[1], ARGV[1] .. ":rc", ARGV[1] .. ":fr")
+return body
+"#;
+
+// KEYS[1]: messages zset, KEYS[2]: queue hash
+// ARGV[1]: message id, ARGV[2]: initial delay in ms, ARGV[3]: maximum delay in ms, ARGV[4]: factor the delay grows by
+// with every receive, ARGV[5]: a random number in [0, 1) to wait between half and all of the delay, or "" to wait all of
+// it. Scripts can only seed Lua's generator deterministically, so the randomness comes from the client.
+const NACK_MESSAGE_LUA: &str = r#"
+if redis.call("EXISTS", KEYS[2]) == 0 then
+	return redis.error_reply("ERR Queue not found")
+end
+local rc = math.max(tonumber(redis.call("HGET", KEYS[2], ARGV[1] .. ":rc")) or 1, 1)
+local delay = math.min(tonumber(ARGV[2]) * tonumber(ARGV[4]) ^ (rc - 1), tonumber(ARGV[3]))
+if ARGV[5] ~= "" then
+	delay = delay / 2 + tonumber(ARGV[5]) * delay / 2
+end
+local expires_at = now + math.floor(delay)
+if redis.call("ZSCORE", KEYS[1], ARGV[1]) then
+	redis.call("ZADD", KEYS[1], string.format("%.0f", expires_at), ARGV[1])
+end
+return expires_at
+"#;
+
+// KEYS[1]: messages zset, KEYS[2]: queue hash
+// ARGV[1]: message id, ARGV[2]: seconds to hide the message for
+const CHANGE_MESSAGE_VISIBILITY_LUA: &str = r#"
+if redis.call("EXISTS", KEYS[2]) == 0 then
+	return redis.error_reply("ERR Queue not found")
+end
+local expires_at = now + tonumber(ARGV[2]) * 1000
+if redis.call("ZSCORE", KEYS[1], ARGV[1]) then
+	redis.call("ZADD", KEYS[1], string.format("%.0f", expires_at), ARGV[1])
+end
+return expires_at
+"#;
+
+// KEYS[1]: messages zset, KEYS[2]: queue hash
+// ARGV[1]: message body, ARGV[2]: delay in seconds or "" for the queue default, ARGV[3]: realtime channel or "",
+// ARGV[4]: 22 random alphanumerics
+//
+// Message ids are the JS RSMQ format: the send time in microseconds as 10 base36 digits followed by 22 random
+// alphanumerics, generated by the client because scripts can only seed Lua's generator deterministically. The timestamp part is kept strictly increasing per queue (in the `lastid` field of the queue hash) so
+// ids are unique and sort in send order even if the server clock goes backwards or two sends land on the same tick.
+const SEND_MESSAGE_LUA: &str = r#"
+local q = redis.call("HMGET", KEYS[2], "delay", "maxsize", "lastid")
+if not q[1] then
+	return redis.error_reply("ERR Queue not found")
+end
+local delay = tonumber(ARGV[2]) or tonumber(q[1])
+local maxsize = tonumber(q[2])
+if maxsize ~= -1 and #ARGV[1] > maxsize then
+	return redis.error_reply("ERR Message is too long")
+end
+local id_us = now_us
+local last = tonumber(q[3])
+if last and id_us <= last then
+	id_us = last + 1
+end
+redis.call("HSET", KEYS[2], "lastid", string.format("%.0f", id_us))
+local base36 = "0123456789abcdefghijklmnopqrstuvwxyz"
+local n = id_us
+local uid = ""
+repeat
+	local d = n % 36
+	uid = string.sub(base36, d + 1, d + 1) .. uid
+	n = math.floor(n / 36)
+until n == 0
+uid = uid .. ARGV[4]
+redis.call("ZADD", KEYS[1], string.format("%.0f", now + delay * 1000), uid)
+redis.call("HSET", KEYS[2], uid, ARGV[1])
+redis.call("HINCRBY", KEYS[2], "totalsent", 1)
+if ARGV[3] ~= "" then
+	redis.call("PUBLISH", ARGV[3], redis.call("ZCARD", KEYS[1]))
+end
+return uid
+"#;
+
+// KEYS[1]: messages zset, KEYS[2]: queue hash
+const POP_MESSAGE_LUA: &str = r#"
+if redis.call("EXISTS", KEYS[2]) == 0 then
+	return redis.error_reply("ERR Queue not found")
+end
+local msg = redis.call("ZRANGEBYSCORE", KEYS[1], "-inf", now, "LIMIT", "0", "1")
+if #msg == 0 then
+	return {}
+end
+redis.call("HINCRBY", KEYS[2], "totalrecv", 1)
+local mbody = redis.call("HGET", KEYS[2], msg[1])
+local rc = redis.call("HINCRBY", KEYS[2], msg[1] .. ":rc", 1)
+local o = {msg[1], mbody, rc}
+if rc == 1 then
+	table.insert(o, now)
+else
+	local fr = redis.call("HGET", KEYS[2], msg[1] .. ":fr")
+	table.insert(o, fr)
+end
+redis.call("ZREM", KEYS[1], msg[1])
+redis.call("HDEL", KEYS[2], msg[1], msg[1] .. ":rc", msg[1] .. ":fr")
+return o
+"#;
+
+// KEYS[1]: messages zset, KEYS[2]: queue hash
+// ARGV[1]: seconds to hide the message for or "" for the queue `vt`
+const RECEIVE_MESSAGE_LUA: &str = r#"
+local vt = redis.call("HGET", KEYS[2], "vt")
+if not vt then
+	return redis.error_reply("ERR Queue not found")
+end
+local hidefor = tonumber(ARGV[1]) or tonumber(vt)
+local msg = redis.call("ZRANGEBYSCORE", KEYS[1], "-inf", now, "LIMIT", "0", "1")
+if #msg == 0 then
+	return {}
+end
+redis.call("ZADD", KEYS[1], string.format("%.0f", now + hidefor * 1000), msg[1])
+redis.call("HINCRBY", KEYS[2], "totalrecv", 1)
+local mbody = redis.call("HGET", KEYS[2], msg[1])
+local rc = redis.call("HINCRBY", KEYS[2], msg[1] .. ":rc", 1)
+local o = {msg[1], mbody, rc}
+if rc == 1 then
+	redis.call("HSET", KEYS[2], msg[1] .. ":fr", now)
+	table.insert(o, now)
+else
+	local fr = redis.call("HGET", KEYS[2], msg[1] .. ":fr")
+	table.insert(o, fr)
+end
+return o
+"#;
+
+// KEYS[1]: messages zset, KEYS[2]: queue hash
+const PURGE_QUEUE_LUA: &str = r#"
+if redis.call("EXISTS", KEYS[2]) == 0 then
+	return redis.error_reply("ERR Queue not found")
+end
+local ids = redis.call("ZRANGE", KEYS[1], 0, -1)
+for _, id in ipairs(ids) do
+	redis.call("HDEL", KEYS[2], id, id .. ":rc", id .. ":fr")
+end
+redis.call("DEL", KEYS[1])
+return #ids
+"#;
+
+/// The Lua scripts used by [`Rsmq`], hashed once per instance. Every operation is a single script that reads the queue
+/// attributes and the server time itself, so it costs one atomic round trip. Invoking a `Script` sends `EVALSHA` and only
+/// falls back to `SCRIPT LOAD` when Redis replies with `NOSCRIPT`, e.g. after a restart or a `SCRIPT FLUSH`.
+pub(crate) struct Scripts {
+	change_message_visibility: redis::Script,
+	nack_message: redis::Script,
+	delete_if_processed: redis::Script,
+	commit_processed: redis::Script,
+	send_message: redis::Script,
+	pop_message: redis::Script,
+	receive_message: redis::Script,
+	purge_queue: redis::Script,
+}
+
+impl Scripts {
+	pub(crate) fn new() -> Scripts {
+		Scripts {
+			change_message_visibility: redis::Script::new(&[LUA_NOW, CHANGE_MESSAGE_VISIBILITY_LUA].concat()),
+			nack_message: redis::Script::new(&[LUA_NOW, NACK_MESSAGE_LUA].concat()),
+			delete_if_processed: redis::Script::new(&[LUA_NOW, DELETE_IF_PROCESSED_LUA].concat()),
+			commit_processed: redis::Script::new(&[LUA_NOW, COMMIT_PROCESSED_LUA].concat()),
+			send_message: redis::Script::new(&[LUA_NOW, SEND_MESSAGE_LUA].concat()),
+			pop_message: redis::Script::new(&[LUA_NOW, POP_MESSAGE_LUA].concat()),
+			receive_message: redis::Script::new(&[LUA_NOW, RECEIVE_MESSAGE_LUA].concat()),
+			purge_queue: redis::Script::new(&[LUA_NOW, PURGE_QUEUE_LUA].concat()),
+		}
+	}
+
+	/// Preload all scripts into the Redis script cache so the first calls do not pay for a `NOSCRIPT` round trip.
+	pub(crate) async fn load<C: redis::aio::ConnectionLike>(&self, con: &mut C) -> RedisResult<()> {
+		let mut pipe = redis::pipe();
+		for body in &[CHANGE_MESSAGE_VISIBILITY_LUA, NACK_MESSAGE_LUA, DELETE_IF_PROCESSED_LUA, COMMIT_PROCESSED_LUA, SEND_MESSAGE_LUA, POP_MESSAGE_LUA, RECEIVE_MESSAGE_LUA, PURGE_QUEUE_LUA] {
+			pipe.cmd("SCRIPT").arg("LOAD").arg([LUA_NOW, body].concat()).ignore();
+		}
+		pipe.query_async(con).await
+	}
+}
+
+/// How `Rsmq` names the Redis keys of a queue.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum KeyLayout {
+	/// The layout of the JS RSMQ: `{ns}:{qname}` for the messages and `{ns}:{qname}:Q` for the queue hash.
+	Plain,
+	/// `{ns}:{{qname}}` and `{ns}:{{qname}}:Q`: the queue name is a hash tag, so all keys of a queue end up in the same
+	/// Redis Cluster hash slot. Required for Redis Cluster, see [`Rsmq::migrate_key_layout`] to move existing queues.
+	HashTagged,
+}
+
+impl KeyLayout {
+	fn message_zset_key(self, name_space: &str, qname: &str) -> String {
+		match self {
+			KeyLayout::Plain => format!("{}:{}", name_space, qname),
+			KeyLayout::HashTagged => format!("{}:{{{}}}", name_space, qname),
+		}
+	}
+
+	fn queue_hash_key(self, name_space: &str, qname: &str) -> String { format!("{}:Q", self.message_zset_key(name_space, qname)) }
+}
+
+// The names of the keys of a queue, built once per operation, or once per `TypedQueue`.
+pub(crate) struct QueueKeys {
+	qname: String,
+	zset: String,
+	hash: String,
+	// The channel to publish the queue size on, empty unless `realtime`.
+	realtime: String,
+}
+
 pub struct Rsmq {
-	pool: Pool<RedisConnectionManager>,
+	pool: RedisPool,
 	name_space: String,
+	key_layout: KeyLayout,
+	realtime: bool,
+	default_queue: Queue,
+	retry_policy: RetryPolicy,
+	metrics: Option<Arc<Metrics>>,
+	// Compress bodies longer than the threshold.
+	compression: Option<(Compression, usize)>,
+	encryption: Option<Encryption>,
+	// Offload bodies longer than the threshold.
+	claim_check: Option<(Arc<dyn BlobStore>, usize)>,
+	#[cfg(feature = "signing")]
+	signing: Option<Signing>,
+	dead_letter_queue: Option<String>,
+	scripts: Scripts,
 }
 
 impl std::fmt::Debug for Rsmq {
@@ -111,303 +469,392 @@ impl std::fmt::Debug for Rsmq {
 
 impl Rsmq {
 	pub async fn new<T: redis::IntoConnectionInfo>(params: T, name_space: &str) -> Result<Rsmq, Error> {
-		let manager = RedisConnectionManager::new(params)?;
-		let pool = bb8::Pool::builder().build(manager).await?;
+		RsmqBuilder::new(params).name_space(name_space).build().await
+	}
 
-		let name_space = if name_space != "" {
-			name_space.into()
-		} else {
-			"rsmq".into()
-		};
+	/// Connect to a Redis Cluster through any of its `nodes`. Queues use the [`KeyLayout::HashTagged`] key layout.
+	pub async fn new_cluster<T: redis::IntoConnectionInfo>(nodes: Vec<T>, name_space: &str) -> Result<Rsmq, Error> {
+		RsmqBuilder::cluster(nodes).name_space(name_space).build().await
+	}
 
-		Ok(Rsmq { pool, name_space })
+	/// Connect to the master called `master_name` of a Redis Sentinel deployment, asking the `sentinels` for its
+	/// address. Connections follow the master when it fails over, see [`SentinelConnectionManager`]. Use
+	/// [`RsmqBuilder::sentinel`] for a master with a password or a database other than `0`.
+	pub async fn new_sentinel<T: redis::IntoConnectionInfo>(master_name: &str, sentinels: Vec<T>, name_space: &str) -> Result<Rsmq, Error> {
+		RsmqBuilder::sentinel(master_name, sentinels).name_space(name_space).build().await
 	}
 
+	/// Options for a queue called `qname` with the defaults set by [`RsmqBuilder::default_queue_options`].
+	pub fn default_queue(&self, qname: &str) -> Queue {
+		Queue { qname: qname.into(), ..self.default_queue.clone() }
+	}
+
+	/// Use a different key layout, e.g. [`KeyLayout::HashTagged`] on a single Redis server whose queues were migrated
+	/// with [`Rsmq::migrate_key_layout`]. Redis Cluster connections always use [`KeyLayout::HashTagged`].
+	pub fn with_key_layout(mut self, key_layout: KeyLayout) -> Rsmq {
+		if !self.is_cluster() {
+			self.key_layout = key_layout;
+		}
+		self
+	}
+
+	pub fn key_layout(&self) -> KeyLayout { self.key_layout }
+
+	/// Rename the keys of every queue in the namespace from the current key layout to `to`, e.g. from
+	/// [`KeyLayout::Plain`] to [`KeyLayout::HashTagged`] before importing the data into a Redis Cluster, including the
+	/// processed keys of idempotent consumers. Returns the names of the migrated queues; afterwards use an `Rsmq` with the new layout. Only supported on a single Redis
+	/// server, and no other client should use the queues while the keys are renamed.
+	#[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all, fields(ns = %self.name_space, to = ?to, round_trips = Empty, outcome = Empty)))]
+	pub async fn migrate_key_layout(&self, to: KeyLayout) -> Result<Vec<String>, Error> {
+		self.observe(None, async {
+			if self.is_cluster() {
+				return Err(format_err!("Key layouts can only be migrated on a single Redis server"));
+			}
+			let mut migrated = Vec::new();
+			if to == self.key_layout {
+				return Ok(migrated);
+			}
+			let mut con = self.connection().await?;
+			let queues: Vec<String> = redis::cmd("SMEMBERS").arg(self.queues_key()).query_async(&mut con).await?;
+			for qname in queues {
+				let from_zset = self.message_zset_key(&qname);
+				let from_hash = self.queue_hash_key(&qname);
+				let from_processed = idempotent::processed_key(&from_zset);
+				let (zset_exists, hash_exists, processed_exists): (bool, bool, bool) = redis::pipe()
+					.cmd("EXISTS").arg(&from_zset)
+					.cmd("EXISTS").arg(&from_hash)
+					.cmd("EXISTS").arg(&from_processed)
+					.query_async(&mut con)
+					.await?;
+				let to_zset = to.message_zset_key(&self.name_space, &qname);
+				let mut pipe = redis::pipe();
+				pipe.atomic();
+				if zset_exists {
+					pipe.cmd("RENAME").arg(&from_zset).arg(&to_zset).ignore();
+				}
+				if processed_exists {
+					pipe.cmd("RENAME").arg(&from_processed).arg(idempotent::processed_key(&to_zset)).ignore();
+				}
+				if hash_exists {
+					pipe.cmd("RENAME").arg(&from_hash).arg(to.queue_hash_key(&self.name_space, &qname)).ignore();
+				}
+				pipe.query_async::<_, ()>(&mut con).await?;
+				migrated.push(qname);
+			}
+			Ok(migrated)
+		})
+		.await
+	}
+
+	#[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all, fields(ns = %self.name_space, qname = %opts.qname, vt = opts.vt, delay = opts.delay, maxsize = opts.maxsize, round_trips = Empty, outcome = Empty)))]
 	pub async fn create_queue(&self, opts: Queue) -> Result<u8, Error> {
-		let con = self.pool.get()
-			.await?
-			.as_mut()
-			.ok_or_else(|| RedisError::from((RedisErrorKind::IoError, "Unable to acquire connection")))?;
-		let qky = self.queue_hash_key(&opts.qname);
-		let (ts, _): (u32, u32) = redis::cmd("TIME").query_async(con).await?;
-		let (res, ): (u8, ) = redis::pipe()
-			.atomic()
-			.cmd("HSETNX").arg(&qky).arg("vt").arg(opts.vt).ignore()
-			.cmd("HSETNX").arg(&qky).arg("delay").arg(opts.delay).ignore()
-			.cmd("HSETNX").arg(&qky).arg("maxsize").arg(opts.maxsize).ignore()
-			.cmd("HSETNX").arg(&qky).arg("totalrecv").arg(0).ignore()
-			.cmd("HSETNX").arg(&qky).arg("totalsent").arg(0).ignore()
-			.cmd("HSETNX").arg(&qky).arg("created").arg(ts).ignore()
-			.cmd("HSETNX").arg(&qky).arg("modified").arg(ts).ignore()
-			.cmd("SADD").arg(format!("{}:QUEUES", self.name_space)).arg(opts.qname)
-			.query_async(con)
-			.await?;
-		Ok(res)
+		self.observe(None, async {
+			let mut con = self.connection().await?;
+			let qky = self.queue_hash_key(&opts.qname);
+			let (ts, _): (u32, u32) = redis::cmd("TIME").query_async(&mut con).await?;
+			let mut pipe = redis::pipe();
+			pipe.atomic()
+				.cmd("HSETNX").arg(&qky).arg("vt").arg(opts.vt).ignore()
+				.cmd("HSETNX").arg(&qky).arg("delay").arg(opts.delay).ignore()
+				.cmd("HSETNX").arg(&qky).arg("maxsize").arg(opts.maxsize).ignore()
+				.cmd("HSETNX").arg(&qky).arg("totalrecv").arg(0).ignore()
+				.cmd("HSETNX").arg(&qky).arg("totalsent").arg(0).ignore()
+				.cmd("HSETNX").arg(&qky).arg("created").arg(ts).ignore()
+				.cmd("HSETNX").arg(&qky).arg("modified").arg(ts).ignore();
+			let mut sadd = redis::cmd("SADD");
+			sadd.arg(self.queues_key()).arg(opts.qname);
+			// On a cluster the set of queues lives in a different hash slot than the queue, so it can't be in the same MULTI.
+			let res = if self.is_cluster() {
+				pipe.query_async::<_, ()>(&mut con).await?;
+				sadd.query_async(&mut con).await?
+			} else {
+				let (res, ): (u8, ) = pipe.add_command(sadd).query_async(&mut con).await?;
+				res
+			};
+			Ok(res)
+		})
+		.await
 	}
 
+	#[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all, fields(ns = %self.name_space, qname = %qname, round_trips = Empty, outcome = Empty)))]
 	pub async fn delete_queue(&self, qname: &str) -> Result<Value, Error> {
-		let con = self.pool.get()
-			.await?
-			.as_mut()
-			.ok_or_else(|| RedisError::from((RedisErrorKind::IoError, "Unable to acquire connection")))?;
-		let key = self.message_zset_key(qname);
-		redis::pipe()
-			.atomic()
-			.cmd("DEL").arg(format!("{}:Q", &key)).ignore() // The queue hash
-			.cmd("DEL").arg(&key).ignore() // The messages zset
-			.cmd("SREM").arg(format!("{}:QUEUES", self.name_space)).arg(qname).ignore()
-			.query_async(con)
-			.await
-			.map_err(|e| e.into())
+		self.observe(None, async {
+			let mut con = self.connection().await?;
+			let mut pipe = redis::pipe();
+			pipe.atomic()
+				.cmd("DEL").arg(self.queue_hash_key(qname)).ignore()
+				.cmd("DEL").arg(self.message_zset_key(qname)).ignore()
+				.cmd("DEL").arg(idempotent::processed_key(&self.message_zset_key(qname))).ignore();
+			let mut srem = redis::cmd("SREM");
+			srem.arg(self.queues_key()).arg(qname);
+			if self.is_cluster() {
+				let res = pipe.query_async(&mut con).await?;
+				srem.query_async::<_, ()>(&mut con).await?;
+				Ok(res)
+			} else {
+				pipe.add_command(srem).ignore().query_async(&mut con).await.map_err(|e| e.into())
+			}
+		})
+		.await
 	}
 
+	/// Delete all messages of a queue but keep the queue and its attributes. Returns the number of deleted messages.
+	#[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all, fields(ns = %self.name_space, qname = %qname, purged = Empty, round_trips = Empty, outcome = Empty)))]
+	pub async fn purge_queue(&self, qname: &str) -> Result<u64, Error> {
+		self.observe(None, async {
+			let mut con = self.connection().await?;
+			let purged: u64 = self.scripts.purge_queue
+				.key(self.message_zset_key(qname))
+				.key(self.queue_hash_key(qname))
+				.invoke_async(&mut con)
+				.await?;
+			trace_record!("purged", purged);
+			Ok(purged)
+		})
+		.await
+	}
+
+	#[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all, fields(ns = %self.name_space, round_trips = Empty, outcome = Empty)))]
 	pub async fn list_queues(&self) -> Result<Vec<String>, Error> {
-		let con = self.pool.get()
-			.await?
-			.as_mut()
-			.ok_or_else(|| RedisError::from((RedisErrorKind::IoError, "Unable to acquire connection")))?;
-		let key = format!("{}:QUEUES", self.name_space);
-		redis::cmd("SMEMBERS")
-			.arg(key)
-			.query_async(con)
-			.await
-			.map_err(|e| e.into())
-	}
-
-	async fn get_queue(&self, qname: &str, set_uid: bool) -> Result<(Queue, u64, Option<String>), Error> {
-		let con = self.pool.get()
-			.await?
-			.as_mut()
-			.ok_or_else(|| RedisError::from((RedisErrorKind::IoError, "Unable to acquire connection")))?;
-		let qkey = self.queue_hash_key(qname);
-		let ((vt, delay, maxsize), (secs, micros)): ((u64, u64, i64), (u64, u64)) = redis::pipe()
-			.atomic()
-			.cmd("HMGET").arg(qkey).arg("vt").arg("delay").arg("maxsize")
-			.cmd("TIME")
-			.query_async(con)
+		self.observe(None, async {
+			let mut con = self.connection().await?;
+			redis::cmd("SMEMBERS")
+				.arg(self.queues_key())
+				.query_async(&mut con)
+				.await
+				.map_err(|e| e.into())
+		})
+		.await
+	}
+
+	#[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all, fields(ns = %self.name_space, qname = %qname, id = msgid, vt = hidefor, round_trips = Empty, outcome = Empty)))]
+	pub async fn change_message_visibility(&self, qname: &str, msgid: &str, hidefor: u64) -> Result<u64, Error> {
+		self.observe(None, self.change_visibility(&self.queue_keys(qname), msgid, hidefor)).await
+	}
+
+	pub(crate) async fn change_visibility(&self, keys: &QueueKeys, msgid: &str, hidefor: u64) -> Result<u64, Error> {
+		let mut con = self.connection().await?;
+		let expires_at = self.scripts.change_message_visibility
+			.key(&keys.zset)
+			.key(&keys.hash)
+			.arg(msgid)
+			.arg(hidefor)
+			.invoke_async(&mut con)
 			.await?;
+		Ok(expires_at)
+	}
 
-		let ts_micros = secs * 1_000_000 + micros;
-		let ts = ts_micros / 1_000; // Epoch time in milliseconds
-		let q = Queue {
-			qname: qname.into(),
-			vt,
-			delay,
-			maxsize,
-			..Default::default()
-		};
-		// This is a bit crazy. The JS version calls getQueue with the `set_uid` set to `true` only from `sendMessage`
-		// where it is used to write the timestamp+random stuff that constituates the (sort)key. This is just a port of
-		// that behavior. I don't understand why it is baked in with the queue attrib fetch.
-		let uid = if set_uid {
-			let ts_rad36 = radix::RadixNum::from(ts_micros).with_radix(36).unwrap().as_str().to_lowercase().to_string();
-			// TODO: make this work
-			// let ts_rad36 = radix::RadixNum::from(ts_micros).with_radix(36)?.as_str().to_lowercase().to_string();
-			Some(ts_rad36 + &make_id_22())
-		} else {
-			None
-		};
-		Ok((q, ts, uid))
+	/// Give up on processing a received message for now and retry it after `backoff`, by its receive count. Returns
+	/// when it becomes visible, in milliseconds.
+	#[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all, fields(ns = %self.name_space, qname = %qname, id = msgid, backoff = ?backoff, round_trips = Empty, outcome = Empty)))]
+	pub async fn nack_message(&self, qname: &str, msgid: &str, backoff: &Backoff) -> Result<u64, Error> {
+		self.observe(None, self.nack(&self.queue_keys(qname), msgid, backoff)).await
 	}
 
-	pub async fn change_message_visibility(&self, qname: &str, msgid: &str, hidefor: u64) -> Result<u64, Error> {
-		const LUA: &'static str = r#"
-            local msg = redis.call("ZSCORE", KEYS[1], KEYS[2])
-			if not msg then
-				return 0
-			end
-			redis.call("ZADD", KEYS[1], KEYS[3], KEYS[2])
-			return 1"#;
-		let (_, ts, _) = self.get_queue(&qname, false).await?;
-		let key = self.message_zset_key(qname);
-		let expires_at = ts + hidefor * 1000u64;
-		let con = self.pool.get()
-			.await?
-			.as_mut()
-			.ok_or_else(|| RedisError::from((RedisErrorKind::IoError, "Unable to acquire connection")))?;
-		redis::Script::new(LUA)
-			.key(key)
-			.key(msgid)
-			.key(expires_at)
-			.invoke_async::<_, ()>(con)
+	// The receive count is read by the script, so the delay is right even if the message was received again meanwhile.
+	pub(crate) async fn nack(&self, keys: &QueueKeys, msgid: &str, backoff: &Backoff) -> Result<u64, Error> {
+		let (initial, max, factor, jitter) = backoff.script_args();
+		let mut con = self.connection().await?;
+		let expires_at = self.scripts.nack_message
+			.key(&keys.zset)
+			.key(&keys.hash)
+			.arg(msgid)
+			.arg(initial.to_string())
+			.arg(max.to_string())
+			.arg(factor)
+			.arg(if jitter { util::random_fraction().to_string() } else { String::new() })
+			.invoke_async(&mut con)
 			.await?;
 		Ok(expires_at)
 	}
 
+	#[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all, fields(ns = %self.name_space, qname = %qname, delay = ?delay, size = message.len(), id = Empty, round_trips = Empty, outcome = Empty)))]
 	pub async fn send_message(&self, qname: &str, message: &str, delay: Option<u64>) -> Result<String, Error> {
-		let (q, ts, uid) = self.get_queue(&qname, true).await?;
-		let uid = uid.ok_or(format_err!("Did not get a proper uid back from Redis"))?;
-		let delay = delay.unwrap_or(q.delay);
-
-		if q.maxsize != -1 && message.as_bytes().len() > q.maxsize as usize {
-			let custom_error = std::io::Error::new(std::io::ErrorKind::Other, "Message is too long");
-			let redis_err = RedisError::from(custom_error);
-			return Err(redis_err.into());
+		self.observe(Some(Operation::Send), self.send_with_headers(qname, message, delay, &Headers::new())).await
+	}
+
+	/// Send a message with `headers`, e.g. a [`TraceContext`]. Receiving clients other than `Rsmq` see the headers as
+	/// part of the message.
+	#[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all, fields(ns = %self.name_space, qname = %qname, delay = ?delay, size = message.len(), id = Empty, round_trips = Empty, outcome = Empty)))]
+	pub async fn send_message_with_headers(&self, qname: &str, message: &str, delay: Option<u64>, headers: &Headers) -> Result<String, Error> {
+		self.observe(Some(Operation::Send), self.send_with_headers(qname, message, delay, headers)).await
+	}
+
+	async fn send_with_headers(&self, qname: &str, message: &str, delay: Option<u64>, headers: &Headers) -> Result<String, Error> {
+		envelope::check_reserved(headers)?;
+		self.send(&self.queue_keys(qname), message, delay, headers).await
+	}
+
+	/// Send `value` encoded with `codec`, to be read with [`Message::decode`].
+	#[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all, fields(ns = %self.name_space, qname = %qname, codec = codec.id(), delay = ?delay, id = Empty, round_trips = Empty, outcome = Empty)))]
+	pub async fn send_encoded<C: Codec, T: Serialize + ?Sized>(&self, qname: &str, codec: &C, value: &T, delay: Option<u64>) -> Result<String, Error> {
+		self.observe(Some(Operation::Send), self.send_value(&self.queue_keys(qname), codec, value, delay)).await
+	}
+
+	pub(crate) async fn send_value<C: Codec, T: Serialize + ?Sized>(&self, keys: &QueueKeys, codec: &C, value: &T, delay: Option<u64>) -> Result<String, Error> {
+		let mut headers = Headers::new();
+		headers.insert(envelope::CODEC.into(), codec.id().into());
+		self.send(keys, &codec::encode(codec, value)?, delay, &headers).await
+	}
+
+	// Send a message whose `headers` may be reserved ones.
+	async fn send(&self, keys: &QueueKeys, message: &str, delay: Option<u64>, headers: &Headers) -> Result<String, Error> {
+		let message = self.seal(&keys.qname, message, headers).await?;
+		let sent: Result<String, Error> = async {
+			let mut con = self.connection().await?;
+			Ok(self.scripts.send_message
+				.key(&keys.zset)
+				.key(&keys.hash)
+				.arg(&message)
+				.arg(delay.map(|d| d.to_string()).unwrap_or_default())
+				.arg(&keys.realtime)
+				.arg(util::random_alphanumerics(22))
+				.invoke_async(&mut con)
+				.await?)
 		}
-		let key = self.message_zset_key(qname);
-		let qky = self.queue_hash_key(qname);
-		let con = self.pool.get()
-			.await?
-			.as_mut()
-			.ok_or_else(|| RedisError::from((RedisErrorKind::IoError, "Unable to acquire connection")))?;
-		redis::pipe().atomic()
-			.cmd("ZADD").arg(&key).arg(ts + delay * 1000).arg(&uid).ignore()
-			.cmd("HSET").arg(&qky).arg(&uid).arg(message).ignore()
-			.cmd("HINCRBY").arg(&qky).arg("totalsent").arg(1).ignore()
-			.query_async::<_, ()>(con)
-			.await?;
+		.await;
+		if sent.is_err() {
+			if let Some(reference) = claim_check_reference(&message) {
+				self.delete_blob(&reference).await;
+			}
+		}
+		let uid = sent?;
+		trace_record!("id", uid.as_str());
 		Ok(uid)
 	}
 
+	#[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all, fields(ns = %self.name_space, qname = %qname, id = msgid, round_trips = Empty, outcome = Empty)))]
 	pub async fn delete_message(&self, qname: &str, msgid: &str) -> Result<bool, Error> {
-		let key = self.message_zset_key(qname);
-		let con = self.pool.get()
-			.await?
-			.as_mut()
-			.ok_or_else(|| RedisError::from((RedisErrorKind::IoError, "Unable to acquire connection")))?;
-		let (delete_count, deleted_fields_count): (u32, u32) = redis::pipe()
-			.atomic()
-			.cmd("ZREM")
-			.arg(&key)
+		self.observe(Some(Operation::Delete), self.delete(&self.queue_keys(qname), msgid)).await
+	}
+
+	pub(crate) async fn delete(&self, keys: &QueueKeys, msgid: &str) -> Result<bool, Error> {
+		let mut con = self.connection().await?;
+		let mut pipe = redis::pipe();
+		pipe.atomic();
+		// With claim-check the body tells whether there's a blob to delete too.
+		if self.claim_check.is_some() {
+			pipe.cmd("HGET").arg(&keys.hash).arg(msgid);
+		}
+		pipe.cmd("ZREM")
+			.arg(&keys.zset)
 			.arg(msgid)
 			.cmd("HDEL")
-			.arg(format!("{}:Q", &key))
+			.arg(&keys.hash)
 			.arg(msgid)
-			.arg(format!("{}:rc", &key))
-			.arg(format!("{}:fr", &key))
-			.query_async(con)
-			.await?;
+			.arg(format!("{}:rc", msgid))
+			.arg(format!("{}:fr", msgid));
+		let (body, delete_count, deleted_fields_count): (Option<String>, u32, u32) = if self.claim_check.is_some() {
+			pipe.query_async(&mut con).await?
+		} else {
+			let (delete_count, deleted_fields_count) = pipe.query_async(&mut con).await?;
+			(None, delete_count, deleted_fields_count)
+		};
 
 		if delete_count == 1 && deleted_fields_count > 0 {
+			if let Some(reference) = body.as_deref().and_then(claim_check_reference) {
+				self.delete_blob(&reference).await;
+			}
 			Ok(true)
 		} else {
 			Ok(false)
 		}
 	}
 
+	#[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all, fields(ns = %self.name_space, qname = %qname, id = Empty, rc = Empty, round_trips = Empty, outcome = Empty)))]
 	pub async fn pop_message(&self, qname: &str) -> Result<Message, Error> {
-		const LUA: &'static str = r##"
-      local msg = redis.call("ZRANGEBYSCORE", KEYS[1], "-inf", KEYS[2], "LIMIT", "0", "1")
-			if #msg == 0 then
-				return {}
-			end
-			redis.call("HINCRBY", KEYS[1] .. ":Q", "totalrecv", 1)
-			local mbody = redis.call("HGET", KEYS[1] .. ":Q", msg[1])
-			local rc = redis.call("HINCRBY", KEYS[1] .. ":Q", msg[1] .. ":rc", 1)
-			local o = {msg[1], mbody, rc}
-			if rc==1 then
-				table.insert(o, KEYS[2])
-			else			
-				local fr = redis.call("HGET", KEYS[1] .. ":Q", msg[1] .. ":fr")	
-				table.insert(o, fr)
-			end
-			redis.call("ZREM", KEYS[1], msg[1])
-			redis.call("HDEL", KEYS[1] .. ":Q", msg[1], msg[1] .. ":rc", msg[1] .. ":fr")
-			return o    
-    "##;
-		let (_, ts, _) = self.get_queue(qname, false).await?;
-		let key = self.message_zset_key(qname);
-		let con = self.pool.get()
-			.await?
-			.as_mut()
-			.ok_or_else(|| RedisError::from((RedisErrorKind::IoError, "Unable to acquire connection")))?;
-		let m: Message = redis::Script::new(LUA)
-			.key(key)
-			.key(ts)
-			.invoke_async(con)
-			.await?;
-		Ok(m)
+		self.observe(Some(Operation::Pop), self.pop(&self.queue_keys(qname))).await
+	}
+
+	pub(crate) async fn pop(&self, keys: &QueueKeys) -> Result<Message, Error> {
+		let mut con = self.connection().await?;
+		loop {
+			let m: Message = self.scripts.pop_message
+				.key(&keys.zset)
+				.key(&keys.hash)
+				.invoke_async(&mut con)
+				.await?;
+			trace_record!("id", m.id.as_str());
+			trace_record!("rc", m.rc);
+			if let Some(m) = self.open_or_quarantine(&mut con, keys, m, true).await? {
+				return Ok(m);
+			}
+		}
 	}
 
+	#[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all, fields(ns = %self.name_space, qname = %qname, vt = ?hidefor, id = Empty, rc = Empty, round_trips = Empty, outcome = Empty)))]
 	pub async fn receive_message(&self, qname: &str, hidefor: Option<u64>) -> Result<Message, Error> {
-		const LUA: &'static str = r##"
-      local msg = redis.call("ZRANGEBYSCORE", KEYS[1], "-inf", KEYS[2], "LIMIT", "0", "1")
-			if #msg == 0 then
-				return {}
-			end
-			redis.call("ZADD", KEYS[1], KEYS[3], msg[1])
-			redis.call("HINCRBY", KEYS[1] .. ":Q", "totalrecv", 1)
-			local mbody = redis.call("HGET", KEYS[1] .. ":Q", msg[1])
-			local rc = redis.call("HINCRBY", KEYS[1] .. ":Q", msg[1] .. ":rc", 1)
-			local o = {msg[1], mbody, rc}
-			if rc==1 then
-				redis.call("HSET", KEYS[1] .. ":Q", msg[1] .. ":fr", KEYS[2])
-				table.insert(o, KEYS[2])
-			else
-				local fr = redis.call("HGET", KEYS[1] .. ":Q", msg[1] .. ":fr")
-				table.insert(o, fr)
-			end
-			return o
-      "##;
-		let (q, ts, _) = self.get_queue(&qname, false).await?;
-		let hidefor = hidefor.unwrap_or(q.vt);
-		let key = self.message_zset_key(qname);
-		let expires_at = ts + hidefor * 1000u64;
-		let con = self.pool.get()
-			.await?
-			.as_mut()
-			.ok_or_else(|| RedisError::from((RedisErrorKind::IoError, "Unable to acquire connection")))?;
-
-		let m: Message = redis::Script::new(LUA)
-			.key(key)
-			.key(ts)
-			.key(expires_at)
-			.invoke_async(con)
-			.await?;
-		Ok(m)
+		self.observe(Some(Operation::Receive), self.receive(&self.queue_keys(qname), hidefor)).await
+	}
+
+	pub(crate) async fn receive(&self, keys: &QueueKeys, hidefor: Option<u64>) -> Result<Message, Error> {
+		let mut con = self.connection().await?;
+		loop {
+			let m: Message = self.scripts.receive_message
+				.key(&keys.zset)
+				.key(&keys.hash)
+				.arg(hidefor.map(|h| h.to_string()).unwrap_or_default())
+				.invoke_async(&mut con)
+				.await?;
+			trace_record!("id", m.id.as_str());
+			trace_record!("rc", m.rc);
+			if let Some(m) = self.open_or_quarantine(&mut con, keys, m, false).await? {
+				return Ok(m);
+			}
+		}
 	}
 
+	#[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all, fields(ns = %self.name_space, qname = %qname, round_trips = Empty, outcome = Empty)))]
 	pub async fn get_queue_attributes(&self, qname: &str) -> Result<Queue, Error> {
-		// TODO: validate qname
-		let con = self.pool.get()
-			.await?
-			.as_mut()
-			.ok_or_else(|| RedisError::from((RedisErrorKind::IoError, "Unable to acquire connection")))?;
-		let key = self.message_zset_key(qname);
-		let qkey = self.queue_hash_key(qname);
-		// TODO: use transaction here to grab the time and then run the data fetch
-		let (time, _): (String, u32) = redis::cmd("TIME")
-			.query_async(con)
-			.await?;
-		let ts_str = format!("{}000", time);
-		// [[60, 10, 1200, 5, 7, 1512492628, 1512492628], 10, 9]
-		let out: ((u64, u64, i64, u64, u64, u64, u64), u64, u64) = redis::pipe().atomic()
-			.cmd("HMGET")
-				.arg(qkey)
-				.arg("vt")
-				.arg("delay")
-				.arg("maxsize")
-				.arg("totalrecv")
-				.arg("totalsent")
-				.arg("created")
-				.arg("modified")
-			.cmd("ZCARD")
-				.arg(&key)
-			.cmd("ZCOUNT")
-				.arg(&key)
-				.arg(ts_str)
-				.arg("+inf")
-			.query_async(con)
-			.await?;
+		self.observe(None, async {
+			// TODO: validate qname
+			let mut con = self.connection().await?;
+			let key = self.message_zset_key(qname);
+			let qkey = self.queue_hash_key(qname);
+			// TODO: use transaction here to grab the time and then run the data fetch
+			let (time, _): (String, u32) = redis::cmd("TIME")
+				.query_async(&mut con)
+				.await?;
+			let ts_str = format!("{}000", time);
+			// [[60, 10, 1200, 5, 7, 1512492628, 1512492628], 10, 9]
+			let (attrs, msgs, hiddenmsgs): (Vec<Option<i64>>, u64, u64) = redis::pipe().atomic()
+				.cmd("HMGET")
+					.arg(qkey)
+					.arg("vt")
+					.arg("delay")
+					.arg("maxsize")
+					.arg("totalrecv")
+					.arg("totalsent")
+					.arg("created")
+					.arg("modified")
+				.cmd("ZCARD")
+					.arg(&key)
+				.cmd("ZCOUNT")
+					.arg(&key)
+					.arg(ts_str)
+					.arg("+inf")
+				.query_async(&mut con)
+				.await?;
 
-		let (vt, delay, maxsize, totalrecv, totalsent, created, modified) = out.0;
-		let msgs = out.1;
-		let hiddenmsgs = out.2;
-		let q = Queue {
-			qname: qname.into(),
-			vt,
-			delay,
-			maxsize,
-			totalrecv,
-			totalsent,
-			created,
-			modified,
-			msgs,
-			hiddenmsgs,
-		};
-		Ok(q)
+			let attrs = attrs.into_iter().collect::<Option<Vec<i64>>>().ok_or_else(|| script_error("Queue not found"))?;
+			let q = Queue {
+				qname: qname.into(),
+				vt: attrs[0] as u64,
+				delay: attrs[1] as u64,
+				maxsize: attrs[2],
+				totalrecv: attrs[3] as u64,
+				totalsent: attrs[4] as u64,
+				created: attrs[5] as u64,
+				modified: attrs[6] as u64,
+				msgs,
+				hiddenmsgs,
+			};
+			Ok(q)
+		})
+		.await
 	}
 
+	#[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all, fields(ns = %self.name_space, qname = %qname, vt = ?vt, delay = ?delay, maxsize = ?maxsize, round_trips = Empty, outcome = Empty)))]
 	pub async fn set_queue_attributes(
 		&self,
 		qname: &str,
@@ -415,39 +862,255 @@ impl Rsmq {
 		delay: Option<u64>,
 		maxsize: Option<i64>,
 	) -> Result<Queue, Error> {
-		let con = self.pool.get()
-			.await?
-			.as_mut()
-			.ok_or_else(|| RedisError::from((RedisErrorKind::IoError, "Unable to acquire connection")))?;
-		let qkey = self.queue_hash_key(qname);
-		let mut pipe = redis::pipe();
-		if vt.is_some() {
-			pipe.cmd("HSET").arg(&qkey).arg("vt").arg(vt).ignore();
+		self.observe(None, async {
+			let mut con = self.connection().await?;
+			let qkey = self.queue_hash_key(qname);
+			let mut pipe = redis::pipe();
+			if vt.is_some() {
+				pipe.cmd("HSET").arg(&qkey).arg("vt").arg(vt).ignore();
+			}
+			if delay.is_some() {
+				pipe.cmd("HSET").arg(&qkey).arg("delay").arg(delay).ignore();
+			}
+			if maxsize.is_some() {
+				pipe.cmd("HSET").arg(&qkey).arg("maxsize").arg(maxsize).ignore();
+			}
+			pipe.atomic().query_async::<_, ()>(&mut con).await?;
+			let q = self.get_queue_attributes(qname).await?;
+			Ok(q)
+		})
+		.await
+	}
+
+	async fn connection(&self) -> Result<Connection<'_>, Error> {
+		let mut attempt = 0;
+		loop {
+			let start = Instant::now();
+			let res = self.pool.get().await;
+			if let Some(metrics) = &self.metrics {
+				metrics.observe_pool_wait(start.elapsed());
+			}
+			match res {
+				Err(_) if attempt < self.retry_policy.max_retries => {
+					tokio::time::delay_for(self.retry_policy.backoff.delay(attempt)).await;
+					attempt += 1;
+				}
+				res => return res,
+			}
+		}
+	}
+
+	// The body to store for a message sent to `qname` with the application's `headers`.
+	async fn seal(&self, qname: &str, body: &str, headers: &Headers) -> Result<String, Error> {
+		let mut headers = headers.clone();
+		let mut bytes = Cow::Borrowed(body.as_bytes());
+		if let Some((compression, threshold)) = self.compression {
+			if body.len() > threshold {
+				let (compressed, encoding) = compression.compress(&bytes)?;
+				// Only worth it if the result is shorter once base64 encoded.
+				if compressed.len().div_ceil(3) * 4 < body.len() {
+					headers.insert(envelope::CONTENT_ENCODING.into(), encoding.into());
+					bytes = Cow::Owned(compressed);
+				}
+			}
+		}
+		if let Some((encrypted, key_id)) = self.encryption.as_ref().map(|e| e.encrypt(qname, &bytes)).transpose()?.flatten() {
+			headers.insert(envelope::KEY_ID.into(), key_id.into());
+			bytes = Cow::Owned(encrypted);
+		}
+		#[cfg(feature = "signing")]
+		if let Some(signature) = self.signing.as_ref().and_then(|s| s.sign(qname, &headers, &bytes)) {
+			headers.insert(envelope::SIGNATURE.into(), signature);
+		}
+		if let Some((store, threshold)) = &self.claim_check {
+			let len = match &bytes {
+				Cow::Borrowed(_) => body.len(),
+				Cow::Owned(bytes) => bytes.len().div_ceil(3) * 4,
+			};
+			if len > *threshold {
+				headers.insert(envelope::CLAIM_CHECK.into(), store.put(&bytes).await?);
+				return envelope::encode(&headers, "");
+			}
+		}
+		match bytes {
+			Cow::Borrowed(_) => envelope::encode(&headers, body),
+			Cow::Owned(bytes) => envelope::encode(&headers, &envelope::base64_encode(&bytes)),
+		}
+	}
+
+	// Undo `seal` for a received message of `qname`, deleting its blob with `delete_blob` once it's verified.
+	#[cfg_attr(not(feature = "signing"), allow(unused_variables))]
+	async fn open(&self, qname: &str, mut m: Message, delete_blob: bool) -> Result<Message, Error> {
+		if let Some((headers, body)) = envelope::decode(&m.message) {
+			m.message = body.into();
+			m.headers = headers;
+		}
+		let signature = m.headers.remove(envelope::SIGNATURE);
+		let reference = m.headers.remove(envelope::CLAIM_CHECK);
+		let transformed = m.headers.contains_key(envelope::KEY_ID) || m.headers.contains_key(envelope::CONTENT_ENCODING);
+		let bytes = match &reference {
+			Some(reference) => {
+				let (store, _) = self.claim_check.as_ref().ok_or_else(|| format_err!("Message body is in blob {:?}, but no blob store is configured", reference))?;
+				Cow::Owned(store.get(reference).await?)
+			}
+			None if transformed => Cow::Owned(envelope::base64_decode(&m.message)?),
+			None => Cow::Borrowed(m.message.as_bytes()),
+		};
+		#[cfg(feature = "signing")]
+		if let Some(signing) = &self.signing {
+			let verified = if self.dead_letter_queue.as_deref() == Some(qname) {
+				signing.verify_dead_lettered(qname, &m.headers, signature.as_deref(), &bytes)
+			} else {
+				signing.verify(qname, &m.headers, signature.as_deref(), &bytes)
+			};
+			verified.map_err(|reason| InvalidSignature(format!("Message {} of queue {:?} {}", m.id, qname, reason)))?;
+		}
+		if let (Some(reference), true) = (&reference, delete_blob) {
+			self.delete_blob(reference).await;
+		}
+		let key_id = m.headers.remove(envelope::KEY_ID);
+		let encoding = m.headers.remove(envelope::CONTENT_ENCODING);
+		let mut bytes = match bytes {
+			Cow::Borrowed(_) => return Ok(m),
+			Cow::Owned(bytes) => bytes,
+		};
+		if let Some(key_id) = key_id {
+			let encryption = self.encryption.as_ref().ok_or_else(|| format_err!("Message is encrypted with key {:?}, but no keys are configured", key_id))?;
+			bytes = encryption.decrypt(&key_id, &bytes)?;
 		}
-		if delay.is_some() {
-			pipe.cmd("HSET").arg(&qkey).arg("delay").arg(delay).ignore();
+		if let Some(encoding) = encoding {
+			bytes = compression::decompress(&encoding, &bytes)?;
 		}
-		if maxsize.is_some() {
-			pipe.cmd("HSET").arg(&qkey).arg("maxsize").arg(maxsize).ignore();
+		m.message = String::from_utf8(bytes)?;
+		Ok(m)
+	}
+
+	// Open a received message, or move it to the quarantine queue if it fails verification and there is one. `None` if
+	// it was moved. Popped messages are already gone from `qname`.
+	#[cfg(feature = "signing")]
+	async fn open_or_quarantine(&self, con: &mut Connection<'_>, keys: &QueueKeys, m: Message, popped: bool) -> Result<Option<Message>, Error> {
+		let quarantine = match self.signing.as_ref().and_then(|s| s.quarantine_queue()) {
+			Some(quarantine) => self.queue_keys(quarantine),
+			None => return self.open(&keys.qname, m, popped).await.map(Some),
+		};
+		let (id, raw) = (m.id.clone(), m.message.clone());
+		let e = match self.open(&keys.qname, m, popped).await {
+			Err(e) if e.downcast_ref::<InvalidSignature>().is_some() => e,
+			res => return res.map(Some),
+		};
+		let from = if popped { None } else { Some((keys, id.as_str())) };
+		self.move_message(con, &raw, &quarantine, (envelope::QUARANTINE_REASON, e.to_string()), from).await?;
+		#[cfg(feature = "tracing")]
+		tracing::warn!(error = %e, quarantine = %quarantine.qname, "message quarantined");
+		Ok(None)
+	}
+
+	#[cfg(not(feature = "signing"))]
+	async fn open_or_quarantine(&self, _con: &mut Connection<'_>, keys: &QueueKeys, m: Message, popped: bool) -> Result<Option<Message>, Error> {
+		self.open(&keys.qname, m, popped).await.map(Some)
+	}
+
+	// Move the message `msgid` of queue `keys` to the dead letter queue, `false` if it's gone.
+	pub(crate) async fn dead_letter(&self, keys: &QueueKeys, msgid: &str) -> Result<bool, Error> {
+		let dead_letter_queue = self.dead_letter_queue.as_deref().ok_or_else(|| format_err!("No dead letter queue is configured"))?;
+		let mut con = self.connection().await?;
+		let raw: Option<String> = redis::cmd("HGET").arg(&keys.hash).arg(msgid).query_async(&mut con).await?;
+		match raw {
+			Some(raw) => {
+				let from = Some((keys, msgid));
+				self.move_message(&mut con, &raw, &self.queue_keys(dead_letter_queue), (envelope::DEAD_LETTERED_FROM, keys.qname.clone()), from).await?;
+				Ok(true)
+			}
+			None => Ok(false),
 		}
-		pipe.atomic().query_async::<_, ()>(con).await?;
-		let q = self.get_queue_attributes(qname).await?;
-		Ok(q)
+	}
+
+	// Send the stored body `raw` to the queue `to` with the reserved `header` added, then delete the message `from`,
+	// unless it was popped. A failure in between leaves the message in both queues rather than in none. Its blob moves
+	// with it.
+	async fn move_message(&self, con: &mut Connection<'_>, raw: &str, to: &QueueKeys, header: (&str, String), from: Option<(&QueueKeys, &str)>) -> Result<(), Error> {
+		let (mut headers, body) = envelope::decode(raw).unwrap_or((Headers::new(), raw));
+		headers.insert(header.0.into(), header.1);
+		let _: String = self.scripts.send_message
+			.key(&to.zset)
+			.key(&to.hash)
+			.arg(envelope::encode(&headers, body)?)
+			.arg("")
+			.arg(&to.realtime)
+			.arg(util::random_alphanumerics(22))
+			.invoke_async(&mut *con)
+			.await?;
+		if let Some((keys, msgid)) = from {
+			let _: (u32, u32) = redis::pipe()
+				.atomic()
+				.cmd("ZREM")
+				.arg(&keys.zset)
+				.arg(msgid)
+				.cmd("HDEL")
+				.arg(&keys.hash)
+				.arg(msgid)
+				.arg(format!("{}:rc", msgid))
+				.arg(format!("{}:fr", msgid))
+				.query_async(&mut *con)
+				.await?;
+		}
+		Ok(())
+	}
+
+	// Delete a blob of a message that is gone. A failure leaves the blob behind rather than failing the operation.
+	async fn delete_blob(&self, reference: &str) {
+		if let Some((store, _)) = &self.claim_check {
+			#[allow(unused_variables)]
+			if let Err(e) = store.delete(reference).await {
+				#[cfg(feature = "tracing")]
+				tracing::warn!(reference, error = %e, "deleting the blob of a message failed");
+			}
+		}
+	}
+
+	// Run the body of a public method, recording its round trips and outcome in its tracing span and, for `op`, in the
+	// metrics.
+	pub(crate) async fn observe<T, F: Future<Output = Result<T, Error>>>(&self, op: Option<Operation>, f: F) -> Result<T, Error> {
+		let start = Instant::now();
+		let res = connection::count_round_trips(f).await;
+		if let (Some(op), Some(metrics)) = (op, &self.metrics) {
+			metrics.observe(op, start.elapsed(), &res);
+		}
+		#[cfg(feature = "tracing")]
+		{
+			let span = tracing::Span::current();
+			match &res {
+				Ok(_) => span.record("outcome", "ok"),
+				Err(e) if is_no_messages(e) => span.record("outcome", "empty"),
+				Err(e) => {
+					tracing::debug!(error = %e, "operation failed");
+					span.record("outcome", "error")
+				}
+			};
+		}
+		res
+	}
+
+	fn is_cluster(&self) -> bool { matches!(self.pool, RedisPool::Cluster(_)) }
+
+	fn queues_key(&self) -> String {
+		format!("{}:QUEUES", self.name_space)
 	}
 
 	fn queue_hash_key(&self, qname: &str) -> String {
-		format!("{}:{}:Q", self.name_space, qname)
+		self.key_layout.queue_hash_key(&self.name_space, qname)
 	}
 
-	fn message_zset_key(&self, qname: &str) -> String {
-		format!("{}:{}", self.name_space, qname)
+	pub(crate) fn queue_keys(&self, qname: &str) -> QueueKeys {
+		QueueKeys {
+			qname: qname.into(),
+			zset: self.message_zset_key(qname),
+			hash: self.queue_hash_key(qname),
+			realtime: if self.realtime { format!("{}:rt:{}", self.name_space, qname) } else { String::new() },
+		}
 	}
-}
 
-fn make_id_22() -> String {
-	use rand::{Rng, distributions::Alphanumeric};
-	rand::thread_rng()
-		.sample_iter(&Alphanumeric)
-		.take(22)
-		.collect::<String>()
+	fn message_zset_key(&self, qname: &str) -> String {
+		self.key_layout.message_zset_key(&self.name_space, qname)
+	}
 }
@@ -0,0 +1,217 @@
+use async_trait::async_trait;
+use failure::Error;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::util::{message_id, now_us};
+use crate::{no_messages, script_error, Headers, Message, Queue, QueueBackend};
+
+/// A [`QueueBackend`] that keeps its queues in memory, for tests of code using queues that should run without Redis.
+///
+/// It behaves like the Redis backend down to the receive counts and the queue statistics, and its message ids have the
+/// same format. Its clock is
+/// the system clock plus an offset that [`MemoryBackend::advance_time`] moves forward, so tests can let visibility
+/// timeouts and delays expire without sleeping.
+#[derive(Debug, Default)]
+pub struct MemoryBackend {
+	state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+	queues: BTreeMap<String, MemoryQueue>,
+	offset: Duration,
+}
+
+#[derive(Debug)]
+struct MemoryQueue {
+	vt: u64,
+	delay: u64,
+	maxsize: i64,
+	totalrecv: u64,
+	totalsent: u64,
+	created: u64,
+	modified: u64,
+	lastid: u64,
+	// (visible from, in ms, id), ordered like the messages zset of the Redis backend
+	schedule: BTreeSet<(u64, String)>,
+	messages: HashMap<String, StoredMessage>,
+}
+
+#[derive(Debug)]
+struct StoredMessage {
+	body: String,
+	visible_at: u64,
+	sent: u64,
+	rc: u64,
+	fr: u64,
+}
+
+impl MemoryBackend {
+	pub fn new() -> MemoryBackend { MemoryBackend::default() }
+
+	/// Move the clock of the backend forward by `by`.
+	pub fn advance_time(&self, by: Duration) { self.state.lock().unwrap().offset += by; }
+}
+
+impl State {
+	fn now_us(&self) -> u64 { now_us() + self.offset.as_micros() as u64 }
+
+	fn queue(&mut self, qname: &str) -> Result<&mut MemoryQueue, Error> { self.queues.get_mut(qname).ok_or_else(|| script_error("Queue not found").into()) }
+}
+
+impl MemoryQueue {
+	// The first visible message, if any.
+	fn next_visible(&self, now: u64) -> Option<String> { self.schedule.iter().next().filter(|(at, _)| *at <= now).map(|(_, id)| id.clone()) }
+
+	fn reschedule(&mut self, msgid: &str, visible_at: u64) {
+		if let Some(msg) = self.messages.get_mut(msgid) {
+			self.schedule.remove(&(msg.visible_at, msgid.to_string()));
+			self.schedule.insert((visible_at, msgid.to_string()));
+			msg.visible_at = visible_at;
+		}
+	}
+
+	fn remove(&mut self, msgid: &str) -> Option<StoredMessage> {
+		let msg = self.messages.remove(msgid)?;
+		self.schedule.remove(&(msg.visible_at, msgid.to_string()));
+		Some(msg)
+	}
+
+	// Count a receive of `msgid` and return it as the scripts of the Redis backend do.
+	fn receive(&mut self, msgid: &str, now: u64) -> Message {
+		self.totalrecv += 1;
+		let msg = self.messages.get_mut(msgid).expect("scheduled messages are stored");
+		msg.rc += 1;
+		if msg.rc == 1 {
+			msg.fr = now;
+		}
+		Message { id: msgid.into(), message: msg.body.clone(), rc: msg.rc, fr: msg.fr, sent: msg.sent, headers: Headers::new() }
+	}
+}
+
+#[async_trait]
+impl QueueBackend for MemoryBackend {
+	async fn create_queue(&self, opts: Queue) -> Result<u8, Error> {
+		let mut state = self.state.lock().unwrap();
+		let ts = state.now_us() / 1_000_000;
+		if state.queues.contains_key(&opts.qname) {
+			return Ok(0);
+		}
+		let queue = MemoryQueue {
+			vt: opts.vt,
+			delay: opts.delay,
+			maxsize: opts.maxsize,
+			totalrecv: 0,
+			totalsent: 0,
+			created: ts,
+			modified: ts,
+			lastid: 0,
+			schedule: BTreeSet::new(),
+			messages: HashMap::new(),
+		};
+		state.queues.insert(opts.qname, queue);
+		Ok(1)
+	}
+
+	async fn list_queues(&self) -> Result<Vec<String>, Error> { Ok(self.state.lock().unwrap().queues.keys().cloned().collect()) }
+
+	async fn delete_queue(&self, qname: &str) -> Result<(), Error> {
+		self.state.lock().unwrap().queues.remove(qname);
+		Ok(())
+	}
+
+	async fn purge_queue(&self, qname: &str) -> Result<u64, Error> {
+		let mut state = self.state.lock().unwrap();
+		let q = state.queue(qname)?;
+		let purged = q.messages.len() as u64;
+		q.messages.clear();
+		q.schedule.clear();
+		Ok(purged)
+	}
+
+	async fn send_message(&self, qname: &str, message: &str, delay: Option<u64>) -> Result<String, Error> {
+		let mut state = self.state.lock().unwrap();
+		let now_us = state.now_us();
+		let q = state.queue(qname)?;
+		if q.maxsize != -1 && message.len() as i64 > q.maxsize {
+			return Err(script_error("Message is too long").into());
+		}
+		let id_us = now_us.max(q.lastid + 1);
+		q.lastid = id_us;
+		let id = message_id(id_us);
+		let visible_at = now_us / 1000 + delay.unwrap_or(q.delay) * 1000;
+		q.schedule.insert((visible_at, id.clone()));
+		q.messages.insert(id.clone(), StoredMessage { body: message.into(), visible_at, sent: id_us, rc: 0, fr: 0 });
+		q.totalsent += 1;
+		Ok(id)
+	}
+
+	async fn receive_message(&self, qname: &str, hidefor: Option<u64>) -> Result<Message, Error> {
+		let mut state = self.state.lock().unwrap();
+		let now = state.now_us() / 1000;
+		let q = state.queue(qname)?;
+		let msgid = q.next_visible(now).ok_or_else(no_messages)?;
+		q.reschedule(&msgid, now + hidefor.unwrap_or(q.vt) * 1000);
+		Ok(q.receive(&msgid, now))
+	}
+
+	async fn pop_message(&self, qname: &str) -> Result<Message, Error> {
+		let mut state = self.state.lock().unwrap();
+		let now = state.now_us() / 1000;
+		let q = state.queue(qname)?;
+		let msgid = q.next_visible(now).ok_or_else(no_messages)?;
+		let msg = q.receive(&msgid, now);
+		q.remove(&msgid);
+		Ok(msg)
+	}
+
+	async fn delete_message(&self, qname: &str, msgid: &str) -> Result<bool, Error> {
+		let mut state = self.state.lock().unwrap();
+		Ok(state.queues.get_mut(qname).and_then(|q| q.remove(msgid)).is_some())
+	}
+
+	async fn change_message_visibility(&self, qname: &str, msgid: &str, hidefor: u64) -> Result<u64, Error> {
+		let mut state = self.state.lock().unwrap();
+		let expires_at = state.now_us() / 1000 + hidefor * 1000;
+		state.queue(qname)?.reschedule(msgid, expires_at);
+		Ok(expires_at)
+	}
+
+	async fn get_queue_attributes(&self, qname: &str) -> Result<Queue, Error> {
+		let mut state = self.state.lock().unwrap();
+		// Like the Redis backend, count the messages hidden from the start of the current second.
+		let from = state.now_us() / 1_000_000 * 1000;
+		let q = state.queue(qname)?;
+		Ok(Queue {
+			qname: qname.into(),
+			vt: q.vt,
+			delay: q.delay,
+			maxsize: q.maxsize,
+			totalrecv: q.totalrecv,
+			totalsent: q.totalsent,
+			created: q.created,
+			modified: q.modified,
+			msgs: q.messages.len() as u64,
+			hiddenmsgs: q.schedule.iter().filter(|(at, _)| *at >= from).count() as u64,
+		})
+	}
+
+	async fn set_queue_attributes(&self, qname: &str, vt: Option<u64>, delay: Option<u64>, maxsize: Option<i64>) -> Result<Queue, Error> {
+		{
+			let mut state = self.state.lock().unwrap();
+			let q = state.queue(qname)?;
+			if let Some(vt) = vt {
+				q.vt = vt;
+			}
+			if let Some(delay) = delay {
+				q.delay = delay;
+			}
+			if let Some(maxsize) = maxsize {
+				q.maxsize = maxsize;
+			}
+		}
+		self.get_queue_attributes(qname).await
+	}
+}
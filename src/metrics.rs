@@ -0,0 +1,236 @@
+//! Prometheus metrics for queue operations and queue depths.
+//!
+//! An [`Rsmq`](crate::Rsmq) built with [`RsmqBuilder::metrics`](crate::RsmqBuilder::metrics) counts and times its
+//! sends, receives, pops and deletes, counts failures by kind and times how long it waits for a pooled connection.
+//! [`watch_queues`] keeps the size and the totals of every queue up to date, for any [`QueueBackend`]. The metrics are
+//! rendered in the Prometheus text format by [`Metrics::render`], or served by [`serve`] with the `metrics` feature.
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), failure::Error> {
+//! use rsmq::{metrics::{self, Metrics}, RsmqBuilder};
+//! use std::{sync::Arc, time::Duration};
+//!
+//! let metrics = Arc::new(Metrics::new());
+//! let rsmq = Arc::new(RsmqBuilder::new("redis://127.0.0.1/").metrics(metrics.clone()).build().await?);
+//! tokio::spawn(metrics::watch_queues(metrics.clone(), rsmq.clone(), Duration::from_secs(15)));
+//! # #[cfg(feature = "metrics")]
+//! metrics::serve(metrics, ([127, 0, 0, 1], 9091).into()).await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use failure::Error;
+use redis::RedisError;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::{is_no_messages, Queue, QueueBackend};
+
+// Upper bounds of the histogram buckets, in seconds.
+const BUCKETS: [f64; 14] = [0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5];
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Operation {
+	Send,
+	Receive,
+	Pop,
+	Delete,
+}
+
+const OPERATIONS: [Operation; 4] = [Operation::Send, Operation::Receive, Operation::Pop, Operation::Delete];
+
+impl Operation {
+	fn name(self) -> &'static str {
+		match self {
+			Operation::Send => "send",
+			Operation::Receive => "receive",
+			Operation::Pop => "pop",
+			Operation::Delete => "delete",
+		}
+	}
+}
+
+#[derive(Debug, Default)]
+struct Histogram {
+	// Observations per bucket, not cumulative; the last one counts those above the largest bound.
+	buckets: [AtomicU64; BUCKETS.len() + 1],
+	sum_ns: AtomicU64,
+}
+
+impl Histogram {
+	fn observe(&self, d: Duration) {
+		let secs = d.as_secs_f64();
+		let i = BUCKETS.iter().position(|&le| secs <= le).unwrap_or(BUCKETS.len());
+		self.buckets[i].fetch_add(1, Ordering::Relaxed);
+		self.sum_ns.fetch_add(d.as_nanos() as u64, Ordering::Relaxed);
+	}
+
+	fn render(&self, out: &mut String, name: &str, labels: &str) {
+		let sep = if labels.is_empty() { "" } else { "," };
+		let mut count = 0;
+		for (i, bucket) in self.buckets.iter().enumerate() {
+			count += bucket.load(Ordering::Relaxed);
+			let le = BUCKETS.get(i).map_or("+Inf".to_string(), |le| le.to_string());
+			let _ = writeln!(out, "{}_bucket{{{}{}le=\"{}\"}} {}", name, labels, sep, le, count);
+		}
+		let sum = self.sum_ns.load(Ordering::Relaxed) as f64 / 1e9;
+		let labels = if labels.is_empty() { String::new() } else { format!("{{{}}}", labels) };
+		let _ = writeln!(out, "{}_sum{} {}", name, labels, sum);
+		let _ = writeln!(out, "{}_count{} {}", name, labels, count);
+	}
+}
+
+// The name, type, help and value of a metric of each queue.
+type QueueMetric = (&'static str, &'static str, &'static str, fn(&Queue) -> u64);
+
+#[derive(Debug, Default)]
+struct OperationMetrics {
+	total: AtomicU64,
+	empty: AtomicU64,
+	duration: Histogram,
+}
+
+/// Metrics shared by the clients and queue watchers that record them, see the [module documentation](self).
+#[derive(Debug, Default)]
+pub struct Metrics {
+	operations: [OperationMetrics; OPERATIONS.len()],
+	errors: Mutex<BTreeMap<(&'static str, &'static str), u64>>,
+	pool_wait: Histogram,
+	queues: Mutex<Vec<Queue>>,
+}
+
+impl Metrics {
+	pub fn new() -> Metrics { Metrics::default() }
+
+	/// Count an operation that took `elapsed`. Receiving from an empty queue isn't a failure.
+	pub(crate) fn observe<T>(&self, op: Operation, elapsed: Duration, res: &Result<T, Error>) {
+		let m = &self.operations[op as usize];
+		m.total.fetch_add(1, Ordering::Relaxed);
+		m.duration.observe(elapsed);
+		match res {
+			Ok(_) => {}
+			Err(e) if is_no_messages(e) => {
+				m.empty.fetch_add(1, Ordering::Relaxed);
+			}
+			Err(e) => *self.errors.lock().unwrap().entry((op.name(), error_kind(e))).or_insert(0) += 1,
+		}
+	}
+
+	pub(crate) fn observe_pool_wait(&self, elapsed: Duration) { self.pool_wait.observe(elapsed) }
+
+	/// Replace the queue metrics with the attributes of every queue of `backend`.
+	pub async fn scrape_queues(&self, backend: &dyn QueueBackend) -> Result<(), Error> {
+		let mut queues = Vec::new();
+		for qname in backend.list_queues().await? {
+			// A queue deleted since it was listed is left out.
+			if let Ok(q) = backend.get_queue_attributes(&qname).await {
+				queues.push(q);
+			}
+		}
+		queues.sort_by(|a, b| a.qname.cmp(&b.qname));
+		*self.queues.lock().unwrap() = queues;
+		Ok(())
+	}
+
+	/// All metrics in the Prometheus text exposition format.
+	pub fn render(&self) -> String {
+		let mut out = String::new();
+		header(&mut out, "rsmq_operations_total", "counter", "Queue operations, including failed ones.");
+		for op in OPERATIONS.iter() {
+			let _ = writeln!(out, "rsmq_operations_total{{operation=\"{}\"}} {}", op.name(), self.operations[*op as usize].total.load(Ordering::Relaxed));
+		}
+		header(&mut out, "rsmq_empty_receives_total", "counter", "Receives and pops that found no visible message.");
+		for op in [Operation::Receive, Operation::Pop].iter() {
+			let _ = writeln!(out, "rsmq_empty_receives_total{{operation=\"{}\"}} {}", op.name(), self.operations[*op as usize].empty.load(Ordering::Relaxed));
+		}
+		header(&mut out, "rsmq_operation_errors_total", "counter", "Failed queue operations, by kind of error.");
+		for ((op, kind), n) in self.errors.lock().unwrap().iter() {
+			let _ = writeln!(out, "rsmq_operation_errors_total{{operation=\"{}\",kind=\"{}\"}} {}", op, kind, n);
+		}
+		header(&mut out, "rsmq_operation_duration_seconds", "histogram", "How long queue operations took.");
+		for op in OPERATIONS.iter() {
+			self.operations[*op as usize].duration.render(&mut out, "rsmq_operation_duration_seconds", &format!("operation=\"{}\"", op.name()));
+		}
+		header(&mut out, "rsmq_pool_wait_seconds", "histogram", "How long operations waited for a pooled connection.");
+		self.pool_wait.render(&mut out, "rsmq_pool_wait_seconds", "");
+
+		let queues = self.queues.lock().unwrap();
+		let gauges: [QueueMetric; 4] = [
+			("rsmq_queue_messages", "gauge", "Messages in the queue.", |q| q.msgs),
+			("rsmq_queue_hidden_messages", "gauge", "Messages in the queue that are not visible.", |q| q.hiddenmsgs),
+			("rsmq_queue_sent_total", "counter", "Messages sent to the queue since it was created.", |q| q.totalsent),
+			("rsmq_queue_received_total", "counter", "Messages received from the queue since it was created.", |q| q.totalrecv),
+		];
+		for (name, kind, help, value) in gauges.iter() {
+			header(&mut out, name, kind, help);
+			for q in queues.iter() {
+				let _ = writeln!(out, "{}{{queue=\"{}\"}} {}", name, escape(&q.qname), value(q));
+			}
+		}
+		out
+	}
+}
+
+/// Scrape the queues of `backend` into `metrics` every `interval`, forever. A failed scrape keeps the previous values.
+pub async fn watch_queues(metrics: Arc<Metrics>, backend: Arc<dyn QueueBackend>, interval: Duration) {
+	loop {
+		let _ = metrics.scrape_queues(backend.as_ref()).await;
+		tokio::time::delay_for(interval).await;
+	}
+}
+
+/// Serve the rendered metrics on `addr`, at any path, until the server fails.
+#[cfg(feature = "metrics")]
+pub async fn serve(metrics: Arc<Metrics>, addr: std::net::SocketAddr) -> Result<(), Error> {
+	use hyper::service::{make_service_fn, service_fn};
+	use hyper::{Body, Response, Server};
+
+	let make_svc = make_service_fn(move |_| {
+		let metrics = metrics.clone();
+		async move {
+			Ok::<_, std::convert::Infallible>(service_fn(move |_| {
+				let res = Response::builder().header("Content-Type", "text/plain; version=0.0.4").body(Body::from(metrics.render()));
+				async move { res }
+			}))
+		}
+	});
+	Server::bind(&addr).serve(make_svc).await?;
+	Ok(())
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+	let _ = writeln!(out, "# HELP {} {}", name, help);
+	let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn escape(label: &str) -> String { label.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n") }
+
+// The `kind` label of a failed operation.
+fn error_kind(e: &Error) -> &'static str {
+	if let Some(e) = e.downcast_ref::<RedisError>() {
+		return redis_error_kind(e);
+	}
+	match e.downcast_ref::<bb8::RunError<RedisError>>() {
+		Some(bb8::RunError::TimedOut) => "pool_timeout",
+		Some(bb8::RunError::User(e)) => redis_error_kind(e),
+		None => "other",
+	}
+}
+
+fn redis_error_kind(e: &RedisError) -> &'static str {
+	match e.detail() {
+		Some("Queue not found") => return "queue_not_found",
+		Some("Message is too long") => return "message_too_long",
+		_ => {}
+	}
+	if e.is_timeout() {
+		"timeout"
+	} else if e.is_io_error() || e.is_connection_dropped() || e.is_connection_refusal() {
+		"connection"
+	} else {
+		"redis"
+	}
+}
@@ -0,0 +1,190 @@
+//! An HTTP API for queues, compatible with the routes and JSON of the JS
+//! [rest-rsmq](https://github.com/smrchy/rest-rsmq), so services without a Redis client can use them.
+//!
+//! | Route                           | Body or query                 | Reply                                  |
+//! |---------------------------------|-------------------------------|----------------------------------------|
+//! | `GET /queues`                   |                               | `{"queues": [...]}`                    |
+//! | `POST /queues/:qname`           | `{"vt", "delay", "maxsize"}`  | `{"result": 1}`                        |
+//! | `GET /queues/:qname`            |                               | the queue attributes                   |
+//! | `PUT /queues/:qname`            | `{"vt", "delay", "maxsize"}`  | the queue attributes                   |
+//! | `DELETE /queues/:qname`         |                               | `{"result": 1}`                        |
+//! | `POST /messages/:qname`         | `{"message", "delay"}`        | `{"id": ...}`                          |
+//! | `GET /messages/:qname`          | `?vt=`                        | `{"id", "message", "rc", "fr", "sent"}` or `{}` |
+//! | `DELETE /messages/:qname/:id`   |                               | `{"result": 1}` or `{"result": 0}`     |
+//! | `PUT /messages/:qname/:id`      | `?vt=`                        | `{"result": 1}`                        |
+//!
+//! Errors are replied as `{"name": ..., "message": ...}` with the error names of the JS RSMQ, e.g. `queueNotFound`.
+//! Times are in milliseconds, like in the JS RSMQ.
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), failure::Error> {
+//! use std::sync::Arc;
+//!
+//! let rsmq = rsmq::Rsmq::new("redis://127.0.0.1/", "rsmq").await?;
+//! rsmq::rest::serve(Arc::new(rsmq), ([127, 0, 0, 1], 8101).into()).await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use failure::Error;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use serde_json::{json, Value as Json};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use crate::http::{json_response, percent_decode};
+use crate::{is_no_messages, Queue, QueueBackend};
+
+/// Serve the API for `backend` on `addr` until the server fails.
+pub async fn serve(backend: Arc<dyn QueueBackend>, addr: SocketAddr) -> Result<(), Error> {
+	let make_service = make_service_fn(move |_| {
+		let backend = backend.clone();
+		async move {
+			Ok::<_, Infallible>(service_fn(move |req| {
+				let backend = backend.clone();
+				async move { Ok::<_, Infallible>(handle(&*backend, req).await) }
+			}))
+		}
+	});
+	Server::bind(&addr).serve(make_service).await?;
+	Ok(())
+}
+
+/// Answer a single request, e.g. from a server the application runs itself.
+pub async fn handle(backend: &dyn QueueBackend, req: Request<Body>) -> Response<Body> {
+	match route(backend, req).await {
+		Ok(reply) => json_response(StatusCode::OK, reply),
+		Err(e) => {
+			let (status, name) = classify(&e);
+			json_response(status, json!({ "name": name, "message": message(&e) }))
+		}
+	}
+}
+
+async fn route(backend: &dyn QueueBackend, req: Request<Body>) -> Result<Json, Error> {
+	let path: Vec<String> = req.uri().path().split('/').filter(|s| !s.is_empty()).map(percent_decode).collect();
+	let vt = query_param(&req, "vt")?;
+	let method = req.method().clone();
+	let body = body_json(req).await?;
+	let path: Vec<&str> = path.iter().map(String::as_str).collect();
+	match (method, path.as_slice()) {
+		(Method::GET, ["queues"]) => Ok(json!({ "queues": backend.list_queues().await? })),
+		(Method::POST, ["queues", qname]) => {
+			let q = Queue::new(qname, field(&body, "vt")?, field(&body, "delay")?, field(&body, "maxsize")?);
+			if backend.create_queue(q).await? == 0 {
+				return Err(RestError::new(StatusCode::CONFLICT, "queueExists", "Queue exists").into());
+			}
+			Ok(json!({ "result": 1 }))
+		}
+		(Method::GET, ["queues", qname]) => Ok(queue_json(&backend.get_queue_attributes(qname).await?)),
+		(Method::PUT, ["queues", qname]) => {
+			let q = backend.set_queue_attributes(qname, field(&body, "vt")?, field(&body, "delay")?, field(&body, "maxsize")?).await?;
+			Ok(queue_json(&q))
+		}
+		(Method::DELETE, ["queues", qname]) => {
+			// Like the JS RSMQ, deleting a queue that does not exist is an error.
+			backend.get_queue_attributes(qname).await?;
+			backend.delete_queue(qname).await?;
+			Ok(json!({ "result": 1 }))
+		}
+		(Method::POST, ["messages", qname]) => {
+			let message = match body.get("message") {
+				Some(Json::String(message)) => message.clone(),
+				_ => return Err(RestError::invalid("message").into()),
+			};
+			Ok(json!({ "id": backend.send_message(qname, &message, field(&body, "delay")?).await? }))
+		}
+		(Method::GET, ["messages", qname]) => match backend.receive_message(qname, vt).await {
+			Ok(msg) => Ok(json!({ "id": msg.id, "message": msg.message, "rc": msg.rc, "fr": msg.fr, "sent": msg.sent / 1000 })),
+			Err(e) if is_no_messages(&e) => Ok(json!({})),
+			Err(e) => Err(e),
+		},
+		(Method::DELETE, ["messages", qname, id]) => Ok(json!({ "result": backend.delete_message(qname, id).await? as u8 })),
+		(Method::PUT, ["messages", qname, id]) => {
+			let vt = vt.ok_or_else(|| RestError::invalid("vt"))?;
+			backend.change_message_visibility(qname, id, vt).await?;
+			Ok(json!({ "result": 1 }))
+		}
+		_ => Err(RestError::new(StatusCode::NOT_FOUND, "notFound", "Not found").into()),
+	}
+}
+
+fn queue_json(q: &Queue) -> Json {
+	json!({
+		"vt": q.vt,
+		"delay": q.delay,
+		"maxsize": q.maxsize,
+		"totalrecv": q.totalrecv,
+		"totalsent": q.totalsent,
+		"created": q.created,
+		"modified": q.modified,
+		"msgs": q.msgs,
+		"hiddenmsgs": q.hiddenmsgs,
+	})
+}
+
+// An error with its HTTP status and JS RSMQ error name.
+#[derive(Debug)]
+struct RestError {
+	status: StatusCode,
+	name: &'static str,
+	message: String,
+}
+
+impl RestError {
+	fn new(status: StatusCode, name: &'static str, message: &str) -> RestError { RestError { status, name, message: message.into() } }
+
+	fn invalid(field: &str) -> RestError { RestError::new(StatusCode::BAD_REQUEST, "invalidValue", &format!("Invalid {} value", field)) }
+}
+
+impl std::fmt::Display for RestError {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result { write!(f, "{}", self.message) }
+}
+
+impl std::error::Error for RestError {}
+
+fn classify(e: &Error) -> (StatusCode, &'static str) {
+	if let Some(e) = e.downcast_ref::<RestError>() {
+		return (e.status, e.name);
+	}
+	match e.downcast_ref::<redis::RedisError>().and_then(|e| e.detail()) {
+		Some("Queue not found") => (StatusCode::NOT_FOUND, "queueNotFound"),
+		Some("Message is too long") => (StatusCode::BAD_REQUEST, "messageTooLong"),
+		_ => (StatusCode::INTERNAL_SERVER_ERROR, "error"),
+	}
+}
+
+fn message(e: &Error) -> String {
+	match e.downcast_ref::<redis::RedisError>().and_then(|e| e.detail()) {
+		Some(detail) => detail.to_string(),
+		None => e.to_string(),
+	}
+}
+
+async fn body_json(req: Request<Body>) -> Result<Json, Error> {
+	let bytes = hyper::body::to_bytes(req.into_body()).await?;
+	if bytes.iter().all(u8::is_ascii_whitespace) {
+		return Ok(json!({}));
+	}
+	serde_json::from_slice(&bytes).map_err(|_| RestError::new(StatusCode::BAD_REQUEST, "invalidValue", "Invalid JSON body").into())
+}
+
+// A numeric field of the body; rest-rsmq also accepts numbers sent as strings.
+fn field<T: std::str::FromStr>(body: &Json, name: &str) -> Result<Option<T>, Error> {
+	match body.get(name) {
+		None | Some(Json::Null) => Ok(None),
+		Some(Json::Number(n)) => n.to_string().parse().map(Some).map_err(|_| RestError::invalid(name).into()),
+		Some(Json::String(s)) => s.parse().map(Some).map_err(|_| RestError::invalid(name).into()),
+		Some(_) => Err(RestError::invalid(name).into()),
+	}
+}
+
+fn query_param(req: &Request<Body>, name: &str) -> Result<Option<u64>, Error> {
+	let query = req.uri().query().unwrap_or("");
+	match query.split('&').filter_map(|pair| pair.split_once('=')).find(|(k, _)| *k == name) {
+		Some((_, v)) => v.parse().map(Some).map_err(|_| RestError::invalid(name).into()),
+		None => Ok(None),
+	}
+}
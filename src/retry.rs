@@ -0,0 +1,64 @@
+use std::time::Duration;
+
+use crate::util::random_fraction;
+
+/// How long to wait before the next attempt, of checking out a connection or of processing a message, see
+/// [`crate::Rsmq::nack_message`].
+#[derive(Clone, Debug, PartialEq)]
+pub enum Backoff {
+	/// Wait the same time before every attempt.
+	Fixed(Duration),
+	/// Start with `initial` and double the wait on every attempt, never waiting longer than `max`.
+	Exponential { initial: Duration, max: Duration },
+	/// Like `Exponential`, but wait a random time between half and all of it, so attempts that failed together
+	/// don't all retry at the same time.
+	ExponentialJitter { initial: Duration, max: Duration },
+}
+
+impl Backoff {
+	/// The time to wait before retry number `attempt`, counting from 0.
+	pub fn delay(&self, attempt: u32) -> Duration {
+		match self {
+			Backoff::Fixed(delay) => *delay,
+			Backoff::Exponential { initial, max } => exponential(*initial, *max, attempt),
+			Backoff::ExponentialJitter { initial, max } => {
+				let delay = exponential(*initial, *max, attempt);
+				delay / 2 + delay.mul_f64(random_fraction() / 2.0)
+			}
+		}
+	}
+
+	/// The arguments of the nack script: the initial and maximum delay in milliseconds, the factor the delay grows by
+	/// with every attempt and whether to add jitter.
+	pub(crate) fn script_args(&self) -> (u128, u128, u32, bool) {
+		match self {
+			Backoff::Fixed(delay) => (delay.as_millis(), delay.as_millis(), 1, false),
+			Backoff::Exponential { initial, max } => (initial.as_millis(), max.as_millis(), 2, false),
+			Backoff::ExponentialJitter { initial, max } => (initial.as_millis(), max.as_millis(), 2, true),
+		}
+	}
+}
+
+fn exponential(initial: Duration, max: Duration, attempt: u32) -> Duration {
+	let factor = 2u32.checked_pow(attempt).unwrap_or(u32::MAX);
+	initial.checked_mul(factor).map_or(max, |d| d.min(max))
+}
+
+/// How [`crate::Rsmq`] retries checking out a connection, e.g. while Redis restarts or a Sentinel failover is in
+/// progress. Commands themselves are not retried: once a command was sent it may have been executed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RetryPolicy {
+	pub max_retries: u32,
+	pub backoff: Backoff,
+}
+
+impl RetryPolicy {
+	/// Fail on the first error.
+	pub fn never() -> RetryPolicy { RetryPolicy { max_retries: 0, backoff: Backoff::Fixed(Duration::from_millis(0)) } }
+
+	pub fn new(max_retries: u32, backoff: Backoff) -> RetryPolicy { RetryPolicy { max_retries, backoff } }
+}
+
+impl Default for RetryPolicy {
+	fn default() -> RetryPolicy { RetryPolicy::never() }
+}
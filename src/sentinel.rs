@@ -0,0 +1,131 @@
+use async_trait::async_trait;
+use redis::{aio::{self, ConnectionLike}, Cmd, ConnectionAddr, ConnectionInfo, ErrorKind as RedisErrorKind, IntoConnectionInfo, Pipeline, RedisError, RedisFuture, RedisResult, Value};
+
+/// A `bb8::ManageConnection` that finds the current master of a Redis Sentinel deployment.
+///
+/// New connections ask the sentinels for the master's address and check that the server they reach really is a master.
+/// After a failover the connections to the old master fail, either with IO errors or with `READONLY` once it has been
+/// turned into a replica, and are dropped by the pool; idle ones fail the `ROLE` check on checkout. The pool thus
+/// rebuilds itself against the new master without the application having to reconfigure anything.
+#[derive(Clone, Debug)]
+pub struct SentinelConnectionManager {
+	master_name: String,
+	sentinels: Vec<ConnectionInfo>,
+	db: i64,
+	passwd: Option<String>,
+}
+
+impl SentinelConnectionManager {
+	/// Create a new `SentinelConnectionManager` for the master called `master_name` in the sentinel configuration.
+	pub fn new<T: IntoConnectionInfo>(master_name: &str, sentinels: Vec<T>) -> RedisResult<SentinelConnectionManager> {
+		let sentinels = sentinels.into_iter().map(|s| s.into_connection_info()).collect::<RedisResult<Vec<_>>>()?;
+		if sentinels.is_empty() {
+			return Err(RedisError::from((RedisErrorKind::InvalidClientConfig, "No sentinels given")));
+		}
+		Ok(SentinelConnectionManager { master_name: master_name.into(), sentinels, db: 0, passwd: None })
+	}
+
+	/// The database to select on the master, `0` by default.
+	pub fn db(mut self, db: i64) -> SentinelConnectionManager {
+		self.db = db;
+		self
+	}
+
+	/// The password of the master, which is usually not the same as the one of the sentinels.
+	pub fn master_password(mut self, passwd: Option<String>) -> SentinelConnectionManager {
+		self.passwd = passwd;
+		self
+	}
+
+	/// Ask the sentinels, in order, for the address of the current master.
+	pub async fn master_addr(&self) -> RedisResult<(String, u16)> {
+		let mut last_err = RedisError::from((RedisErrorKind::MasterDown, "No sentinel reachable"));
+		for sentinel in &self.sentinels {
+			let addr = match aio::connect(sentinel).await {
+				Ok(mut con) => redis::cmd("SENTINEL")
+					.arg("get-master-addr-by-name")
+					.arg(&self.master_name)
+					.query_async::<_, Option<(String, u16)>>(&mut con)
+					.await,
+				Err(e) => Err(e),
+			};
+			match addr {
+				Ok(Some(addr)) => return Ok(addr),
+				Ok(None) => last_err = RedisError::from((RedisErrorKind::MasterDown, "Sentinel does not know the master", self.master_name.clone())),
+				Err(e) => last_err = e,
+			}
+		}
+		Err(last_err)
+	}
+}
+
+#[async_trait]
+impl bb8::ManageConnection for SentinelConnectionManager {
+	type Connection = SentinelConnection;
+	type Error = RedisError;
+
+	async fn connect(&self) -> Result<Self::Connection, Self::Error> {
+		let (host, port) = self.master_addr().await?;
+		let info = ConnectionInfo { addr: Box::new(ConnectionAddr::Tcp(host, port)), db: self.db, passwd: self.passwd.clone() };
+		let mut con = SentinelConnection { con: aio::connect(&info).await?, broken: false };
+		check_master(&mut con).await?;
+		Ok(con)
+	}
+
+	async fn is_valid(&self, mut conn: Self::Connection) -> Result<Self::Connection, Self::Error> {
+		check_master(&mut conn).await?;
+		Ok(conn)
+	}
+
+	fn has_broken(&self, conn: &mut Self::Connection) -> bool { conn.broken }
+}
+
+async fn check_master(con: &mut SentinelConnection) -> RedisResult<()> {
+	let role: Vec<Value> = redis::cmd("ROLE").query_async(con).await?;
+	match role.first().map(redis::from_redis_value::<String>) {
+		Some(Ok(ref role)) if role == "master" => Ok(()),
+		_ => {
+			con.broken = true;
+			Err(RedisError::from((RedisErrorKind::MasterDown, "Server is not the master (anymore)")))
+		}
+	}
+}
+
+/// A connection to the master of a Redis Sentinel deployment, see [`SentinelConnectionManager`].
+pub struct SentinelConnection {
+	con: aio::Connection,
+	broken: bool,
+}
+
+impl std::fmt::Debug for SentinelConnection {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result { write!(f, "SentinelConnection {{ broken: {} }}", self.broken) }
+}
+
+impl SentinelConnection {
+	fn check<T>(&mut self, res: RedisResult<T>) -> RedisResult<T> {
+		if let Err(ref e) = res {
+			if e.is_io_error() || e.is_connection_dropped() || e.code() == Some("READONLY") {
+				self.broken = true;
+			}
+		}
+		res
+	}
+}
+
+impl ConnectionLike for SentinelConnection {
+	fn req_packed_command<'a>(&'a mut self, cmd: &'a Cmd) -> RedisFuture<'a, Value> {
+		Box::pin(async move {
+			let res = self.con.req_packed_command(cmd).await;
+			self.check(res)
+		})
+	}
+
+	fn req_packed_commands<'a>(&'a mut self, cmd: &'a Pipeline, offset: usize, count: usize) -> RedisFuture<'a, Vec<Value>> {
+		Box::pin(async move {
+			let res = self.con.req_packed_commands(cmd, offset, count).await;
+			self.check(res)
+		})
+	}
+
+	fn get_db(&self) -> i64 { self.con.get_db() }
+}
@@ -0,0 +1,176 @@
+use failure::{format_err, Error, Fail};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::collections::HashMap;
+
+use crate::envelope;
+use crate::Headers;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// HMAC-SHA256 signing of messages with shared keys, see [`RsmqBuilder::signing`](crate::RsmqBuilder::signing).
+///
+/// The signature covers the queue name, the headers and the body as stored, so messages can't be altered or moved to
+/// another queue unnoticed. Received messages must be signed with a key trusted for their queue, any known key unless
+/// set differently. Other messages are rejected, or moved to a quarantine queue.
+///
+/// ```no_run
+/// # fn run(billing_key: [u8; 32], orders_key: [u8; 32]) -> Result<(), failure::Error> {
+/// use rsmq::Signing;
+///
+/// let signing = Signing::new()
+///   .add_key("billing", &billing_key)?
+///   .add_key("orders", &orders_key)?
+///   .sign_with("billing")?
+///   // Only accept messages of the "orders" queue signed by the orders service...
+///   .verify_queue_with("orders", Some(&["orders"]))?
+///   // ...and don't check those of the "logs" queue.
+///   .verify_queue_with("logs", None)?
+///   .quarantine("rejected");
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Default)]
+pub struct Signing {
+	keys: HashMap<String, Vec<u8>>,
+	default_key: Option<String>,
+	queue_keys: HashMap<String, Option<String>>,
+	trusted_keys: HashMap<String, Option<Vec<String>>>,
+	quarantine: Option<String>,
+}
+
+impl std::fmt::Debug for Signing {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		let mut keys: Vec<_> = self.keys.keys().collect();
+		keys.sort();
+		f.debug_struct("Signing")
+			.field("keys", &keys)
+			.field("default_key", &self.default_key)
+			.field("queue_keys", &self.queue_keys)
+			.field("trusted_keys", &self.trusted_keys)
+			.field("quarantine", &self.quarantine)
+			.finish()
+	}
+}
+
+impl Signing {
+	/// No keys, nothing is signed.
+	pub fn new() -> Signing { Signing::default() }
+
+	/// Add the shared `key` with the id `id`, at least 32 bytes long, to sign with and to verify messages signed with it.
+	pub fn add_key(mut self, id: &str, key: &[u8]) -> Result<Signing, Error> {
+		if id.is_empty() || id.chars().any(|c| c.is_whitespace() || c.is_control()) {
+			return Err(format_err!("Invalid signing key id {:?}", id));
+		}
+		if key.len() < 32 {
+			return Err(format_err!("Signing keys must be at least 32 bytes long, {:?} is {}", id, key.len()));
+		}
+		self.keys.insert(id.into(), key.into());
+		Ok(self)
+	}
+
+	/// Sign the messages sent to every queue with the key `id`, unless set differently for the queue.
+	pub fn sign_with(mut self, id: &str) -> Result<Signing, Error> {
+		self.default_key = Some(self.known(id)?);
+		Ok(self)
+	}
+
+	/// Sign the messages sent to `qname` with the key `id`, or leave them unsigned with `None`.
+	pub fn sign_queue_with(mut self, qname: &str, id: Option<&str>) -> Result<Signing, Error> {
+		let id = id.map(|id| self.known(id)).transpose()?;
+		self.queue_keys.insert(qname.into(), id);
+		Ok(self)
+	}
+
+	/// Only accept messages of `qname` signed with one of the keys `ids`, or any message with `None`.
+	pub fn verify_queue_with(mut self, qname: &str, ids: Option<&[&str]>) -> Result<Signing, Error> {
+		let ids = ids.map(|ids| ids.iter().map(|id| self.known(id)).collect::<Result<Vec<_>, _>>()).transpose()?;
+		self.trusted_keys.insert(qname.into(), ids);
+		Ok(self)
+	}
+
+	/// Move received messages that fail verification to the queue `qname` instead of failing the receive. The
+	/// messages of the quarantine queue itself aren't verified, a header tells why they were moved.
+	pub fn quarantine(mut self, qname: &str) -> Signing {
+		self.quarantine = Some(qname.into());
+		self
+	}
+
+	fn known(&self, id: &str) -> Result<String, Error> {
+		if self.keys.contains_key(id) {
+			Ok(id.into())
+		} else {
+			Err(format_err!("Unknown signing key {:?}", id))
+		}
+	}
+
+	pub(crate) fn quarantine_queue(&self) -> Option<&str> { self.quarantine.as_deref() }
+
+	/// The signature of a message for `qname`, `None` if the queue's messages aren't signed.
+	pub(crate) fn sign(&self, qname: &str, headers: &Headers, body: &[u8]) -> Option<String> {
+		let id = match self.queue_keys.get(qname) {
+			Some(id) => id.as_ref(),
+			None => self.default_key.as_ref(),
+		}?;
+		let tag = mac(&self.keys[id], qname, headers, body).finalize().into_bytes();
+		Some(format!("{}:{}", id, envelope::base64_encode(&tag)))
+	}
+
+	/// Check the `signature` of a message of the dead letter queue `qname` against the queue it was dead lettered from,
+	/// where it was signed. Messages without their original signature are rejected.
+	pub(crate) fn verify_dead_lettered(&self, qname: &str, headers: &Headers, signature: Option<&str>, body: &[u8]) -> Result<(), String> {
+		if self.quarantine.as_deref() == Some(qname) || matches!(self.trusted_keys.get(qname), Some(None)) {
+			return Ok(());
+		}
+		let mut headers = headers.clone();
+		let from = headers.remove(envelope::DEAD_LETTERED_FROM).ok_or_else(|| "was not dead lettered from a queue".to_string())?;
+		if signature.is_none() {
+			return Err("is not signed".into());
+		}
+		self.verify(&from, &headers, signature, body)
+	}
+
+	/// Check the `signature` of a message of `qname`, failing with the reason it isn't trusted.
+	pub(crate) fn verify(&self, qname: &str, headers: &Headers, signature: Option<&str>, body: &[u8]) -> Result<(), String> {
+		if self.quarantine.as_deref() == Some(qname) {
+			return Ok(());
+		}
+		let trusted = match self.trusted_keys.get(qname) {
+			Some(None) => return Ok(()),
+			Some(Some(ids)) => Some(ids),
+			None => None,
+		};
+		let (id, tag) = signature.and_then(|s| s.rsplit_once(':')).ok_or_else(|| "is not signed".to_string())?;
+		if !trusted.map_or(self.keys.contains_key(id), |ids| ids.iter().any(|t| t == id)) {
+			return Err(format!("is signed with the untrusted key {:?}", id));
+		}
+		let tag = envelope::base64_decode(tag).map_err(|_| "has a malformed signature".to_string())?;
+		mac(&self.keys[id], qname, headers, body).verify_slice(&tag).map_err(|_| "has an invalid signature".to_string())
+	}
+}
+
+// The signed data: the queue name, the headers and the body, each unambiguously delimited.
+fn mac(key: &[u8], qname: &str, headers: &Headers, body: &[u8]) -> HmacSha256 {
+	let mut mac = HmacSha256::new_from_slice(key).expect("HMAC takes keys of any length");
+	mac.update(&(qname.len() as u64).to_be_bytes());
+	mac.update(qname.as_bytes());
+	for (name, value) in headers {
+		mac.update(name.as_bytes());
+		mac.update(b":");
+		mac.update(value.as_bytes());
+		mac.update(b"\n");
+	}
+	mac.update(b"\n");
+	mac.update(body);
+	mac
+}
+
+/// A received message that failed verification.
+#[derive(Debug)]
+pub(crate) struct InvalidSignature(pub(crate) String);
+
+impl std::fmt::Display for InvalidSignature {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result { f.write_str(&self.0) }
+}
+
+impl Fail for InvalidSignature {}
@@ -0,0 +1,223 @@
+use async_trait::async_trait;
+use failure::Error;
+use rusqlite::{params, Connection, OptionalExtension, Transaction};
+use std::path::Path;
+use std::sync::Mutex;
+
+use crate::util::{message_id, now_us};
+use crate::{no_messages, script_error, Headers, Message, Queue, QueueBackend};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS queues (
+	qname TEXT PRIMARY KEY,
+	vt INTEGER NOT NULL,
+	delay INTEGER NOT NULL,
+	maxsize INTEGER NOT NULL,
+	totalrecv INTEGER NOT NULL DEFAULT 0,
+	totalsent INTEGER NOT NULL DEFAULT 0,
+	created INTEGER NOT NULL,
+	modified INTEGER NOT NULL,
+	lastid INTEGER NOT NULL DEFAULT 0
+);
+CREATE TABLE IF NOT EXISTS messages (
+	qname TEXT NOT NULL REFERENCES queues (qname) ON DELETE CASCADE,
+	id TEXT NOT NULL,
+	body TEXT NOT NULL,
+	visible_at INTEGER NOT NULL,
+	sent INTEGER NOT NULL,
+	rc INTEGER NOT NULL DEFAULT 0,
+	fr INTEGER NOT NULL DEFAULT 0,
+	PRIMARY KEY (qname, id)
+);
+CREATE INDEX IF NOT EXISTS messages_by_visibility ON messages (qname, visible_at, id);
+";
+
+/// A [`QueueBackend`] persisting queues to a local SQLite database, for edge deployments and local development without
+/// Redis.
+///
+/// Every operation is a single SQLite transaction with the semantics of the Redis backend: the same message ids,
+/// visibility timeouts, delays, receive counts and queue statistics. Calls block the executor thread for the duration
+/// of the transaction, which is fine for a local database but makes this backend a poor fit for high throughput.
+#[derive(Debug)]
+pub struct SqliteBackend {
+	con: Mutex<Connection>,
+}
+
+impl SqliteBackend {
+	/// Open or create the database at `path`.
+	pub fn open<P: AsRef<Path>>(path: P) -> Result<SqliteBackend, Error> { SqliteBackend::with_connection(Connection::open(path)?) }
+
+	/// A database that lives as long as the backend.
+	pub fn open_in_memory() -> Result<SqliteBackend, Error> { SqliteBackend::with_connection(Connection::open_in_memory()?) }
+
+	fn with_connection(con: Connection) -> Result<SqliteBackend, Error> {
+		con.pragma_update(None, "foreign_keys", true)?;
+		con.execute_batch(SCHEMA)?;
+		Ok(SqliteBackend { con: Mutex::new(con) })
+	}
+
+	// Run `f` in a transaction, committed if it succeeds.
+	fn transaction<T, F: FnOnce(&Transaction) -> Result<T, Error>>(&self, f: F) -> Result<T, Error> {
+		let mut con = self.con.lock().unwrap();
+		let tx = con.transaction()?;
+		let res = f(&tx)?;
+		tx.commit()?;
+		Ok(res)
+	}
+}
+
+// The `vt`, `delay`, `maxsize` and `lastid` of a queue.
+fn queue_settings(tx: &Transaction, qname: &str) -> Result<(u64, u64, i64, u64), Error> {
+	tx.query_row("SELECT vt, delay, maxsize, lastid FROM queues WHERE qname = ?1", params![qname], |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?, r.get(3)?)))
+		.optional()?
+		.ok_or_else(|| script_error("Queue not found").into())
+}
+
+// Count a receive of the first visible message, if any, and return it as the scripts of the Redis backend do.
+fn receive_next(tx: &Transaction, qname: &str, now: u64) -> Result<Message, Error> {
+	let (id, body, sent, rc, fr): (String, String, u64, u64, u64) = tx
+		.query_row(
+			"SELECT id, body, sent, rc, fr FROM messages WHERE qname = ?1 AND visible_at <= ?2 ORDER BY visible_at, id LIMIT 1",
+			params![qname, now],
+			|r| Ok((r.get(0)?, r.get(1)?, r.get(2)?, r.get(3)?, r.get(4)?)),
+		)
+		.optional()?
+		.ok_or_else(no_messages)?;
+	let rc = rc + 1;
+	let fr = if rc == 1 { now } else { fr };
+	tx.execute("UPDATE messages SET rc = ?3, fr = ?4 WHERE qname = ?1 AND id = ?2", params![qname, id, rc, fr])?;
+	tx.execute("UPDATE queues SET totalrecv = totalrecv + 1 WHERE qname = ?1", params![qname])?;
+	Ok(Message { id, message: body, rc, fr, sent, headers: Headers::new() })
+}
+
+#[async_trait]
+impl QueueBackend for SqliteBackend {
+	async fn create_queue(&self, opts: Queue) -> Result<u8, Error> {
+		let ts = now_us() / 1_000_000;
+		self.transaction(|tx| {
+			let created = tx.execute(
+				"INSERT OR IGNORE INTO queues (qname, vt, delay, maxsize, created, modified) VALUES (?1, ?2, ?3, ?4, ?5, ?5)",
+				params![opts.qname, opts.vt, opts.delay, opts.maxsize, ts],
+			)?;
+			Ok(created as u8)
+		})
+	}
+
+	async fn list_queues(&self) -> Result<Vec<String>, Error> {
+		self.transaction(|tx| {
+			let mut stmt = tx.prepare("SELECT qname FROM queues ORDER BY qname")?;
+			let queues = stmt.query_map([], |r| r.get(0))?.collect::<Result<_, _>>()?;
+			Ok(queues)
+		})
+	}
+
+	async fn delete_queue(&self, qname: &str) -> Result<(), Error> {
+		self.transaction(|tx| {
+			tx.execute("DELETE FROM queues WHERE qname = ?1", params![qname])?;
+			Ok(())
+		})
+	}
+
+	async fn purge_queue(&self, qname: &str) -> Result<u64, Error> {
+		self.transaction(|tx| {
+			queue_settings(tx, qname)?;
+			Ok(tx.execute("DELETE FROM messages WHERE qname = ?1", params![qname])? as u64)
+		})
+	}
+
+	async fn send_message(&self, qname: &str, message: &str, delay: Option<u64>) -> Result<String, Error> {
+		let now_us = now_us();
+		self.transaction(|tx| {
+			let (_, default_delay, maxsize, lastid) = queue_settings(tx, qname)?;
+			if maxsize != -1 && message.len() as i64 > maxsize {
+				return Err(script_error("Message is too long").into());
+			}
+			let id_us = now_us.max(lastid + 1);
+			let id = message_id(id_us);
+			let visible_at = now_us / 1000 + delay.unwrap_or(default_delay) * 1000;
+			tx.execute(
+				"INSERT INTO messages (qname, id, body, visible_at, sent) VALUES (?1, ?2, ?3, ?4, ?5)",
+				params![qname, id, message, visible_at, id_us],
+			)?;
+			tx.execute("UPDATE queues SET totalsent = totalsent + 1, lastid = ?2 WHERE qname = ?1", params![qname, id_us])?;
+			Ok(id)
+		})
+	}
+
+	async fn receive_message(&self, qname: &str, hidefor: Option<u64>) -> Result<Message, Error> {
+		let now = now_us() / 1000;
+		self.transaction(|tx| {
+			let (vt, ..) = queue_settings(tx, qname)?;
+			let msg = receive_next(tx, qname, now)?;
+			let visible_at = now + hidefor.unwrap_or(vt) * 1000;
+			tx.execute("UPDATE messages SET visible_at = ?3 WHERE qname = ?1 AND id = ?2", params![qname, msg.id, visible_at])?;
+			Ok(msg)
+		})
+	}
+
+	async fn pop_message(&self, qname: &str) -> Result<Message, Error> {
+		let now = now_us() / 1000;
+		self.transaction(|tx| {
+			queue_settings(tx, qname)?;
+			let msg = receive_next(tx, qname, now)?;
+			tx.execute("DELETE FROM messages WHERE qname = ?1 AND id = ?2", params![qname, msg.id])?;
+			Ok(msg)
+		})
+	}
+
+	async fn delete_message(&self, qname: &str, msgid: &str) -> Result<bool, Error> {
+		self.transaction(|tx| Ok(tx.execute("DELETE FROM messages WHERE qname = ?1 AND id = ?2", params![qname, msgid])? == 1))
+	}
+
+	async fn change_message_visibility(&self, qname: &str, msgid: &str, hidefor: u64) -> Result<u64, Error> {
+		let expires_at = now_us() / 1000 + hidefor * 1000;
+		self.transaction(|tx| {
+			queue_settings(tx, qname)?;
+			tx.execute("UPDATE messages SET visible_at = ?3 WHERE qname = ?1 AND id = ?2", params![qname, msgid, expires_at])?;
+			Ok(expires_at)
+		})
+	}
+
+	async fn get_queue_attributes(&self, qname: &str) -> Result<Queue, Error> {
+		// Like the Redis backend, count the messages hidden from the start of the current second.
+		let from = now_us() / 1_000_000 * 1000;
+		self.transaction(|tx| {
+			let q = tx
+				.query_row(
+					"SELECT vt, delay, maxsize, totalrecv, totalsent, created, modified,
+						(SELECT COUNT(*) FROM messages WHERE qname = ?1),
+						(SELECT COUNT(*) FROM messages WHERE qname = ?1 AND visible_at >= ?2)
+					FROM queues WHERE qname = ?1",
+					params![qname, from],
+					|r| {
+						Ok(Queue {
+							qname: qname.into(),
+							vt: r.get(0)?,
+							delay: r.get(1)?,
+							maxsize: r.get(2)?,
+							totalrecv: r.get(3)?,
+							totalsent: r.get(4)?,
+							created: r.get(5)?,
+							modified: r.get(6)?,
+							msgs: r.get(7)?,
+							hiddenmsgs: r.get(8)?,
+						})
+					},
+				)
+				.optional()?;
+			q.ok_or_else(|| script_error("Queue not found").into())
+		})
+	}
+
+	async fn set_queue_attributes(&self, qname: &str, vt: Option<u64>, delay: Option<u64>, maxsize: Option<i64>) -> Result<Queue, Error> {
+		self.transaction(|tx| {
+			queue_settings(tx, qname)?;
+			tx.execute(
+				"UPDATE queues SET vt = COALESCE(?2, vt), delay = COALESCE(?3, delay), maxsize = COALESCE(?4, maxsize) WHERE qname = ?1",
+				params![qname, vt, delay, maxsize],
+			)?;
+			Ok(())
+		})?;
+		self.get_queue_attributes(qname).await
+	}
+}
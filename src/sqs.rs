@@ -0,0 +1,495 @@
+//! A subset of the AWS SQS API, so services written against an SQS SDK can use queues by changing the endpoint URL.
+//!
+//! Both the JSON protocol of current SDKs (`X-Amz-Target: AmazonSQS.SendMessage`) and the older query protocol
+//! (`Action=SendMessage`, XML replies) are understood, for these actions: `CreateQueue`, `GetQueueUrl`, `ListQueues`,
+//! `DeleteQueue`, `PurgeQueue`, `GetQueueAttributes`, `SetQueueAttributes`, `SendMessage`, `SendMessageBatch`,
+//! `ReceiveMessage` (with long polling), `DeleteMessage`, `DeleteMessageBatch`, `ChangeMessageVisibility` and
+//! `ChangeMessageVisibilityBatch`.
+//!
+//! Queue URLs are `http://{host}/000000000000/{qname}`. RSMQ has no receipt handles: the receipt handle of a message is
+//! its id. Requests are not authenticated; signatures sent by SDKs are ignored. Message attributes, FIFO queues and
+//! dead letter queues are not supported.
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), failure::Error> {
+//! use std::sync::Arc;
+//!
+//! let rsmq = rsmq::Rsmq::new("redis://127.0.0.1/", "rsmq").await?;
+//! rsmq::sqs::serve(Arc::new(rsmq), ([127, 0, 0, 1], 9324).into()).await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use failure::Error;
+use hyper::header::{CONTENT_TYPE, HOST};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, Server, StatusCode};
+use serde_json::{json, Map, Value as Json};
+use std::collections::{BTreeMap, HashSet};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::http::{form_pairs, percent_decode};
+use crate::util::now_us;
+use crate::{is_no_messages, Message, Queue, QueueBackend};
+
+// The account id in queue URLs and ARNs.
+const ACCOUNT_ID: &str = "000000000000";
+const MAX_BATCH_ENTRIES: usize = 10;
+// How often `ReceiveMessage` checks for messages while long polling.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Serve the API for `backend` on `addr` until the server fails.
+pub async fn serve(backend: Arc<dyn QueueBackend>, addr: SocketAddr) -> Result<(), Error> {
+	let make_service = make_service_fn(move |_| {
+		let backend = backend.clone();
+		async move {
+			Ok::<_, Infallible>(service_fn(move |req| {
+				let backend = backend.clone();
+				async move { Ok::<_, Infallible>(handle(&*backend, req).await) }
+			}))
+		}
+	});
+	Server::bind(&addr).serve(make_service).await?;
+	Ok(())
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Protocol {
+	Json,
+	Query,
+}
+
+/// Answer a single request, e.g. from a server the application runs itself.
+pub async fn handle(backend: &dyn QueueBackend, req: Request<Body>) -> Response<Body> {
+	let protocol = if req.headers().contains_key("x-amz-target") { Protocol::Json } else { Protocol::Query };
+	let host = req.headers().get(HOST).and_then(|h| h.to_str().ok()).unwrap_or("localhost").to_string();
+	let request_id = format!("{:016x}", now_us());
+	let reply = match parse(req).await {
+		Ok((action, params)) => execute(backend, &action, &params, &host).await.map(|reply| (action, reply)),
+		Err(e) => Err(e),
+	};
+	match (reply, protocol) {
+		(Ok((_, reply)), Protocol::Json) => response(StatusCode::OK, "application/x-amz-json-1.0", reply.to_string()),
+		(Ok((action, reply)), Protocol::Query) => response(StatusCode::OK, "text/xml", render_xml(&action, &reply, &request_id)),
+		(Err(e), protocol) => error_response(protocol, &SqsError::from_error(&e), &request_id),
+	}
+}
+
+// The action and its parameters, in the shape of the JSON protocol.
+async fn parse(req: Request<Body>) -> Result<(String, Json), Error> {
+	let target = req.headers().get("x-amz-target").map(|t| t.to_str().unwrap_or("").to_string());
+	let path = req.uri().path().to_string();
+	let query = req.uri().query().unwrap_or("").to_string();
+	let bytes = hyper::body::to_bytes(req.into_body()).await?;
+	if let Some(target) = target {
+		let action = target.strip_prefix("AmazonSQS.").ok_or_else(|| SqsError::invalid_action(&target))?;
+		let params = if bytes.is_empty() { json!({}) } else { serde_json::from_slice(&bytes).map_err(|_| SqsError::invalid("The request body is not valid JSON"))? };
+		return Ok((action.into(), params));
+	}
+
+	let mut pairs = form_pairs(&query);
+	pairs.extend(form_pairs(&String::from_utf8_lossy(&bytes)));
+	let mut params = query_params(pairs);
+	// Query protocol requests may be sent to the queue URL instead of naming it.
+	if params.get("QueueUrl").is_none() && path.trim_matches('/').contains('/') {
+		params["QueueUrl"] = Json::String(path);
+	}
+	let action = match params.get("Action") {
+		Some(Json::String(action)) => action.clone(),
+		_ => return Err(SqsError::missing("Action").into()),
+	};
+	Ok((action, params))
+}
+
+// Turn the flattened lists and maps of the query protocol, e.g. `Attribute.1.Name`, into JSON protocol parameters.
+fn query_params(pairs: Vec<(String, String)>) -> Json {
+	let mut params = Map::new();
+	let mut attributes: BTreeMap<u32, (String, String)> = BTreeMap::new();
+	let mut attribute_names: BTreeMap<u32, String> = BTreeMap::new();
+	let mut entries: BTreeMap<u32, Map<String, Json>> = BTreeMap::new();
+	for (key, value) in pairs {
+		let parts: Vec<&str> = key.split('.').collect();
+		let index = parts.get(1).and_then(|n| n.parse::<u32>().ok());
+		match (parts.as_slice(), index) {
+			([name], _) => {
+				params.insert(name.to_string(), Json::String(value));
+			}
+			(["Attribute", _, "Name"], Some(n)) => attributes.entry(n).or_default().0 = value,
+			(["Attribute", _, "Value"], Some(n)) => attributes.entry(n).or_default().1 = value,
+			(["AttributeName", _], Some(n)) | (["MessageSystemAttributeName", _], Some(n)) => {
+				attribute_names.insert(n, value);
+			}
+			([prefix, _, field], Some(n)) if prefix.ends_with("RequestEntry") => {
+				entries.entry(n).or_default().insert(field.to_string(), Json::String(value));
+			}
+			_ => {}
+		}
+	}
+	if !attributes.is_empty() {
+		params.insert("Attributes".into(), Json::Object(attributes.into_values().map(|(name, value)| (name, Json::String(value))).collect()));
+	}
+	if !attribute_names.is_empty() {
+		params.insert("AttributeNames".into(), attribute_names.into_values().map(Json::String).collect());
+	}
+	if !entries.is_empty() {
+		params.insert("Entries".into(), entries.into_values().map(Json::Object).collect());
+	}
+	Json::Object(params)
+}
+
+async fn execute(backend: &dyn QueueBackend, action: &str, params: &Json, host: &str) -> Result<Json, Error> {
+	match action {
+		"CreateQueue" => {
+			let qname = string(params, "QueueName")?;
+			let attrs = params.get("Attributes");
+			let maxsize = number(attrs, "MaximumMessageSize")?.map(|m| m as i64);
+			backend.create_queue(Queue::new(qname, number(attrs, "VisibilityTimeout")?, number(attrs, "DelaySeconds")?, maxsize)).await?;
+			Ok(json!({ "QueueUrl": queue_url(host, qname) }))
+		}
+		"GetQueueUrl" => {
+			let qname = string(params, "QueueName")?;
+			backend.get_queue_attributes(qname).await?;
+			Ok(json!({ "QueueUrl": queue_url(host, qname) }))
+		}
+		"ListQueues" => {
+			let prefix = params.get("QueueNamePrefix").and_then(Json::as_str).unwrap_or("");
+			let mut queues = backend.list_queues().await?;
+			queues.sort();
+			let urls: Vec<String> = queues.iter().filter(|q| q.starts_with(prefix)).map(|q| queue_url(host, q)).collect();
+			Ok(json!({ "QueueUrls": urls }))
+		}
+		"DeleteQueue" => {
+			let qname = queue_name(params)?;
+			backend.get_queue_attributes(&qname).await?;
+			backend.delete_queue(&qname).await?;
+			Ok(json!({}))
+		}
+		"PurgeQueue" => {
+			backend.purge_queue(&queue_name(params)?).await?;
+			Ok(json!({}))
+		}
+		"GetQueueAttributes" => {
+			let qname = queue_name(params)?;
+			let q = backend.get_queue_attributes(&qname).await?;
+			let names = attribute_names(params);
+			let attributes: Map<String, Json> = vec![
+				("VisibilityTimeout", q.vt.to_string()),
+				("DelaySeconds", q.delay.to_string()),
+				("MaximumMessageSize", q.maxsize.to_string()),
+				("ApproximateNumberOfMessages", q.msgs.saturating_sub(q.hiddenmsgs).to_string()),
+				("ApproximateNumberOfMessagesNotVisible", q.hiddenmsgs.to_string()),
+				("CreatedTimestamp", q.created.to_string()),
+				("LastModifiedTimestamp", q.modified.to_string()),
+				("QueueArn", format!("arn:aws:sqs:local:{}:{}", ACCOUNT_ID, qname)),
+			]
+			.into_iter()
+			.filter(|(name, _)| requested(&names, name))
+			.map(|(name, value)| (name.to_string(), Json::String(value)))
+			.collect();
+			Ok(json!({ "Attributes": attributes }))
+		}
+		"SetQueueAttributes" => {
+			let attrs = params.get("Attributes");
+			let maxsize = number(attrs, "MaximumMessageSize")?.map(|m| m as i64);
+			backend.set_queue_attributes(&queue_name(params)?, number(attrs, "VisibilityTimeout")?, number(attrs, "DelaySeconds")?, maxsize).await?;
+			Ok(json!({}))
+		}
+		"SendMessage" => {
+			let body = string(params, "MessageBody")?;
+			let id = backend.send_message(&queue_name(params)?, body, number(Some(params), "DelaySeconds")?).await?;
+			Ok(json!({ "MessageId": id, "MD5OfMessageBody": md5_hex(body) }))
+		}
+		"SendMessageBatch" => {
+			let qname = queue_name(params)?;
+			let mut reply = BatchReply::default();
+			for entry in batch_entries(params)? {
+				let id = string(entry, "Id")?;
+				let sent = match (string(entry, "MessageBody"), number(Some(entry), "DelaySeconds")) {
+					(Ok(body), Ok(delay)) => backend.send_message(&qname, body, delay).await.map(|msgid| (msgid, md5_hex(body))),
+					(Err(e), _) | (_, Err(e)) => Err(e),
+				};
+				match sent {
+					Ok((msgid, md5)) => reply.successful.push(json!({ "Id": id, "MessageId": msgid, "MD5OfMessageBody": md5 })),
+					Err(e) => reply.fail(id, &e),
+				}
+			}
+			Ok(reply.into())
+		}
+		"ReceiveMessage" => {
+			let qname = queue_name(params)?;
+			let max = number(Some(params), "MaxNumberOfMessages")?.unwrap_or(1) as usize;
+			if !(1..=MAX_BATCH_ENTRIES).contains(&max) {
+				return Err(SqsError::invalid("MaxNumberOfMessages must be between 1 and 10").into());
+			}
+			let vt = number(Some(params), "VisibilityTimeout")?;
+			let wait = number(Some(params), "WaitTimeSeconds")?.unwrap_or(0);
+			if wait > 20 {
+				return Err(SqsError::invalid("WaitTimeSeconds must be between 0 and 20").into());
+			}
+			let names = attribute_names(params);
+			let deadline = Instant::now() + Duration::from_secs(wait);
+			let mut messages = Vec::new();
+			loop {
+				while messages.len() < max {
+					match backend.receive_message(&qname, vt).await {
+						Ok(msg) => messages.push(message_json(&msg, &names)),
+						Err(e) if is_no_messages(&e) => break,
+						Err(e) => return Err(e),
+					}
+				}
+				if !messages.is_empty() || Instant::now() >= deadline {
+					break;
+				}
+				tokio::time::delay_for(POLL_INTERVAL).await;
+			}
+			Ok(json!({ "Messages": messages }))
+		}
+		"DeleteMessage" => {
+			backend.delete_message(&queue_name(params)?, string(params, "ReceiptHandle")?).await?;
+			Ok(json!({}))
+		}
+		"DeleteMessageBatch" => {
+			let qname = queue_name(params)?;
+			let mut reply = BatchReply::default();
+			for entry in batch_entries(params)? {
+				let id = string(entry, "Id")?;
+				let deleted = match string(entry, "ReceiptHandle") {
+					Ok(handle) => backend.delete_message(&qname, handle).await,
+					Err(e) => Err(e),
+				};
+				match deleted {
+					Ok(_) => reply.successful.push(json!({ "Id": id })),
+					Err(e) => reply.fail(id, &e),
+				}
+			}
+			Ok(reply.into())
+		}
+		"ChangeMessageVisibility" => {
+			let vt = number(Some(params), "VisibilityTimeout")?.ok_or_else(|| SqsError::missing("VisibilityTimeout"))?;
+			backend.change_message_visibility(&queue_name(params)?, string(params, "ReceiptHandle")?, vt).await?;
+			Ok(json!({}))
+		}
+		"ChangeMessageVisibilityBatch" => {
+			let qname = queue_name(params)?;
+			let mut reply = BatchReply::default();
+			for entry in batch_entries(params)? {
+				let id = string(entry, "Id")?;
+				let changed = match (string(entry, "ReceiptHandle"), number(Some(entry), "VisibilityTimeout")) {
+					(Ok(handle), Ok(Some(vt))) => backend.change_message_visibility(&qname, handle, vt).await,
+					(Ok(_), Ok(None)) => Err(SqsError::missing("VisibilityTimeout").into()),
+					(Err(e), _) | (_, Err(e)) => Err(e),
+				};
+				match changed {
+					Ok(_) => reply.successful.push(json!({ "Id": id })),
+					Err(e) => reply.fail(id, &e),
+				}
+			}
+			Ok(reply.into())
+		}
+		_ => Err(SqsError::invalid_action(action).into()),
+	}
+}
+
+#[derive(Default)]
+struct BatchReply {
+	successful: Vec<Json>,
+	failed: Vec<Json>,
+}
+
+impl BatchReply {
+	fn fail(&mut self, id: &str, e: &Error) {
+		let e = SqsError::from_error(e);
+		self.failed.push(json!({ "Id": id, "SenderFault": e.sender, "Code": e.code, "Message": e.message }));
+	}
+}
+
+impl From<BatchReply> for Json {
+	fn from(reply: BatchReply) -> Json { json!({ "Successful": reply.successful, "Failed": reply.failed }) }
+}
+
+fn batch_entries(params: &Json) -> Result<&Vec<Json>, Error> {
+	let entries = match params.get("Entries") {
+		Some(Json::Array(entries)) if !entries.is_empty() => entries,
+		_ => return Err(SqsError::sender("AWS.SimpleQueueService.EmptyBatchRequest", "EmptyBatchRequest", "There should be at least one entry in the request").into()),
+	};
+	if entries.len() > MAX_BATCH_ENTRIES {
+		return Err(SqsError::sender("AWS.SimpleQueueService.TooManyEntriesInBatchRequest", "TooManyEntriesInBatchRequest", "Maximum number of entries per request are 10").into());
+	}
+	let mut ids = HashSet::new();
+	for entry in entries {
+		if !ids.insert(string(entry, "Id")?) {
+			return Err(SqsError::sender("AWS.SimpleQueueService.BatchEntryIdsNotDistinct", "BatchEntryIdsNotDistinct", "Two or more batch entries have the same Id").into());
+		}
+	}
+	Ok(entries)
+}
+
+fn message_json(msg: &Message, names: &[String]) -> Json {
+	let attributes: Map<String, Json> = vec![("ApproximateReceiveCount", msg.rc), ("ApproximateFirstReceiveTimestamp", msg.fr), ("SentTimestamp", msg.sent / 1000)]
+		.into_iter()
+		.filter(|(name, _)| requested(names, name))
+		.map(|(name, value)| (name.to_string(), Json::String(value.to_string())))
+		.collect();
+	let mut json = json!({ "MessageId": msg.id, "ReceiptHandle": msg.id, "MD5OfBody": md5_hex(&msg.message), "Body": msg.message });
+	if !attributes.is_empty() {
+		json["Attributes"] = Json::Object(attributes);
+	}
+	json
+}
+
+fn attribute_names(params: &Json) -> Vec<String> {
+	["AttributeNames", "MessageSystemAttributeNames"]
+		.iter()
+		.filter_map(|key| params.get(*key).and_then(Json::as_array))
+		.flatten()
+		.filter_map(|name| name.as_str().map(String::from))
+		.collect()
+}
+
+fn requested(names: &[String], name: &str) -> bool { names.iter().any(|n| n == "All" || n == name) }
+
+fn queue_url(host: &str, qname: &str) -> String { format!("http://{}/{}/{}", host, ACCOUNT_ID, qname) }
+
+// The queue name is the last segment of the queue URL.
+fn queue_name(params: &Json) -> Result<String, Error> {
+	let url = string(params, "QueueUrl")?;
+	Ok(percent_decode(url.trim_end_matches('/').rsplit('/').next().unwrap_or(url)))
+}
+
+fn string<'a>(params: &'a Json, name: &str) -> Result<&'a str, Error> { params.get(name).and_then(Json::as_str).ok_or_else(|| SqsError::missing(name).into()) }
+
+// A number parameter; the query protocol sends them as strings.
+fn number(params: Option<&Json>, name: &str) -> Result<Option<u64>, Error> {
+	match params.and_then(|p| p.get(name)) {
+		None | Some(Json::Null) => Ok(None),
+		Some(Json::Number(n)) => n.as_u64().map(Some).ok_or_else(|| SqsError::invalid(&format!("Invalid value for {}", name)).into()),
+		Some(Json::String(s)) => s.parse().map(Some).map_err(|_| SqsError::invalid(&format!("Invalid value for {}", name)).into()),
+		Some(_) => Err(SqsError::invalid(&format!("Invalid value for {}", name)).into()),
+	}
+}
+
+fn md5_hex(body: &str) -> String { format!("{:x}", md5::compute(body)) }
+
+fn response(status: StatusCode, content_type: &str, body: String) -> Response<Body> {
+	Response::builder().status(status).header(CONTENT_TYPE, content_type).body(Body::from(body)).expect("valid response")
+}
+
+// An SQS error: `code` is the error code of the query protocol, `shape` the `__type` of the JSON protocol.
+#[derive(Clone, Debug)]
+struct SqsError {
+	status: StatusCode,
+	code: &'static str,
+	shape: &'static str,
+	message: String,
+	sender: bool,
+}
+
+impl SqsError {
+	fn sender(code: &'static str, shape: &'static str, message: &str) -> SqsError {
+		SqsError { status: StatusCode::BAD_REQUEST, code, shape, message: message.into(), sender: true }
+	}
+
+	fn invalid(message: &str) -> SqsError { SqsError::sender("InvalidParameterValue", "InvalidParameterValue", message) }
+
+	fn missing(name: &str) -> SqsError { SqsError::sender("MissingParameter", "MissingParameter", &format!("The request must contain the parameter {}", name)) }
+
+	fn invalid_action(action: &str) -> SqsError { SqsError::sender("InvalidAction", "InvalidAction", &format!("The action {} is not valid for this endpoint", action)) }
+
+	fn from_error(e: &Error) -> SqsError {
+		if let Some(e) = e.downcast_ref::<SqsError>() {
+			return e.clone();
+		}
+		match e.downcast_ref::<redis::RedisError>().and_then(|e| e.detail()) {
+			Some("Queue not found") => SqsError::sender("AWS.SimpleQueueService.NonExistentQueue", "QueueDoesNotExist", "The specified queue does not exist"),
+			Some("Message is too long") => SqsError::invalid("The message is longer than the MaximumMessageSize of the queue"),
+			_ => SqsError { status: StatusCode::INTERNAL_SERVER_ERROR, code: "InternalFailure", shape: "InternalFailure", message: e.to_string(), sender: false },
+		}
+	}
+}
+
+impl std::fmt::Display for SqsError {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result { write!(f, "{}: {}", self.code, self.message) }
+}
+
+impl std::error::Error for SqsError {}
+
+fn error_response(protocol: Protocol, e: &SqsError, request_id: &str) -> Response<Body> {
+	let fault = if e.sender { "Sender" } else { "Receiver" };
+	match protocol {
+		Protocol::Json => {
+			let body = json!({ "__type": format!("com.amazonaws.sqs#{}", e.shape), "message": e.message });
+			let mut res = response(e.status, "application/x-amz-json-1.0", body.to_string());
+			let query_error = format!("{};{}", e.code, fault).parse().expect("valid header");
+			res.headers_mut().insert("x-amzn-query-error", query_error);
+			res
+		}
+		Protocol::Query => {
+			let body = format!(
+				"<?xml version=\"1.0\"?><ErrorResponse><Error><Type>{}</Type><Code>{}</Code><Message>{}</Message></Error><RequestId>{}</RequestId></ErrorResponse>",
+				fault,
+				e.code,
+				xml_escape(&e.message),
+				request_id
+			);
+			response(e.status, "text/xml", body)
+		}
+	}
+}
+
+// The query protocol reply of `action`, from the JSON protocol reply.
+fn render_xml(action: &str, reply: &Json, request_id: &str) -> String {
+	let result = match reply {
+		Json::Object(fields) if !fields.is_empty() => format!("<{0}Result>{1}</{0}Result>", action, xml_fields(action, reply)),
+		_ => String::new(),
+	};
+	format!(
+		"<?xml version=\"1.0\"?><{0}Response xmlns=\"http://queue.amazonaws.com/doc/2012-11-05/\">{1}<ResponseMetadata><RequestId>{2}</RequestId></ResponseMetadata></{0}Response>",
+		action, result, request_id
+	)
+}
+
+fn xml_fields(action: &str, value: &Json) -> String {
+	let mut xml = String::new();
+	let fields = match value {
+		Json::Object(fields) => fields,
+		_ => return xml,
+	};
+	for (name, value) in fields {
+		match value {
+			// Lists are flattened into repeated elements named after their members.
+			Json::Array(items) => {
+				let tag = match name.as_str() {
+					"QueueUrls" => "QueueUrl".to_string(),
+					"Messages" => "Message".to_string(),
+					"Successful" => format!("{}ResultEntry", action),
+					"Failed" => "BatchResultErrorEntry".to_string(),
+					_ => name.clone(),
+				};
+				for item in items {
+					let inner = if item.is_object() { xml_fields(action, item) } else { xml_escape(&scalar(item)) };
+					xml.push_str(&format!("<{0}>{1}</{0}>", tag, inner));
+				}
+			}
+			// Maps are attribute lists.
+			Json::Object(attributes) => {
+				for (name, value) in attributes {
+					xml.push_str(&format!("<Attribute><Name>{}</Name><Value>{}</Value></Attribute>", xml_escape(name), xml_escape(&scalar(value))));
+				}
+			}
+			_ => xml.push_str(&format!("<{0}>{1}</{0}>", name, xml_escape(&scalar(value)))),
+		}
+	}
+	xml
+}
+
+fn scalar(value: &Json) -> String {
+	match value {
+		Json::String(s) => s.clone(),
+		other => other.to_string(),
+	}
+}
+
+fn xml_escape(s: &str) -> String { s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;").replace('\'', "&apos;") }
@@ -0,0 +1,96 @@
+use failure::{format_err, Error};
+
+use crate::{Headers, Message};
+
+const TRACEPARENT: &str = "traceparent";
+const TRACESTATE: &str = "tracestate";
+
+/// A [W3C Trace Context](https://www.w3.org/TR/trace-context/) carried from the producer of a message to its consumer in
+/// the `traceparent` and `tracestate` message headers.
+///
+/// ```no_run
+/// # async fn run(rsmq: rsmq::Rsmq) -> Result<(), failure::Error> {
+/// use rsmq::TraceContext;
+///
+/// let context = TraceContext::new("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01")?;
+/// rsmq.send_message_with_headers("jobs", "hello", None, &context.headers()).await?;
+///
+/// let message = rsmq.receive_message("jobs", None).await?;
+/// if let Some(parent) = message.trace_context() {
+///   // Make `parent.traceparent` the parent of the span processing the message.
+/// }
+/// # Ok(())
+/// # }
+/// ```
+///
+/// OpenTelemetry propagators can inject into and extract from the message headers directly, as a map of strings.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TraceContext {
+	pub traceparent: String,
+	pub tracestate: Option<String>,
+}
+
+impl TraceContext {
+	/// A context with a `traceparent` of the form `{version}-{trace-id}-{parent-id}-{flags}`.
+	pub fn new(traceparent: &str) -> Result<TraceContext, Error> {
+		if !valid_traceparent(traceparent) {
+			return Err(format_err!("Invalid traceparent {:?}", traceparent));
+		}
+		Ok(TraceContext { traceparent: traceparent.into(), tracestate: None })
+	}
+
+	/// Add the vendor specific `tracestate`.
+	pub fn with_tracestate(mut self, tracestate: &str) -> TraceContext {
+		self.tracestate = Some(tracestate.into()).filter(|s: &String| !s.is_empty());
+		self
+	}
+
+	/// Add the context to message headers.
+	pub fn inject(&self, headers: &mut Headers) {
+		headers.insert(TRACEPARENT.into(), self.traceparent.clone());
+		if let Some(tracestate) = &self.tracestate {
+			headers.insert(TRACESTATE.into(), tracestate.clone());
+		}
+	}
+
+	/// Message headers carrying only the context.
+	pub fn headers(&self) -> Headers {
+		let mut headers = Headers::new();
+		self.inject(&mut headers);
+		headers
+	}
+
+	/// The context in message headers, `None` if there's no valid `traceparent`.
+	pub fn extract(headers: &Headers) -> Option<TraceContext> {
+		let context = TraceContext::new(headers.get(TRACEPARENT)?).ok()?;
+		Some(match headers.get(TRACESTATE) {
+			Some(tracestate) => context.with_tracestate(tracestate),
+			None => context,
+		})
+	}
+}
+
+impl Message {
+	/// The trace context the message was sent with, see [`TraceContext`].
+	pub fn trace_context(&self) -> Option<TraceContext> { TraceContext::extract(&self.headers) }
+}
+
+fn valid_traceparent(traceparent: &str) -> bool {
+	let parts: Vec<&str> = traceparent.split('-').collect();
+	let hex = |s: &str, len: usize| s.len() == len && s.bytes().all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b));
+	let nonzero = |s: &str| s.bytes().any(|b| b != b'0');
+	// Later versions may append fields, version ff is invalid.
+	match parts.as_slice() {
+		[version, trace_id, parent_id, flags, ..] => {
+			hex(version, 2)
+				&& *version != "ff"
+				&& (parts.len() == 4 || *version != "00")
+				&& hex(trace_id, 32)
+				&& nonzero(trace_id)
+				&& hex(parent_id, 16)
+				&& nonzero(parent_id)
+				&& hex(flags, 2)
+		}
+		_ => false,
+	}
+}
@@ -0,0 +1,110 @@
+use failure::Error;
+use serde::{de::DeserializeOwned, Serialize};
+use std::marker::PhantomData;
+
+use crate::metrics::Operation;
+#[cfg(feature = "json")]
+use crate::Json;
+use crate::{Codec, Headers, Message, QueueKeys, Rsmq};
+#[cfg(feature = "tracing")]
+use tracing::field::Empty;
+
+/// A handle to a queue of `T` values, encoded with the codec `C`, see [`Rsmq::typed_queue`].
+///
+/// The handle builds the queue's key names once instead of on every operation.
+///
+/// ```no_run
+/// # #[cfg(feature = "json")]
+/// # async fn run(rsmq: rsmq::Rsmq) -> Result<(), failure::Error> {
+/// #[derive(serde::Serialize, serde::Deserialize)]
+/// struct OrderCreated {
+///   id: u64,
+/// }
+///
+/// let orders = rsmq.typed_queue::<OrderCreated>("orders");
+/// orders.send(&OrderCreated { id: 1 }, None).await?;
+///
+/// let received = orders.receive(None).await?;
+/// println!("order {} received {} times", received.value.id, received.rc);
+/// orders.delete(&received.id).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct TypedQueue<'a, T, C> {
+	rsmq: &'a Rsmq,
+	keys: QueueKeys,
+	codec: C,
+	values: PhantomData<fn(T) -> T>,
+}
+
+/// A value received from a [`TypedQueue`], with the attributes of its message.
+#[derive(Clone, Debug)]
+pub struct TypedMessage<T> {
+	pub id: String,
+	pub value: T,
+	/// Receive count
+	pub rc: u64,
+	/// First receive time
+	pub fr: u64,
+	pub sent: u64,
+	pub headers: Headers,
+}
+
+impl Rsmq {
+	/// A handle to the queue `qname` sending and receiving `T` values as JSON.
+	#[cfg(feature = "json")]
+	pub fn typed_queue<T>(&self, qname: &str) -> TypedQueue<'_, T, Json> { self.typed_queue_with(qname, Json) }
+
+	/// A handle to the queue `qname` sending and receiving `T` values encoded with `codec`.
+	pub fn typed_queue_with<T, C: Codec>(&self, qname: &str, codec: C) -> TypedQueue<'_, T, C> {
+		TypedQueue { rsmq: self, keys: self.queue_keys(qname), codec, values: PhantomData }
+	}
+}
+
+impl<T: Serialize + DeserializeOwned, C: Codec> TypedQueue<'_, T, C> {
+	pub fn qname(&self) -> &str { &self.keys.qname }
+
+	/// Send `value`, returning the id of its message.
+	#[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all, fields(ns = %self.rsmq.name_space, qname = %self.keys.qname, codec = self.codec.id(), delay = ?delay, id = Empty, round_trips = Empty, outcome = Empty)))]
+	pub async fn send(&self, value: &T, delay: Option<u64>) -> Result<String, Error> {
+		self.rsmq.observe(Some(Operation::Send), self.rsmq.send_value(&self.keys, &self.codec, value, delay)).await
+	}
+
+	/// Receive a value, hiding its message for `hidefor` seconds or the `vt` of the queue. A message that can't be
+	/// decoded is hidden all the same.
+	#[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all, fields(ns = %self.rsmq.name_space, qname = %self.keys.qname, vt = ?hidefor, id = Empty, rc = Empty, round_trips = Empty, outcome = Empty)))]
+	pub async fn receive(&self, hidefor: Option<u64>) -> Result<TypedMessage<T>, Error> {
+		self.rsmq.observe(Some(Operation::Receive), async { self.decode(self.rsmq.receive(&self.keys, hidefor).await?) }).await
+	}
+
+	/// Receive a value and delete its message. The message is deleted once it's decoded, so a message that can't be
+	/// decoded is left in the queue, hidden for the `vt` of the queue.
+	#[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all, fields(ns = %self.rsmq.name_space, qname = %self.keys.qname, id = Empty, rc = Empty, round_trips = Empty, outcome = Empty)))]
+	pub async fn pop(&self) -> Result<TypedMessage<T>, Error> {
+		self.rsmq
+			.observe(Some(Operation::Pop), async {
+				let m = self.decode(self.rsmq.receive(&self.keys, None).await?)?;
+				self.rsmq.delete(&self.keys, &m.id).await?;
+				Ok(m)
+			})
+			.await
+	}
+
+	/// See [`Rsmq::delete_message`].
+	#[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all, fields(ns = %self.rsmq.name_space, qname = %self.keys.qname, id = msgid, round_trips = Empty, outcome = Empty)))]
+	pub async fn delete(&self, msgid: &str) -> Result<bool, Error> {
+		self.rsmq.observe(Some(Operation::Delete), self.rsmq.delete(&self.keys, msgid)).await
+	}
+
+	/// See [`Rsmq::change_message_visibility`].
+	#[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all, fields(ns = %self.rsmq.name_space, qname = %self.keys.qname, id = msgid, vt = hidefor, round_trips = Empty, outcome = Empty)))]
+	pub async fn change_visibility(&self, msgid: &str, hidefor: u64) -> Result<u64, Error> {
+		self.rsmq.observe(None, self.rsmq.change_visibility(&self.keys, msgid, hidefor)).await
+	}
+
+	// Messages encoded with another codec, e.g. while the queue moves to `C`, are decoded with the built-in codecs.
+	fn decode(&self, m: Message) -> Result<TypedMessage<T>, Error> {
+		let value = if m.codec() == Some(self.codec.id()) { m.decode_with(&self.codec)? } else { m.decode()? };
+		Ok(TypedMessage { id: m.id, value, rc: m.rc, fr: m.fr, sent: m.sent, headers: m.headers })
+	}
+}
@@ -0,0 +1,52 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const ALPHANUMERIC: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789";
+
+// The system time in microseconds.
+pub(crate) fn now_us() -> u64 {
+	let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+	now.as_secs() * 1_000_000 + u64::from(now.subsec_micros())
+}
+
+// A JS RSMQ message id: `id_us` as base36 followed by 22 alphanumerics derived from it.
+pub(crate) fn message_id(id_us: u64) -> String {
+	const BASE36: &[u8] = b"0123456789abcdefghijklmnopqrstuvwxyz";
+	let mut digits = Vec::new();
+	let mut n = id_us;
+	loop {
+		digits.push(BASE36[(n % 36) as usize]);
+		n /= 36;
+		if n == 0 {
+			break;
+		}
+	}
+	digits.reverse();
+	// xorshift64*, ids only need to look random
+	let mut x = id_us | 1;
+	for _ in 0..22 {
+		x ^= x >> 12;
+		x ^= x << 25;
+		x ^= x >> 27;
+		let r = x.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 32;
+		digits.push(ALPHANUMERIC[(r % ALPHANUMERIC.len() as u64) as usize]);
+	}
+	String::from_utf8(digits).expect("ids are ASCII")
+}
+
+// 64 random bits. Every `RandomState` has new keys, seeded from the OS once per thread, so hashing with it is random
+// enough for ids and jitter without a dependency on a random number generator.
+pub(crate) fn random_u64() -> u64 {
+	let mut hasher = RandomState::new().build_hasher();
+	hasher.write_u64(now_us());
+	hasher.finish()
+}
+
+// A number in [0, 1).
+pub(crate) fn random_fraction() -> f64 { (random_u64() >> 11) as f64 / (1u64 << 53) as f64 }
+
+// `n` random alphanumerics.
+pub(crate) fn random_alphanumerics(n: usize) -> String {
+	(0..n).map(|_| ALPHANUMERIC[(random_u64() % ALPHANUMERIC.len() as u64) as usize] as char).collect()
+}
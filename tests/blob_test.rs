@@ -0,0 +1,25 @@
+use rsmq::*;
+
+#[tokio::test]
+async fn fs_blob_store() {
+	let dir = std::env::temp_dir().join(format!("rsmq-blobs-{}", std::process::id()));
+	let store = FsBlobStore::new(&dir).unwrap();
+
+	let first = store.put(b"first").await.unwrap();
+	let second = store.put(b"second").await.unwrap();
+	assert_ne!(first, second);
+	assert_eq!(store.get(&first).await.unwrap(), b"first");
+	assert_eq!(store.get(&second).await.unwrap(), b"second");
+
+	store.delete(&first).await.unwrap();
+	assert!(store.get(&first).await.is_err());
+	// Deleting twice is fine.
+	store.delete(&first).await.unwrap();
+
+	// References from messages can't reach outside the directory.
+	for invalid in &["", "../blob", "a/b", "a.tmp"] {
+		assert!(store.get(invalid).await.is_err(), "{}", invalid);
+		assert!(store.delete(invalid).await.is_err(), "{}", invalid);
+	}
+	std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,71 @@
+#![cfg(feature = "blocking")]
+
+use rsmq::blocking::Rsmq;
+use rsmq::{Backoff, Headers, Queue};
+use std::time::Duration;
+
+#[test]
+fn blocking_round_trip() {
+	let rsmq = Rsmq::new("redis://127.0.0.1/", "test-blocking-ns").expect("Can't instantiate RSMQ");
+	let qname = "blocking-q";
+	rsmq.delete_queue(qname).expect("no queue deleted");
+	rsmq.create_queue(Queue::new(qname, None, None, None)).expect("no queue for you!");
+	assert!(rsmq.list_queues().unwrap().contains(&qname.to_string()));
+
+	let msg_id = rsmq.send_message(qname, "no async here", Some(0)).expect("no, did not send that");
+	let received = rsmq.receive_message(qname, Some(10)).expect("no, did not receive that");
+	assert_eq!(received.id, msg_id);
+	assert_eq!(received.message, "no async here");
+	assert_eq!(rsmq.get_queue_attributes(qname).unwrap().hiddenmsgs, 1);
+
+	rsmq.change_message_visibility(qname, &msg_id, 0).expect("no, did not change that");
+	let popped = rsmq.pop_message(qname).expect("no, did not pop that");
+	assert_eq!(popped.id, msg_id);
+	assert!(!rsmq.delete_message(qname, &msg_id).unwrap());
+	rsmq.delete_queue(qname).expect("no queue deleted");
+}
+
+#[test]
+fn blocking_send_message_with_headers() {
+	let rsmq = Rsmq::new("redis://127.0.0.1/", "test-blocking-ns").expect("Can't instantiate RSMQ");
+	let qname = "blocking-headers-q";
+	rsmq.delete_queue(qname).expect("no queue deleted");
+	rsmq.create_queue(Queue::new(qname, None, None, None)).expect("no queue for you!");
+
+	let mut headers = Headers::new();
+	headers.insert("tenant".into(), "acme".into());
+	let msg_id = rsmq.send_message_with_headers(qname, "with headers", None, &headers).expect("no, did not send that");
+	let popped = rsmq.pop_message(qname).expect("no, did not pop that");
+	assert_eq!((popped.id, popped.message, popped.headers), (msg_id, "with headers".to_string(), headers));
+	rsmq.delete_queue(qname).expect("no queue deleted");
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn blocking_send_encoded() {
+	let rsmq = Rsmq::new("redis://127.0.0.1/", "test-blocking-ns").expect("Can't instantiate RSMQ");
+	let qname = "blocking-encoded-q";
+	rsmq.delete_queue(qname).expect("no queue deleted");
+	rsmq.create_queue(Queue::new(qname, None, None, None)).expect("no queue for you!");
+
+	rsmq.send_encoded(qname, &rsmq::Json, &vec![1, 2, 3], None).expect("no, did not send that");
+	let popped = rsmq.pop_message(qname).expect("no, did not pop that");
+	assert_eq!(popped.decode::<Vec<u32>>().unwrap(), vec![1, 2, 3]);
+	rsmq.delete_queue(qname).expect("no queue deleted");
+}
+
+#[test]
+fn blocking_nack_message() {
+	let rsmq = Rsmq::new("redis://127.0.0.1/", "test-blocking-ns").expect("Can't instantiate RSMQ");
+	let qname = "blocking-nack-q";
+	rsmq.delete_queue(qname).expect("no queue deleted");
+	rsmq.create_queue(Queue::new(qname, None, None, None)).expect("no queue for you!");
+
+	rsmq.send_message(qname, "retry me", None).expect("no, did not send that");
+	let received = rsmq.receive_message(qname, None).expect("no, did not receive that");
+	let backoff = Backoff::Fixed(Duration::from_secs(60));
+	let visible_at = rsmq.nack_message(qname, &received.id, &backoff).expect("no, did not nack that");
+	assert!(visible_at >= received.sent + 60_000);
+	assert!(rsmq.receive_message(qname, None).is_err());
+	rsmq.delete_queue(qname).expect("no queue deleted");
+}
@@ -0,0 +1,41 @@
+#![cfg(all(feature = "json", feature = "msgpack"))]
+
+use base64::Engine;
+use rsmq::*;
+
+type Order = (String, u32, Vec<bool>);
+
+fn order() -> Order { ("order-1".into(), 3, vec![true, false]) }
+
+fn message(codec: Option<&str>, body: &str) -> Message {
+	let mut m = Message::new();
+	m.message = body.into();
+	if let Some(codec) = codec {
+		m.headers.insert("rsmq-codec".into(), codec.into());
+	}
+	m
+}
+
+#[test]
+fn decode_picks_the_codec() {
+	let json = message(Some("json"), &String::from_utf8(Json.encode(&order()).unwrap()).unwrap());
+	assert_eq!(json.codec(), Some("json"));
+	assert_eq!(json.decode::<Order>().unwrap(), order());
+
+	let msgpack = message(Some("msgpack"), &base64::engine::general_purpose::STANDARD.encode(MessagePack.encode(&order()).unwrap()));
+	assert_eq!(msgpack.decode::<Order>().unwrap(), order());
+	assert!(msgpack.decode_with::<_, Order>(&Json).is_err());
+
+	// Messages of other clients are read as JSON.
+	assert_eq!(message(None, r#"["order-1",3,[true,false]]"#).decode::<Order>().unwrap(), order());
+	assert!(message(None, "[]").decode_with::<_, Order>(&MessagePack).is_err());
+	assert!(message(Some("yaml"), "[]").decode::<Order>().is_err());
+}
+
+#[cfg(all(feature = "cbor", feature = "bincode"))]
+#[test]
+fn binary_codecs_round_trip() {
+	assert_eq!(Cbor.decode::<Order>(&Cbor.encode(&order()).unwrap()).unwrap(), order());
+	assert_eq!(Bincode.decode::<Order>(&Bincode.encode(&order()).unwrap()).unwrap(), order());
+	assert!(!Cbor.is_text() && !Bincode.is_text() && Json.is_text());
+}
@@ -0,0 +1,16 @@
+#![cfg(feature = "aes-gcm")]
+
+use rsmq::*;
+
+#[test]
+fn keys_are_validated() {
+	assert!(Encryption::new().add_key("k1", Cipher::Aes256Gcm, &[0; 16]).is_err());
+	assert!(Encryption::new().add_key("k 1", Cipher::Aes256Gcm, &[0; 32]).is_err());
+	assert!(Encryption::new().encrypt_with("k1").is_err());
+
+	let encryption = Encryption::new().add_key("k1", Cipher::Aes256Gcm, &[0; 32]).unwrap();
+	assert!(encryption.clone().encrypt_queue_with("jobs", Some("k2")).is_err());
+	let encryption = encryption.encrypt_with("k1").unwrap().encrypt_queue_with("jobs", None).unwrap();
+	// Keys don't show up in debug output.
+	assert!(!format!("{:?}", encryption).contains("[0"));
+}
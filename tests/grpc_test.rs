@@ -0,0 +1,63 @@
+#![cfg(feature = "grpc")]
+
+use rsmq::grpc::proto::rsmq_client::RsmqClient;
+use rsmq::grpc::proto::*;
+use rsmq::*;
+use std::sync::Arc;
+use tokio::stream::StreamExt;
+use tonic::transport::Channel;
+use tonic::Code;
+
+async fn client(backend: Arc<dyn QueueBackend>, port: u16) -> RsmqClient<Channel> {
+	tokio::spawn(grpc::serve(backend, ([127, 0, 0, 1], port).into()));
+	let url = format!("http://127.0.0.1:{}", port);
+	for _ in 0..50 {
+		if let Ok(client) = RsmqClient::connect(url.clone()).await {
+			return client;
+		}
+		tokio::time::delay_for(std::time::Duration::from_millis(20)).await;
+	}
+	panic!("server didn't start");
+}
+
+#[tokio::test]
+async fn unary_calls() {
+	let mut client = client(Arc::new(MemoryBackend::new()), 50151).await;
+	let created = client.create_queue(CreateQueueRequest { qname: "jobs".into(), vt: None, delay: None, maxsize: Some(5) }).await.unwrap();
+	assert!(created.into_inner().created);
+	assert_eq!(client.list_queues(ListQueuesRequest {}).await.unwrap().into_inner().queues, vec!["jobs".to_string()]);
+
+	let id = client.send_message(SendMessageRequest { qname: "jobs".into(), message: "hello".into(), delay: None }).await.unwrap().into_inner().id;
+	let err = client.send_message(SendMessageRequest { qname: "jobs".into(), message: "too long".into(), delay: None }).await.unwrap_err();
+	assert_eq!(err.code(), Code::InvalidArgument);
+
+	let msg = client.receive_message(ReceiveMessageRequest { qname: "jobs".into(), vt: None }).await.unwrap().into_inner().message.unwrap();
+	assert_eq!((msg.id.as_str(), msg.message.as_str(), msg.rc), (id.as_str(), "hello", 1));
+	assert_eq!(client.receive_message(ReceiveMessageRequest { qname: "jobs".into(), vt: None }).await.unwrap().into_inner().message, None);
+	let attrs = client.get_queue_attributes(GetQueueAttributesRequest { qname: "jobs".into() }).await.unwrap().into_inner();
+	assert_eq!((attrs.msgs, attrs.totalrecv, attrs.maxsize), (1, 1, 5));
+	assert!(client.delete_message(DeleteMessageRequest { qname: "jobs".into(), id }).await.unwrap().into_inner().deleted);
+
+	let err = client.get_queue_attributes(GetQueueAttributesRequest { qname: "nope".into() }).await.unwrap_err();
+	assert_eq!(err.code(), Code::NotFound);
+}
+
+#[tokio::test]
+async fn streaming_receive() {
+	let backend = Arc::new(MemoryBackend::new());
+	backend.create_queue(Queue::new("jobs", None, None, None)).await.unwrap();
+	let mut client = client(backend.clone(), 50152).await;
+
+	let mut stream = client.receive(ReceiveRequest { qname: "jobs".into(), vt: None, max_messages: 2, poll_interval_ms: 10 }).await.unwrap().into_inner();
+	backend.send_message("jobs", "one", None).await.unwrap();
+	assert_eq!(stream.next().await.unwrap().unwrap().message, "one");
+	backend.send_message("jobs", "two", None).await.unwrap();
+	backend.send_message("jobs", "three", None).await.unwrap();
+	assert_eq!(stream.next().await.unwrap().unwrap().message, "two");
+	// The stream ends after `max_messages`, leaving the rest in the queue.
+	assert!(stream.next().await.is_none());
+	assert_eq!(backend.get_queue_attributes("jobs").await.unwrap().totalrecv, 2);
+
+	let err = client.receive(ReceiveRequest { qname: "nope".into(), vt: None, max_messages: 0, poll_interval_ms: 0 }).await.unwrap_err();
+	assert_eq!(err.code(), Code::NotFound);
+}
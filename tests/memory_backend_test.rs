@@ -0,0 +1,102 @@
+use rsmq::*;
+use std::time::Duration;
+
+async fn setup(vt: u64, delay: u64) -> MemoryBackend {
+	let backend = MemoryBackend::new();
+	assert_eq!(backend.create_queue(Queue::new("test-q", Some(vt), Some(delay), None)).await.unwrap(), 1);
+	backend
+}
+
+#[tokio::test]
+async fn create_list_delete_queues() {
+	let backend = setup(30, 0).await;
+	assert_eq!(backend.create_queue(Queue::new("test-q", None, None, None)).await.unwrap(), 0);
+	backend.create_queue(Queue::new("other-q", None, None, None)).await.unwrap();
+	assert_eq!(backend.list_queues().await.unwrap(), vec!["other-q".to_string(), "test-q".to_string()]);
+
+	backend.delete_queue("other-q").await.unwrap();
+	assert_eq!(backend.list_queues().await.unwrap(), vec!["test-q".to_string()]);
+	let err = backend.send_message("other-q", "hello", None).await.unwrap_err();
+	let err = err.downcast::<redis::RedisError>().unwrap();
+	assert_eq!(err.kind(), redis::ErrorKind::ResponseError);
+	assert_eq!(err.detail(), Some("Queue not found"));
+}
+
+#[tokio::test]
+async fn receive_hides_for_vt() {
+	let backend = setup(30, 0).await;
+	let id = backend.send_message("test-q", "hello", None).await.unwrap();
+
+	let msg = backend.receive_message("test-q", None).await.unwrap();
+	assert_eq!(msg.id, id);
+	assert_eq!(msg.message, "hello");
+	assert_eq!(msg.rc, 1);
+	assert_eq!(msg.sent, u64::from_str_radix(&id[0..10], 36).unwrap());
+	let err = backend.receive_message("test-q", None).await.unwrap_err();
+	assert_eq!(err.downcast::<redis::RedisError>().unwrap().kind(), redis::ErrorKind::TryAgain);
+
+	backend.advance_time(Duration::from_secs(31));
+	let again = backend.receive_message("test-q", Some(5)).await.unwrap();
+	assert_eq!(again.id, id);
+	assert_eq!(again.rc, 2);
+	assert_eq!(again.fr, msg.fr);
+	assert!(backend.receive_message("test-q", None).await.is_err());
+}
+
+#[tokio::test]
+async fn delay_and_change_message_visibility() {
+	let backend = setup(30, 10).await;
+	let id = backend.send_message("test-q", "later", None).await.unwrap();
+	let now = backend.send_message("test-q", "now", Some(0)).await.unwrap();
+	backend.advance_time(Duration::from_secs(1));
+	assert_eq!(backend.get_queue_attributes("test-q").await.unwrap().hiddenmsgs, 1);
+
+	assert_eq!(backend.receive_message("test-q", Some(0)).await.unwrap().id, now);
+	backend.change_message_visibility("test-q", &now, 60).await.unwrap();
+	backend.advance_time(Duration::from_secs(11));
+	assert_eq!(backend.receive_message("test-q", None).await.unwrap().id, id);
+	assert!(backend.receive_message("test-q", None).await.is_err());
+}
+
+#[tokio::test]
+async fn pop_and_delete_message() {
+	let backend = setup(30, 0).await;
+	let first = backend.send_message("test-q", "first", None).await.unwrap();
+	let second = backend.send_message("test-q", "second", None).await.unwrap();
+	assert!(first < second);
+
+	let popped = backend.pop_message("test-q").await.unwrap();
+	assert_eq!(popped.id, first);
+	assert_eq!(popped.rc, 1);
+	assert!(backend.delete_message("test-q", &second).await.unwrap());
+	assert!(!backend.delete_message("test-q", &second).await.unwrap());
+	assert!(backend.pop_message("test-q").await.is_err());
+
+	let q = backend.get_queue_attributes("test-q").await.unwrap();
+	assert_eq!((q.msgs, q.totalsent, q.totalrecv), (0, 2, 1));
+}
+
+#[tokio::test]
+async fn set_queue_attributes_and_maxsize() {
+	let backend = setup(30, 0).await;
+	let q = backend.set_queue_attributes("test-q", Some(5), None, Some(1024)).await.unwrap();
+	assert_eq!((q.vt, q.delay, q.maxsize), (5, 0, 1024));
+
+	let err = backend.send_message("test-q", &"x".repeat(1025), None).await.unwrap_err();
+	assert_eq!(err.downcast::<redis::RedisError>().unwrap().detail(), Some("Message is too long"));
+	backend.set_queue_attributes("test-q", None, None, Some(-1)).await.unwrap();
+	assert!(backend.send_message("test-q", &"x".repeat(100_000), None).await.is_ok());
+}
+
+#[tokio::test]
+async fn purge_queue() {
+	let backend = setup(30, 0).await;
+	backend.send_message("test-q", "one", None).await.unwrap();
+	backend.send_message("test-q", "two", None).await.unwrap();
+	backend.receive_message("test-q", None).await.unwrap();
+
+	assert_eq!(backend.purge_queue("test-q").await.unwrap(), 2);
+	let q = backend.get_queue_attributes("test-q").await.unwrap();
+	assert_eq!((q.msgs, q.hiddenmsgs, q.totalsent), (0, 0, 2));
+	assert!(backend.purge_queue("other-q").await.is_err());
+}
@@ -0,0 +1,62 @@
+use rsmq::metrics::{self, Metrics};
+use rsmq::*;
+use std::sync::Arc;
+use std::time::Duration;
+
+#[tokio::test]
+async fn scrape_queues() {
+	let backend = MemoryBackend::new();
+	backend.create_queue(Queue::new("jobs", None, None, None)).await.unwrap();
+	backend.create_queue(Queue::new("mail", None, None, None)).await.unwrap();
+	backend.send_message("jobs", "one", None).await.unwrap();
+	backend.send_message("jobs", "two", None).await.unwrap();
+	backend.receive_message("jobs", None).await.unwrap();
+
+	let metrics = Metrics::new();
+	metrics.scrape_queues(&backend).await.unwrap();
+	let text = metrics.render();
+	assert!(text.contains("# TYPE rsmq_queue_messages gauge\nrsmq_queue_messages{queue=\"jobs\"} 2\nrsmq_queue_messages{queue=\"mail\"} 0\n"), "{}", text);
+	assert!(text.contains("rsmq_queue_sent_total{queue=\"jobs\"} 2\n"), "{}", text);
+	assert!(text.contains("rsmq_queue_received_total{queue=\"jobs\"} 1\n"), "{}", text);
+
+	// Deleted queues disappear with the next scrape.
+	backend.delete_queue("mail").await.unwrap();
+	metrics.scrape_queues(&backend).await.unwrap();
+	assert!(!metrics.render().contains("queue=\"mail\""));
+}
+
+#[tokio::test]
+async fn render_without_observations() {
+	let text = Metrics::new().render();
+	assert!(text.contains("rsmq_operations_total{operation=\"delete\"} 0\n"), "{}", text);
+	assert!(text.contains("rsmq_pool_wait_seconds_bucket{le=\"+Inf\"} 0\nrsmq_pool_wait_seconds_sum 0\nrsmq_pool_wait_seconds_count 0\n"), "{}", text);
+	assert!(text.contains("rsmq_operation_duration_seconds_bucket{operation=\"send\",le=\"0.001\"} 0\n"), "{}", text);
+}
+
+#[tokio::test]
+async fn watch_queues_keeps_scraping() {
+	let backend = Arc::new(MemoryBackend::new());
+	let metrics = Arc::new(Metrics::new());
+	tokio::spawn(metrics::watch_queues(metrics.clone(), backend.clone(), Duration::from_millis(10)));
+	backend.create_queue(Queue::new("jobs", None, None, None)).await.unwrap();
+	tokio::time::delay_for(Duration::from_millis(50)).await;
+	assert!(metrics.render().contains("rsmq_queue_messages{queue=\"jobs\"} 0\n"));
+}
+
+#[cfg(feature = "metrics")]
+#[tokio::test]
+async fn serve() {
+	let metrics = Arc::new(Metrics::new());
+	tokio::spawn(metrics::serve(metrics, ([127, 0, 0, 1], 9191).into()));
+	let client = hyper::Client::new();
+	for _ in 0..50 {
+		if let Ok(res) = client.get("http://127.0.0.1:9191/metrics".parse().unwrap()).await {
+			assert_eq!(res.headers()["Content-Type"], "text/plain; version=0.0.4");
+			let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+			assert!(String::from_utf8(body.to_vec()).unwrap().contains("# TYPE rsmq_operations_total counter"));
+			return;
+		}
+		tokio::time::delay_for(Duration::from_millis(20)).await;
+	}
+	panic!("server didn't start");
+}
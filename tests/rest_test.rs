@@ -0,0 +1,48 @@
+#![cfg(feature = "rest")]
+
+use hyper::{Body, Method, Request, StatusCode};
+use rsmq::*;
+use serde_json::{json, Value};
+
+async fn call(backend: &MemoryBackend, method: Method, uri: &str, body: Value) -> (StatusCode, Value) {
+	let req = Request::builder().method(method).uri(uri).body(Body::from(body.to_string())).unwrap();
+	let res = rest::handle(backend, req).await;
+	let status = res.status();
+	let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+	(status, serde_json::from_slice(&bytes).unwrap())
+}
+
+#[tokio::test]
+async fn queue_routes() {
+	let backend = MemoryBackend::new();
+	assert_eq!(call(&backend, Method::POST, "/queues/jobs", json!({ "vt": 60, "maxsize": "1024" })).await, (StatusCode::OK, json!({ "result": 1 })));
+	let (status, err) = call(&backend, Method::POST, "/queues/jobs", json!({})).await;
+	assert_eq!((status, &err["name"]), (StatusCode::CONFLICT, &json!("queueExists")));
+	assert_eq!(call(&backend, Method::GET, "/queues", json!(null)).await.1, json!({ "queues": ["jobs"] }));
+
+	let (_, q) = call(&backend, Method::PUT, "/queues/jobs", json!({ "delay": 5 })).await;
+	assert_eq!((&q["vt"], &q["delay"], &q["maxsize"], &q["msgs"]), (&json!(60), &json!(5), &json!(1024), &json!(0)));
+	assert_eq!(call(&backend, Method::DELETE, "/queues/jobs", json!(null)).await.1, json!({ "result": 1 }));
+	let (status, err) = call(&backend, Method::GET, "/queues/jobs", json!(null)).await;
+	assert_eq!((status, &err["name"]), (StatusCode::NOT_FOUND, &json!("queueNotFound")));
+}
+
+#[tokio::test]
+async fn message_routes() {
+	let backend = MemoryBackend::new();
+	backend.create_queue(Queue::new("jobs", None, None, None)).await.unwrap();
+	let (status, sent) = call(&backend, Method::POST, "/messages/jobs", json!({ "message": "hello" })).await;
+	assert_eq!(status, StatusCode::OK);
+	let id = sent["id"].as_str().unwrap().to_string();
+
+	let (_, msg) = call(&backend, Method::GET, "/messages/jobs?vt=0", json!(null)).await;
+	assert_eq!((&msg["id"], &msg["message"], &msg["rc"]), (&json!(id), &json!("hello"), &json!(1)));
+	let uri = format!("/messages/jobs/{}", id);
+	assert_eq!(call(&backend, Method::PUT, &format!("{}?vt=60", uri), json!(null)).await.1, json!({ "result": 1 }));
+	assert_eq!(call(&backend, Method::GET, "/messages/jobs", json!(null)).await.1, json!({}));
+	assert_eq!(call(&backend, Method::DELETE, &uri, json!(null)).await.1, json!({ "result": 1 }));
+	assert_eq!(call(&backend, Method::DELETE, &uri, json!(null)).await.1, json!({ "result": 0 }));
+
+	let (status, err) = call(&backend, Method::POST, "/messages/jobs", json!({ "delay": 1 })).await;
+	assert_eq!((status, &err["name"]), (StatusCode::BAD_REQUEST, &json!("invalidValue")));
+}
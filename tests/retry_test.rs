@@ -0,0 +1,20 @@
+use rsmq::*;
+use std::time::Duration;
+
+#[test]
+fn backoff_delays() {
+	let ms = Duration::from_millis;
+	assert_eq!(Backoff::Fixed(ms(100)).delay(5), ms(100));
+
+	let exponential = Backoff::Exponential { initial: ms(100), max: ms(1000) };
+	let delays: Vec<_> = (0..6).map(|attempt| exponential.delay(attempt)).collect();
+	assert_eq!(delays, vec![ms(100), ms(200), ms(400), ms(800), ms(1000), ms(1000)]);
+	assert_eq!(exponential.delay(u32::MAX), ms(1000));
+
+	// Between half and all of the exponential delay.
+	let jitter = Backoff::ExponentialJitter { initial: ms(100), max: ms(1000) };
+	for attempt in 0..6 {
+		let delay = jitter.delay(attempt);
+		assert!(delay >= exponential.delay(attempt) / 2 && delay <= exponential.delay(attempt), "{:?}", delay);
+	}
+}
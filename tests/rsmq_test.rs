@@ -1,4 +1,5 @@
 use rsmq::*;
+use std::time::Duration;
 
 async fn setup(ns: &str) -> Rsmq {
 	let rsmq = Rsmq::new("redis://127.0.0.1/", ns).await.expect("Can't instantiate RSMQ");
@@ -130,4 +131,503 @@ async fn receive_message() {
 
 	assert_eq!(queue_stats_after.hiddenmsgs, 1); // reserving a message hides it from others for queue.vt seconds
 	assert_eq!(queue_stats_before.hiddenmsgs, 0);
-}
\ No newline at end of file
+}
+
+#[tokio::test]
+async fn message_ids_sort_in_send_order() {
+	let rsmq = setup("test-ns").await;
+	let qname = "ordered-ids-q";
+	rsmq.delete_queue(qname).await.expect("no queue deleted");
+	rsmq.create_queue(Queue::new(qname, None, None, None)).await.expect("no queue for you!");
+
+	let mut ids = Vec::new();
+	for i in 0..20 {
+		let id = rsmq.send_message(qname, &format!("message {}", i), None).await.expect("no, did not send that");
+		assert_eq!(id.len(), 32);
+		ids.push(id);
+	}
+	let mut sorted = ids.clone();
+	sorted.sort();
+	sorted.dedup();
+	assert_eq!(ids, sorted);
+
+	let first = rsmq.pop_message(qname).await.expect("no, did not pop that");
+	assert_eq!(first.id, ids[0]);
+	assert_eq!(first.message, "message 0");
+}
+
+#[tokio::test]
+async fn migrate_key_layout() {
+	let rsmq = Rsmq::new("redis://127.0.0.1/", "test-migrate-ns").await.expect("Can't instantiate RSMQ");
+	let qname = "migrate-me-q";
+	rsmq.delete_queue(qname).await.expect("no queue deleted");
+	rsmq.create_queue(Queue::new(qname, None, None, None)).await.expect("no queue for you!");
+	let consumer = rsmq.idempotent_consumer(qname, Duration::from_secs(60)).dedup_key(|m| m.message.clone());
+	rsmq.send_message(qname, "processed", Some(0)).await.expect("no, did not send that");
+	consumer.process(None, |m| async move { Ok(m.message) }).await.expect("no, did not process that");
+	let msg_id = rsmq.send_message(qname, "moving house", Some(0)).await.expect("no, did not send that");
+
+	let migrated = rsmq.migrate_key_layout(KeyLayout::HashTagged).await.expect("migration failed");
+	assert!(migrated.contains(&qname.to_string()));
+
+	let tagged = Rsmq::new("redis://127.0.0.1/", "test-migrate-ns").await.expect("Can't instantiate RSMQ").with_key_layout(KeyLayout::HashTagged);
+	assert_eq!(tagged.key_layout(), KeyLayout::HashTagged);
+	let attrs = tagged.get_queue_attributes(qname).await.expect("fetch queue stats failed");
+	assert_eq!(attrs.msgs, 1);
+	let popped = tagged.pop_message(qname).await.expect("no, did not pop that");
+	assert_eq!(popped.id, msg_id);
+
+	// The processed keys of idempotent consumers move with the queue.
+	tagged.send_message(qname, "processed", Some(0)).await.expect("no, did not send that");
+	let skipped: Option<()> = tagged.idempotent_consumer(qname, Duration::from_secs(60)).dedup_key(|m| m.message.clone()).process(None, |_| async { panic!("processed a duplicate") }).await.unwrap();
+	assert!(skipped.is_none());
+
+	tagged.migrate_key_layout(KeyLayout::Plain).await.expect("migration back failed");
+	rsmq.delete_queue(qname).await.expect("no queue deleted");
+}
+
+// Needs a Redis Cluster with a node on 127.0.0.1:7000, e.g. from redis' `utils/create-cluster`.
+#[tokio::test]
+#[ignore]
+async fn cluster_send_receive() {
+	let rsmq = Rsmq::new_cluster(vec!["redis://127.0.0.1:7000/"], "test-ns").await.expect("Can't connect to the cluster");
+	let qname = "cluster-q";
+	rsmq.delete_queue(qname).await.expect("no queue deleted");
+	rsmq.create_queue(Queue::new(qname, None, None, None)).await.expect("no queue for you!");
+	assert!(rsmq.list_queues().await.unwrap().contains(&qname.to_string()));
+
+	let msg_id = rsmq.send_message(qname, "clustered", Some(0)).await.expect("no, did not send that");
+	let received = rsmq.receive_message(qname, None).await.expect("no, did not receive that");
+	assert_eq!(received.id, msg_id);
+	assert!(rsmq.delete_message(qname, &msg_id).await.unwrap());
+	rsmq.delete_queue(qname).await.expect("no queue deleted");
+}
+
+// Needs the three master Redis Cluster of redis' `utils/create-cluster` on 127.0.0.1:7000-7002. The hash tags of the
+// queues are in slots 1269, 9895 and 13462, one on each master.
+#[tokio::test]
+#[ignore]
+async fn cluster_scripts_on_every_master() {
+	let rsmq = Rsmq::new_cluster(vec!["redis://127.0.0.1:7000/"], "test-ns").await.expect("Can't connect to the cluster");
+	let queues = ["cluster-b", "cluster-q", "cluster-a"];
+	for qname in &queues {
+		rsmq.delete_queue(qname).await.expect("no queue deleted");
+		rsmq.create_queue(Queue::new(qname, None, None, None)).await.expect("no queue for you!");
+		let msg_id = rsmq.send_message(qname, "preloaded", Some(0)).await.expect("no, did not send that");
+		assert_eq!(rsmq.pop_message(qname).await.expect("no, did not pop that").id, msg_id);
+	}
+
+	// Scripts reloaded after NOSCRIPT must reach the master of each queue too.
+	for port in 7000..7003 {
+		let mut con = redis::Client::open(format!("redis://127.0.0.1:{}/", port)).unwrap().get_async_connection().await.unwrap();
+		redis::cmd("SCRIPT").arg("FLUSH").query_async::<_, ()>(&mut con).await.expect("can't flush the scripts");
+	}
+	for qname in &queues {
+		let msg_id = rsmq.send_message(qname, "reloaded", Some(0)).await.expect("no, did not send that");
+		assert_eq!(rsmq.receive_message(qname, None).await.expect("no, did not receive that").id, msg_id);
+		rsmq.delete_queue(qname).await.expect("no queue deleted");
+	}
+}
+
+#[tokio::test]
+async fn builder_with_multiplexed_connection() {
+	let client = redis::Client::open("redis://127.0.0.1/").unwrap();
+	let (con, driver) = client.get_multiplexed_async_connection().await.expect("Can't connect to Redis");
+	tokio::spawn(driver);
+	let rsmq = RsmqBuilder::multiplexed(con)
+		.name_space("test-builder-ns")
+		.default_queue_options(Some(5), Some(0), Some(100))
+		.build()
+		.await
+		.expect("Can't instantiate RSMQ");
+
+	let q = rsmq.default_queue("builder-q");
+	assert_eq!((q.qname.as_str(), q.vt, q.delay, q.maxsize), ("builder-q", 5, 0, 100));
+	rsmq.delete_queue("builder-q").await.expect("no queue deleted");
+	rsmq.create_queue(q).await.expect("no queue for you!");
+	let attrs = rsmq.get_queue_attributes("builder-q").await.expect("fetch queue stats failed");
+	assert_eq!((attrs.vt, attrs.delay, attrs.maxsize), (5, 0, 100));
+	assert!(rsmq.send_message("builder-q", &"x".repeat(101), None).await.is_err());
+}
+
+#[tokio::test]
+async fn builder_realtime_publishes_queue_size() {
+	let rsmq = RsmqBuilder::new("redis://127.0.0.1/")
+		.name_space("test-rt-ns")
+		.max_size(2)
+		.connection_timeout(std::time::Duration::from_secs(5))
+		.realtime(true)
+		.build()
+		.await
+		.expect("Can't instantiate RSMQ");
+	rsmq.delete_queue("rt-q").await.expect("no queue deleted");
+	rsmq.create_queue(Queue::new("rt-q", None, None, None)).await.expect("no queue for you!");
+
+	let (tx, rx) = std::sync::mpsc::channel();
+	std::thread::spawn(move || {
+		let mut con = redis::Client::open("redis://127.0.0.1/").unwrap().get_connection().unwrap();
+		let mut pubsub = con.as_pubsub();
+		pubsub.subscribe("test-rt-ns:rt:rt-q").unwrap();
+		tx.send(0).unwrap();
+		let msg = pubsub.get_message().unwrap();
+		tx.send(msg.get_payload::<u64>().unwrap()).unwrap();
+	});
+	rx.recv().unwrap(); // subscribed
+	rsmq.send_message("rt-q", "realtime", None).await.expect("no, did not send that");
+	assert_eq!(rx.recv_timeout(std::time::Duration::from_secs(5)).unwrap(), 1);
+}
+
+#[tokio::test]
+async fn purge_queue() {
+	let rsmq = setup("test-ns").await;
+	let qname = "test-purge-q";
+	rsmq.delete_queue(qname).await.expect("no queue deleted");
+	rsmq.create_queue(rsmq::Queue::new(qname, None, None, None)).await.expect("can't create queue");
+	rsmq.send_message(qname, "one", None).await.expect("no, did not send that");
+	rsmq.send_message(qname, "two", None).await.expect("no, did not send that");
+	rsmq.receive_message(qname, None).await.expect("no, did not receive that");
+
+	assert_eq!(rsmq.purge_queue(qname).await.expect("purge failed"), 2);
+	let q = rsmq.get_queue_attributes(qname).await.expect("fetch queue stats failed");
+	assert_eq!((q.msgs, q.totalsent), (0, 2));
+	assert!(rsmq.pop_message(qname).await.is_err());
+}
+
+#[tokio::test]
+async fn builder_metrics_count_operations() {
+	let metrics = std::sync::Arc::new(metrics::Metrics::new());
+	let rsmq = RsmqBuilder::new("redis://127.0.0.1/").name_space("test-metrics").metrics(metrics.clone()).build().await.unwrap();
+	rsmq.delete_queue("test-q").await.unwrap();
+	rsmq.create_queue(Queue::new("test-q", None, None, None)).await.unwrap();
+	rsmq.send_message("test-q", "hello", None).await.unwrap();
+	rsmq.pop_message("test-q").await.unwrap();
+	assert!(rsmq.receive_message("test-q", None).await.is_err());
+	assert!(rsmq.send_message("no-such-q", "hello", None).await.is_err());
+
+	let text = metrics.render();
+	assert!(text.contains("rsmq_operations_total{operation=\"send\"} 2\n"), "{}", text);
+	assert!(text.contains("rsmq_empty_receives_total{operation=\"receive\"} 1\n"), "{}", text);
+	assert!(text.contains("rsmq_operation_errors_total{operation=\"send\",kind=\"queue_not_found\"} 1\n"), "{}", text);
+	assert!(text.contains("rsmq_operation_duration_seconds_count{operation=\"pop\"} 1\n"), "{}", text);
+	assert!(!text.contains("rsmq_pool_wait_seconds_count 0\n"), "{}", text);
+}
+
+#[tokio::test]
+async fn send_message_with_headers() {
+	let rsmq = setup("test-headers").await;
+	let context = TraceContext::new("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01").unwrap();
+	rsmq.send_message_with_headers("test-q", "with\nheaders", None, &context.headers()).await.unwrap();
+	rsmq.send_message("test-q", "plain", None).await.unwrap();
+
+	let msg = rsmq.receive_message("test-q", None).await.unwrap();
+	assert_eq!((msg.message.as_str(), msg.trace_context()), ("with\nheaders", Some(context)));
+	let msg = rsmq.receive_message("test-q", None).await.unwrap();
+	assert_eq!((msg.message.as_str(), msg.headers.len()), ("plain", 0));
+
+	let mut headers = Headers::new();
+	headers.insert("bad name".into(), "value".into());
+	assert!(rsmq.send_message_with_headers("test-q", "hello", None, &headers).await.is_err());
+}
+
+#[cfg(any(feature = "zstd", feature = "gzip"))]
+#[tokio::test]
+async fn builder_compression() {
+	#[cfg(feature = "zstd")]
+	let compression = Compression::Zstd(3);
+	#[cfg(not(feature = "zstd"))]
+	let compression = Compression::Gzip(6);
+	let rsmq = RsmqBuilder::new("redis://127.0.0.1/").name_space("test-compression").compression(compression, 100).build().await.unwrap();
+	rsmq.delete_queue("test-q").await.unwrap();
+	rsmq.create_queue(Queue::new("test-q", None, None, Some(1024))).await.unwrap();
+
+	// Too long for the queue unless it's compressed.
+	let large = format!("[{}]", vec!["{\"id\": 1, \"name\": \"compressible\"}"; 100].join(","));
+	let large_id = rsmq.send_message("test-q", &large, None).await.unwrap();
+	let small_id = rsmq.send_message("test-q", "small", None).await.unwrap();
+
+	let client = redis::Client::open("redis://127.0.0.1/").unwrap();
+	let mut con = client.get_async_connection().await.unwrap();
+	let stored: (String, String) = redis::cmd("HMGET").arg("test-compression:test-q:Q").arg(&large_id).arg(&small_id).query_async(&mut con).await.unwrap();
+	assert!(stored.0.len() < large.len() && stored.0.contains("rsmq-content-encoding:"));
+	assert_eq!(stored.1, "small");
+
+	let msg = rsmq.receive_message("test-q", None).await.unwrap();
+	assert_eq!((msg.message, msg.headers.len()), (large, 0));
+	assert_eq!(rsmq.pop_message("test-q").await.unwrap().message, "small");
+}
+
+#[cfg(feature = "aes-gcm")]
+#[tokio::test]
+async fn builder_encryption() {
+	let old = Encryption::new().add_key("old", Cipher::Aes256Gcm, &[1; 32]).unwrap().encrypt_with("old").unwrap();
+	let new = old.clone().add_key("new", Cipher::Aes256Gcm, &[2; 32]).unwrap().encrypt_with("new").unwrap().encrypt_queue_with("test-plain", None).unwrap();
+	let before = RsmqBuilder::new("redis://127.0.0.1/").name_space("test-encryption").encryption(old).build().await.unwrap();
+	let after = RsmqBuilder::new("redis://127.0.0.1/").name_space("test-encryption").encryption(new).build().await.unwrap();
+	let plain = Rsmq::new("redis://127.0.0.1/", "test-encryption").await.unwrap();
+	for qname in &["test-q", "test-plain"] {
+		plain.delete_queue(qname).await.unwrap();
+		plain.create_queue(Queue::new(qname, None, None, None)).await.unwrap();
+	}
+
+	// Messages encrypted with a rotated key can still be read.
+	let id = before.send_message("test-q", "secret", None).await.unwrap();
+	after.send_message("test-q", "also secret", None).await.unwrap();
+	let client = redis::Client::open("redis://127.0.0.1/").unwrap();
+	let mut con = client.get_async_connection().await.unwrap();
+	let stored: String = redis::cmd("HGET").arg("test-encryption:test-q:Q").arg(&id).query_async(&mut con).await.unwrap();
+	assert!(stored.contains("rsmq-key-id:old\n") && !stored.contains("secret"), "{}", stored);
+	// Clients without the keys fail to read encrypted messages.
+	assert!(plain.receive_message("test-q", Some(0)).await.is_err());
+	assert_eq!(after.receive_message("test-q", None).await.unwrap().message, "secret");
+	assert_eq!(after.receive_message("test-q", None).await.unwrap().message, "also secret");
+
+	after.send_message("test-plain", "public", None).await.unwrap();
+	assert_eq!(plain.receive_message("test-plain", None).await.unwrap().message, "public");
+}
+
+#[tokio::test]
+async fn builder_claim_check() {
+	let dir = std::env::temp_dir().join(format!("rsmq-claim-check-{}", std::process::id()));
+	let store = std::sync::Arc::new(FsBlobStore::new(&dir).unwrap());
+	let rsmq = RsmqBuilder::new("redis://127.0.0.1/").name_space("test-claim-check").claim_check(store.clone(), 100).build().await.unwrap();
+	rsmq.delete_queue("test-q").await.unwrap();
+	rsmq.create_queue(Queue::new("test-q", None, None, Some(1024))).await.unwrap();
+
+	// Too long for the queue, but only the reference is stored.
+	let large = "x".repeat(10_000);
+	let large_id = rsmq.send_message("test-q", &large, None).await.unwrap();
+	let small_id = rsmq.send_message("test-q", "small", None).await.unwrap();
+	let popped_id = rsmq.send_message("test-q", &large, None).await.unwrap();
+
+	let client = redis::Client::open("redis://127.0.0.1/").unwrap();
+	let mut con = client.get_async_connection().await.unwrap();
+	let stored: (String, String) = redis::cmd("HMGET").arg("test-claim-check:test-q:Q").arg(&large_id).arg(&small_id).query_async(&mut con).await.unwrap();
+	assert!(stored.0.len() < 100 && stored.0.contains("rsmq-claim-check:"));
+	assert_eq!(stored.1, "small");
+	assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 2);
+
+	let msg = rsmq.receive_message("test-q", None).await.unwrap();
+	assert_eq!((msg.id.as_str(), msg.message.as_str(), msg.headers.len()), (large_id.as_str(), large.as_str(), 0));
+	assert!(rsmq.delete_message("test-q", &large_id).await.unwrap());
+	assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+
+	assert!(rsmq.delete_message("test-q", &small_id).await.unwrap());
+	let msg = rsmq.pop_message("test-q").await.unwrap();
+	assert_eq!((msg.id, msg.message), (popped_id, large));
+	assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+
+	// Nothing is left behind when sending fails.
+	assert!(rsmq.send_message("test-missing", &"x".repeat(1000), None).await.is_err());
+	assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+	std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(feature = "signing")]
+#[tokio::test]
+async fn builder_signing() {
+	let keys = Signing::new().add_key("orders", &[1; 32]).unwrap().add_key("billing", &[2; 32]).unwrap();
+	let orders = RsmqBuilder::new("redis://127.0.0.1/").name_space("test-signing").signing(keys.clone().sign_with("orders").unwrap()).build().await.unwrap();
+	let billing = RsmqBuilder::new("redis://127.0.0.1/").name_space("test-signing").signing(keys.clone().sign_with("billing").unwrap()).build().await.unwrap();
+	let verify = keys.clone().verify_queue_with("test-q", Some(&["orders"])).unwrap();
+	let strict = RsmqBuilder::new("redis://127.0.0.1/").name_space("test-signing").signing(verify.clone()).build().await.unwrap();
+	let quarantining = RsmqBuilder::new("redis://127.0.0.1/").name_space("test-signing").signing(verify.quarantine("test-rejected")).build().await.unwrap();
+	let plain = Rsmq::new("redis://127.0.0.1/", "test-signing").await.unwrap();
+	for qname in &["test-q", "test-rejected"] {
+		plain.delete_queue(qname).await.unwrap();
+		plain.create_queue(Queue::new(qname, None, None, None)).await.unwrap();
+	}
+
+	let id = orders.send_message("test-q", "signed", None).await.unwrap();
+	let msg = strict.receive_message("test-q", None).await.unwrap();
+	assert_eq!((msg.id.as_str(), msg.message.as_str(), msg.headers.len()), (id.as_str(), "signed", 0));
+	strict.delete_message("test-q", &id).await.unwrap();
+
+	// Tampered, unsigned and untrusted messages are rejected.
+	let id = orders.send_message("test-q", "signed", None).await.unwrap();
+	let client = redis::Client::open("redis://127.0.0.1/").unwrap();
+	let mut con = client.get_async_connection().await.unwrap();
+	let stored: String = redis::cmd("HGET").arg("test-signing:test-q:Q").arg(&id).query_async(&mut con).await.unwrap();
+	let _: () = redis::cmd("HSET").arg("test-signing:test-q:Q").arg(&id).arg(stored.replace("signed", "forged")).query_async(&mut con).await.unwrap();
+	let err = strict.receive_message("test-q", None).await.unwrap_err();
+	assert!(err.to_string().contains("has an invalid signature"), "{}", err);
+	plain.delete_message("test-q", &id).await.unwrap();
+
+	plain.send_message("test-q", "unsigned", None).await.unwrap();
+	assert!(strict.pop_message("test-q").await.unwrap_err().to_string().contains("is not signed"));
+	billing.send_message("test-q", "untrusted", None).await.unwrap();
+
+	// Quarantined messages are moved, with the reason, and the next message is received.
+	let id = orders.send_message("test-q", "trusted", None).await.unwrap();
+	let msg = quarantining.receive_message("test-q", None).await.unwrap();
+	assert_eq!((msg.id, msg.message), (id, "trusted".to_string()));
+	let rejected = quarantining.pop_message("test-rejected").await.unwrap();
+	assert_eq!(rejected.message, "untrusted");
+	assert!(rejected.headers["rsmq-quarantine-reason"].contains("untrusted key \"billing\""));
+	assert_eq!(plain.get_queue_attributes("test-q").await.unwrap().msgs, 1);
+
+	// Dead lettered messages are verified as messages of the queue they came from.
+	let dead_lettering = RsmqBuilder::new("redis://127.0.0.1/").name_space("test-signing").signing(keys.sign_with("orders").unwrap()).dead_letter_queue("test-dlq").build().await.unwrap();
+	plain.delete_queue("test-dlq").await.unwrap();
+	plain.create_queue(Queue::new("test-dlq", None, None, None)).await.unwrap();
+	plain.purge_queue("test-q").await.unwrap();
+	dead_lettering.send_message("test-q", "poison", None).await.unwrap();
+	assert!(dead_lettering.receive_delivery("test-q", None).await.unwrap().dead_letter().await.unwrap());
+	let msg = dead_lettering.receive_message("test-dlq", None).await.unwrap();
+	assert_eq!((msg.message.as_str(), msg.headers["rsmq-dead-lettered-from"].as_str()), ("poison", "test-q"));
+	dead_lettering.delete_message("test-dlq", &msg.id).await.unwrap();
+	plain.send_message("test-dlq", "forged", None).await.unwrap();
+	let err = dead_lettering.receive_message("test-dlq", None).await.unwrap_err();
+	assert!(err.to_string().contains("was not dead lettered"), "{}", err);
+}
+
+#[cfg(all(feature = "json", feature = "msgpack"))]
+#[tokio::test]
+async fn send_encoded() {
+	let rsmq = setup("test-codec").await;
+	// A queue moving from JSON to MessagePack.
+	rsmq.send_encoded("test-q", &Json, &("order-1", 1), None).await.unwrap();
+	rsmq.send_encoded("test-q", &MessagePack, &("order-2", 2), None).await.unwrap();
+
+	let msg = rsmq.pop_message("test-q").await.unwrap();
+	assert_eq!((msg.message.as_str(), msg.codec()), (r#"["order-1",1]"#, Some("json")));
+	assert_eq!(msg.decode::<(String, u32)>().unwrap(), ("order-1".to_string(), 1));
+	let msg = rsmq.pop_message("test-q").await.unwrap();
+	assert_eq!(msg.codec(), Some("msgpack"));
+	assert_eq!(msg.decode::<(String, u32)>().unwrap(), ("order-2".to_string(), 2));
+
+	// The codec header is reserved.
+	let headers = vec![("rsmq-codec".to_string(), "json".to_string())].into_iter().collect();
+	assert!(rsmq.send_message_with_headers("test-q", "[]", None, &headers).await.is_err());
+}
+
+#[cfg(feature = "json")]
+#[tokio::test]
+async fn typed_queue() {
+	#[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+	struct OrderCreated {
+		id: u64,
+		items: Vec<String>,
+	}
+
+	let rsmq = setup("test-typed").await;
+	let orders = rsmq.typed_queue::<OrderCreated>("test-q");
+	assert_eq!(orders.qname(), "test-q");
+	let order = OrderCreated { id: 1, items: vec!["book".into()] };
+	let id = orders.send(&order, None).await.unwrap();
+
+	let received = orders.receive(None).await.unwrap();
+	assert_eq!((received.id.as_str(), &received.value, received.rc), (id.as_str(), &order, 1));
+	assert!(orders.change_visibility(&id, 0).await.is_ok());
+	assert_eq!(orders.receive(None).await.unwrap().rc, 2);
+	assert!(orders.delete(&id).await.unwrap());
+
+	// Messages that aren't orders fail to decode.
+	rsmq.send_message("test-q", r#"{"id": "one"}"#, None).await.unwrap();
+	assert!(orders.pop().await.is_err());
+	// They stay in the queue instead of being lost.
+	assert_eq!(rsmq.get_queue_attributes("test-q").await.unwrap().msgs, 1);
+}
+
+#[tokio::test]
+async fn receive_delivery() {
+	let rsmq = RsmqBuilder::new("redis://127.0.0.1/").name_space("test-delivery").dead_letter_queue("test-dlq").build().await.unwrap();
+	for qname in &["test-q", "test-dlq"] {
+		rsmq.delete_queue(qname).await.unwrap();
+		rsmq.create_queue(Queue::new(qname, None, None, None)).await.unwrap();
+	}
+	let id = rsmq.send_message("test-q", "job", None).await.unwrap();
+
+	let mut delivery = rsmq.receive_delivery("test-q", None).await.unwrap();
+	assert_eq!((delivery.qname(), delivery.id.as_str(), delivery.message.as_str()), ("test-q", id.as_str(), "job"));
+	delivery.extend(60).await.unwrap();
+	assert!(delivery.nack(0).await.is_ok());
+
+	let delivery = rsmq.receive_delivery("test-q", None).await.unwrap();
+	assert_eq!(delivery.rc, 2);
+	assert!(delivery.dead_letter().await.unwrap());
+	let dead = rsmq.pop_message("test-dlq").await.unwrap();
+	assert_eq!((dead.message.as_str(), dead.headers["rsmq-dead-lettered-from"].as_str()), ("job", "test-q"));
+
+	rsmq.send_message("test-q", "job", None).await.unwrap();
+	let delivery = rsmq.receive_delivery("test-q", None).await.unwrap();
+	assert!(delivery.ack().await.unwrap());
+	assert_eq!(rsmq.get_queue_attributes("test-q").await.unwrap().msgs, 0);
+}
+
+// Dropping an unsettled delivery leaves the message hidden until its visibility timeout expires.
+#[tokio::test]
+async fn delivery_dropped_without_ack() {
+	let rsmq = setup("test-delivery-dropped").await;
+	let id = rsmq.send_message("test-q", "job", None).await.unwrap();
+	drop(rsmq.receive_delivery("test-q", Some(1)).await.unwrap());
+	assert!(rsmq.receive_message("test-q", None).await.is_err());
+	tokio::time::delay_for(Duration::from_millis(1100)).await;
+	assert_eq!(rsmq.receive_message("test-q", None).await.unwrap().id, id);
+}
+
+fn now_ms() -> u64 { std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_millis() as u64 }
+
+#[tokio::test]
+async fn nack_message_backs_off() {
+	let rsmq = setup("test-nack").await;
+	let backoff = Backoff::Exponential { initial: Duration::from_secs(10), max: Duration::from_secs(25) };
+	let id = rsmq.send_message("test-q", "job", None).await.unwrap();
+
+	// The delay doubles with every receive, up to the maximum.
+	let mut expected = vec![10_000, 20_000, 25_000].into_iter();
+	for _ in 0..3 {
+		let msg = rsmq.receive_message("test-q", None).await.unwrap();
+		let before = now_ms();
+		let visible_at = rsmq.nack_message("test-q", &msg.id, &backoff).await.unwrap();
+		let delay = visible_at - before;
+		let want = expected.next().unwrap();
+		assert!((want - 1000..=want + 1000).contains(&delay), "{} {}", delay, want);
+		assert!(rsmq.receive_message("test-q", None).await.is_err());
+		rsmq.change_message_visibility("test-q", &id, 0).await.unwrap();
+	}
+
+	let delivery = rsmq.receive_delivery("test-q", None).await.unwrap();
+	assert_eq!(delivery.rc, 4);
+	let jitter = Backoff::ExponentialJitter { initial: Duration::from_secs(1), max: Duration::from_secs(30) };
+	let before = now_ms();
+	let delay = delivery.nack_with(&jitter).await.unwrap() - before;
+	assert!((3000..=9000).contains(&delay), "{}", delay);
+}
+
+#[tokio::test]
+async fn idempotent_consumer() {
+	let rsmq = setup("test-idempotent").await;
+	let consumer = rsmq.idempotent_consumer("test-q", Duration::from_secs(60)).dedup_key(|m| m.message.clone());
+	rsmq.send_message("test-q", "job-1", None).await.unwrap();
+	assert_eq!(consumer.process(None, |m| async move { Ok(m.message) }).await.unwrap().as_deref(), Some("job-1"));
+	assert_eq!(rsmq.get_queue_attributes("test-q").await.unwrap().msgs, 0);
+
+	// A duplicate is deleted without processing it.
+	rsmq.send_message("test-q", "job-1", None).await.unwrap();
+	let skipped: Option<()> = consumer.process(None, |_| async { panic!("processed a duplicate") }).await.unwrap();
+	assert!(skipped.is_none());
+	assert_eq!(rsmq.get_queue_attributes("test-q").await.unwrap().msgs, 0);
+
+	// A failed message is left in the queue, to be processed again.
+	let id = rsmq.send_message("test-q", "job-2", None).await.unwrap();
+	assert!(consumer.process(None, |_| async { Err::<(), _>(failure::format_err!("failed")) }).await.is_err());
+	rsmq.change_message_visibility("test-q", &id, 0).await.unwrap();
+	assert_eq!(consumer.process(None, |m| async move { Ok(m.rc) }).await.unwrap(), Some(2));
+}
+
+#[tokio::test]
+async fn idempotent_consumer_metrics_count_deleted_messages() {
+	let metrics = std::sync::Arc::new(metrics::Metrics::new());
+	let rsmq = RsmqBuilder::new("redis://127.0.0.1/").name_space("test-idempotent-metrics").metrics(metrics.clone()).build().await.unwrap();
+	rsmq.delete_queue("test-q").await.unwrap();
+	rsmq.create_queue(Queue::new("test-q", None, None, None)).await.unwrap();
+	let consumer = rsmq.idempotent_consumer("test-q", Duration::from_secs(60)).dedup_key(|m| m.message.clone());
+	rsmq.send_message("test-q", "job-1", None).await.unwrap();
+	rsmq.send_message("test-q", "job-1", None).await.unwrap();
+	consumer.process(None, |m| async move { Ok(m.message) }).await.unwrap();
+	consumer.process(None, |m| async move { Ok(m.message) }).await.unwrap();
+
+	// One delete for the processed message and one for the duplicate.
+	let text = metrics.render();
+	assert!(text.contains("rsmq_operations_total{operation=\"delete\"} 2\n"), "{}", text);
+}
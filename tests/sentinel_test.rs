@@ -0,0 +1,120 @@
+use rsmq::*;
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+
+// A master on `port`, a replica on `port + 1` and a sentinel on `port + 20000` watching them, killed on drop.
+struct SentinelSetup {
+	processes: Vec<Child>,
+	dir: std::path::PathBuf,
+}
+
+impl SentinelSetup {
+	async fn spawn(port: u16, password: Option<&str>) -> SentinelSetup {
+		let dir = std::env::temp_dir().join(format!("rsmq-sentinel-{}-{}", std::process::id(), port));
+		std::fs::create_dir_all(&dir).expect("can't create the sentinel dir");
+		let conf = dir.join("sentinel.conf");
+		let mut sentinel_conf = format!(
+			"port {}\n\
+			 sentinel monitor rsmq-master 127.0.0.1 {} 1\n\
+			 sentinel down-after-milliseconds rsmq-master 1000\n\
+			 sentinel failover-timeout rsmq-master 5000\n",
+			port + 20000,
+			port
+		);
+		if let Some(password) = password {
+			sentinel_conf.push_str(&format!("sentinel auth-pass rsmq-master {}\n", password));
+		}
+		std::fs::write(&conf, sentinel_conf).expect("can't write sentinel.conf");
+
+		let spawn = |args: &[&str]| {
+			Command::new("redis-server")
+				.args(args)
+				.current_dir(&dir)
+				.stdout(Stdio::null())
+				.spawn()
+				.expect("redis-server must be on the PATH")
+		};
+		let (master, replica) = (port.to_string(), (port + 1).to_string());
+		let mut auth = Vec::new();
+		if let Some(password) = password {
+			auth = vec!["--requirepass", password, "--masterauth", password];
+		}
+		let processes = vec![
+			spawn(&[&["--port", &master, "--save", "", "--appendonly", "no"], &auth[..]].concat()),
+			spawn(&[&["--port", &replica, "--save", "", "--appendonly", "no", "--replicaof", "127.0.0.1", &master], &auth[..]].concat()),
+			spawn(&[conf.to_str().unwrap(), "--sentinel"]),
+		];
+		tokio::time::delay_for(Duration::from_secs(2)).await;
+		SentinelSetup { processes, dir }
+	}
+}
+
+impl Drop for SentinelSetup {
+	fn drop(&mut self) {
+		for p in &mut self.processes {
+			let _ = p.kill();
+			let _ = p.wait();
+		}
+		let _ = std::fs::remove_dir_all(&self.dir);
+	}
+}
+
+async fn master_port(manager: &SentinelConnectionManager) -> u16 { manager.master_addr().await.expect("no master").1 }
+
+// Spawns its own redis-server and sentinel processes; needs `redis-server` on the PATH.
+#[tokio::test]
+#[ignore]
+async fn sentinel_failover() {
+	let _setup = SentinelSetup::spawn(6390, None).await;
+	let sentinels = vec!["redis://127.0.0.1:26390/"];
+	let manager = SentinelConnectionManager::new("rsmq-master", sentinels.clone()).expect("bad sentinel config");
+	assert_eq!(master_port(&manager).await, 6390);
+
+	let rsmq = Rsmq::new_sentinel("rsmq-master", sentinels.clone(), "test-ns").await.expect("Can't instantiate RSMQ");
+	let qname = "sentinel-q";
+	rsmq.create_queue(Queue::new(qname, None, None, None)).await.expect("no queue for you!");
+	let before = rsmq.send_message(qname, "before failover", Some(0)).await.expect("no, did not send that");
+
+	// Give the replica a moment to catch up, then promote it.
+	tokio::time::delay_for(Duration::from_secs(1)).await;
+	let mut sentinel = redis::Client::open("redis://127.0.0.1:26390/").unwrap().get_async_connection().await.unwrap();
+	redis::cmd("SENTINEL").arg("FAILOVER").arg("rsmq-master").query_async::<_, ()>(&mut sentinel).await.expect("failover refused");
+	let started = Instant::now();
+	while master_port(&manager).await != 6391 {
+		assert!(started.elapsed() < Duration::from_secs(20), "the failover did not happen");
+		tokio::time::delay_for(Duration::from_millis(200)).await;
+	}
+
+	// The same Rsmq keeps working: connections to the old master are replaced by connections to the new one.
+	let mut sent = None;
+	while sent.is_none() {
+		assert!(started.elapsed() < Duration::from_secs(30), "Rsmq did not follow the new master");
+		sent = rsmq.send_message(qname, "after failover", Some(0)).await.ok();
+	}
+	let first = rsmq.pop_message(qname).await.expect("no, did not pop that");
+	assert_eq!(first.id, before);
+	let second = rsmq.pop_message(qname).await.expect("no, did not pop that");
+	assert_eq!(Some(second.id), sent);
+}
+
+// Spawns its own redis-server and sentinel processes; needs `redis-server` on the PATH.
+#[tokio::test]
+#[ignore]
+async fn sentinel_db_and_password() {
+	let _setup = SentinelSetup::spawn(6392, Some("s3cret")).await;
+	let rsmq = RsmqBuilder::sentinel("rsmq-master", vec!["redis://127.0.0.1:26392/"])
+		.sentinel_db(3)
+		.sentinel_master_password(Some("s3cret".into()))
+		.name_space("test-ns")
+		.build()
+		.await
+		.expect("Can't instantiate RSMQ");
+	let qname = "sentinel-db-q";
+	rsmq.create_queue(Queue::new(qname, None, None, None)).await.expect("no queue for you!");
+	let sent = rsmq.send_message(qname, "in db 3", Some(0)).await.expect("no, did not send that");
+
+	let mut master = redis::Client::open("redis://:s3cret@127.0.0.1:6392/3").unwrap().get_async_connection().await.unwrap();
+	let queues: Vec<String> = redis::cmd("SMEMBERS").arg("test-ns:QUEUES").query_async(&mut master).await.unwrap();
+	assert_eq!(queues, vec![qname.to_string()]);
+	assert_eq!(rsmq.pop_message(qname).await.expect("no, did not pop that").id, sent);
+}
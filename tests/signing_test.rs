@@ -0,0 +1,17 @@
+#![cfg(feature = "signing")]
+
+use rsmq::*;
+
+#[test]
+fn signing_keys() {
+	assert!(Signing::new().add_key("short", &[1; 16]).is_err());
+	assert!(Signing::new().add_key("with space", &[1; 32]).is_err());
+	let signing = Signing::new().add_key("a", &[1; 32]).unwrap();
+	assert!(signing.clone().sign_with("b").is_err());
+	assert!(signing.clone().sign_queue_with("q", Some("b")).is_err());
+	assert!(signing.clone().verify_queue_with("q", Some(&["a", "b"])).is_err());
+	assert!(signing.clone().sign_queue_with("q", None).unwrap().verify_queue_with("q", Some(&["a"])).is_ok());
+	// Keys don't show up in debug output.
+	let signing = signing.sign_with("a").unwrap();
+	assert!(!format!("{:?}", signing).contains("[1, 1"));
+}
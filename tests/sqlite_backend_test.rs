@@ -0,0 +1,66 @@
+#![cfg(feature = "sqlite")]
+
+use rsmq::*;
+use std::time::Duration;
+
+#[tokio::test]
+async fn messages_survive_reopening() {
+	let path = std::env::temp_dir().join(format!("rsmq-sqlite-{}.db", std::process::id()));
+	let _ = std::fs::remove_file(&path);
+	let id = {
+		let backend = SqliteBackend::open(&path).unwrap();
+		assert_eq!(backend.create_queue(Queue::new("test-q", None, None, None)).await.unwrap(), 1);
+		backend.send_message("test-q", "hello", None).await.unwrap()
+	};
+
+	let backend = SqliteBackend::open(&path).unwrap();
+	assert_eq!(backend.create_queue(Queue::new("test-q", None, None, None)).await.unwrap(), 0);
+	let msg = backend.pop_message("test-q").await.unwrap();
+	assert_eq!((msg.id.as_str(), msg.message.as_str(), msg.rc), (id.as_str(), "hello", 1));
+	let q = backend.get_queue_attributes("test-q").await.unwrap();
+	assert_eq!((q.msgs, q.totalsent, q.totalrecv), (0, 1, 1));
+	let _ = std::fs::remove_file(&path);
+}
+
+#[tokio::test]
+async fn receive_hides_and_counts() {
+	let backend = SqliteBackend::open_in_memory().unwrap();
+	backend.create_queue(Queue::new("test-q", Some(1), None, None)).await.unwrap();
+	let first = backend.send_message("test-q", "first", None).await.unwrap();
+	let second = backend.send_message("test-q", "second", Some(60)).await.unwrap();
+
+	let msg = backend.receive_message("test-q", None).await.unwrap();
+	assert_eq!((msg.id.as_str(), msg.rc), (first.as_str(), 1));
+	let err = backend.receive_message("test-q", None).await.unwrap_err();
+	assert_eq!(err.downcast::<redis::RedisError>().unwrap().kind(), redis::ErrorKind::TryAgain);
+
+	std::thread::sleep(Duration::from_millis(1100));
+	let again = backend.receive_message("test-q", Some(60)).await.unwrap();
+	assert_eq!((again.id.as_str(), again.rc, again.fr), (first.as_str(), 2, msg.fr));
+	backend.change_message_visibility("test-q", &second, 0).await.unwrap();
+	assert_eq!(backend.receive_message("test-q", None).await.unwrap().id, second);
+	assert!(backend.delete_message("test-q", &first).await.unwrap());
+	assert!(!backend.delete_message("test-q", &first).await.unwrap());
+
+	let q = backend.get_queue_attributes("test-q").await.unwrap();
+	assert_eq!((q.msgs, q.hiddenmsgs, q.totalsent, q.totalrecv), (1, 1, 2, 3));
+}
+
+#[tokio::test]
+async fn queue_errors_and_attributes() {
+	let backend = SqliteBackend::open_in_memory().unwrap();
+	let err = backend.send_message("nope", "hello", None).await.unwrap_err();
+	assert_eq!(err.downcast::<redis::RedisError>().unwrap().detail(), Some("Queue not found"));
+
+	backend.create_queue(Queue::new("test-q", None, None, None)).await.unwrap();
+	let q = backend.set_queue_attributes("test-q", None, Some(5), Some(10)).await.unwrap();
+	assert_eq!((q.vt, q.delay, q.maxsize), (30, 5, 10));
+	let err = backend.send_message("test-q", "much too long", None).await.unwrap_err();
+	assert_eq!(err.downcast::<redis::RedisError>().unwrap().detail(), Some("Message is too long"));
+
+	backend.send_message("test-q", "short", None).await.unwrap();
+	backend.delete_queue("test-q").await.unwrap();
+	assert!(backend.list_queues().await.unwrap().is_empty());
+	backend.create_queue(Queue::new("test-q", None, None, None)).await.unwrap();
+	assert_eq!(backend.get_queue_attributes("test-q").await.unwrap().msgs, 0);
+}
@@ -0,0 +1,93 @@
+#![cfg(feature = "sqs")]
+
+use hyper::{Body, Request, StatusCode};
+use rsmq::*;
+use serde_json::{json, Value};
+
+async fn call(backend: &MemoryBackend, action: &str, params: Value) -> (StatusCode, Value) {
+	let req = Request::post("/")
+		.header("Host", "localhost:9324")
+		.header("X-Amz-Target", format!("AmazonSQS.{}", action))
+		.header("Content-Type", "application/x-amz-json-1.0")
+		.body(Body::from(params.to_string()))
+		.unwrap();
+	let res = sqs::handle(backend, req).await;
+	let status = res.status();
+	let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+	(status, serde_json::from_slice(&bytes).unwrap())
+}
+
+async fn call_query(backend: &MemoryBackend, path: &str, form: &str) -> (StatusCode, String) {
+	let req = Request::post(path).header("Host", "localhost:9324").header("Content-Type", "application/x-www-form-urlencoded").body(Body::from(form.to_string())).unwrap();
+	let res = sqs::handle(backend, req).await;
+	let status = res.status();
+	let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+	(status, String::from_utf8(bytes.to_vec()).unwrap())
+}
+
+const URL: &str = "http://localhost:9324/000000000000/jobs";
+
+#[tokio::test]
+async fn json_protocol() {
+	let backend = MemoryBackend::new();
+	let (_, created) = call(&backend, "CreateQueue", json!({ "QueueName": "jobs", "Attributes": { "VisibilityTimeout": "60" } })).await;
+	assert_eq!(created, json!({ "QueueUrl": URL }));
+
+	let (_, sent) = call(&backend, "SendMessage", json!({ "QueueUrl": URL, "MessageBody": "hello" })).await;
+	assert_eq!(sent["MD5OfMessageBody"], json!("5d41402abc4b2a76b9719d911017c592"));
+	let (_, received) = call(&backend, "ReceiveMessage", json!({ "QueueUrl": URL, "MaxNumberOfMessages": 10, "AttributeNames": ["All"] })).await;
+	let msg = &received["Messages"][0];
+	assert_eq!((&msg["MessageId"], &msg["Body"], &msg["Attributes"]["ApproximateReceiveCount"]), (&sent["MessageId"], &json!("hello"), &json!("1")));
+	assert_eq!(received["Messages"].as_array().unwrap().len(), 1);
+
+	let (_, attrs) = call(&backend, "GetQueueAttributes", json!({ "QueueUrl": URL, "AttributeNames": ["VisibilityTimeout", "ApproximateNumberOfMessagesNotVisible"] })).await;
+	assert_eq!(attrs, json!({ "Attributes": { "VisibilityTimeout": "60", "ApproximateNumberOfMessagesNotVisible": "1" } }));
+	let handle = msg["ReceiptHandle"].clone();
+	assert_eq!(call(&backend, "DeleteMessage", json!({ "QueueUrl": URL, "ReceiptHandle": handle })).await, (StatusCode::OK, json!({})));
+
+	let (status, err) = call(&backend, "SendMessage", json!({ "QueueUrl": "http://localhost:9324/000000000000/nope", "MessageBody": "hello" })).await;
+	assert_eq!((status, &err["__type"]), (StatusCode::BAD_REQUEST, &json!("com.amazonaws.sqs#QueueDoesNotExist")));
+}
+
+#[tokio::test]
+async fn batches() {
+	let backend = MemoryBackend::new();
+	backend.create_queue(Queue::new("jobs", None, None, Some(5))).await.unwrap();
+	let entries = json!([{ "Id": "a", "MessageBody": "one" }, { "Id": "b", "MessageBody": "too long" }, { "Id": "c", "MessageBody": "two", "DelaySeconds": 0 }]);
+	let (_, sent) = call(&backend, "SendMessageBatch", json!({ "QueueUrl": URL, "Entries": entries })).await;
+	assert_eq!(sent["Successful"].as_array().unwrap().len(), 2);
+	assert_eq!((&sent["Failed"][0]["Id"], &sent["Failed"][0]["SenderFault"]), (&json!("b"), &json!(true)));
+
+	let (_, received) = call(&backend, "ReceiveMessage", json!({ "QueueUrl": URL, "MaxNumberOfMessages": 10 })).await;
+	let handles: Vec<Value> = received["Messages"].as_array().unwrap().iter().enumerate().map(|(i, m)| json!({ "Id": i.to_string(), "ReceiptHandle": m["ReceiptHandle"], "VisibilityTimeout": 0 })).collect();
+	assert_eq!(handles.len(), 2);
+	let (_, changed) = call(&backend, "ChangeMessageVisibilityBatch", json!({ "QueueUrl": URL, "Entries": handles })).await;
+	assert_eq!(changed["Successful"].as_array().unwrap().len(), 2);
+	let (_, deleted) = call(&backend, "DeleteMessageBatch", json!({ "QueueUrl": URL, "Entries": handles })).await;
+	assert_eq!(deleted["Successful"].as_array().unwrap().len(), 2);
+	assert_eq!(backend.get_queue_attributes("jobs").await.unwrap().msgs, 0);
+
+	let (status, err) = call(&backend, "DeleteMessageBatch", json!({ "QueueUrl": URL, "Entries": [] })).await;
+	assert_eq!((status, &err["__type"]), (StatusCode::BAD_REQUEST, &json!("com.amazonaws.sqs#EmptyBatchRequest")));
+}
+
+#[tokio::test]
+async fn query_protocol() {
+	let backend = MemoryBackend::new();
+	let (status, xml) = call_query(&backend, "/", "Action=CreateQueue&QueueName=jobs&Attribute.1.Name=DelaySeconds&Attribute.1.Value=0").await;
+	assert_eq!(status, StatusCode::OK);
+	assert!(xml.contains(&format!("<CreateQueueResult><QueueUrl>{}</QueueUrl></CreateQueueResult>", URL)), "{}", xml);
+
+	let (_, xml) = call_query(&backend, "/000000000000/jobs", "Action=SendMessage&MessageBody=a+%3Cb%3E").await;
+	assert!(xml.contains("<SendMessageResult>") && xml.contains("<MessageId>"), "{}", xml);
+	let (_, xml) = call_query(&backend, "/", &format!("Action=ReceiveMessage&QueueUrl={}&AttributeName.1=SentTimestamp", URL)).await;
+	assert!(xml.contains("<Body>a &lt;b&gt;</Body>"), "{}", xml);
+	assert!(xml.contains("<Attribute><Name>SentTimestamp</Name>"), "{}", xml);
+
+	let (_, xml) = call_query(&backend, "/000000000000/jobs", "Action=PurgeQueue").await;
+	assert!(xml.contains("<PurgeQueueResponse"), "{}", xml);
+	assert_eq!(backend.get_queue_attributes("jobs").await.unwrap().msgs, 0);
+	let (status, xml) = call_query(&backend, "/000000000000/nope", "Action=GetQueueAttributes").await;
+	assert_eq!(status, StatusCode::BAD_REQUEST);
+	assert!(xml.contains("<Code>AWS.SimpleQueueService.NonExistentQueue</Code>"), "{}", xml);
+}
@@ -0,0 +1,35 @@
+use rsmq::*;
+
+const TRACEPARENT: &str = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+
+#[test]
+fn parse_traceparent() {
+	assert!(TraceContext::new(TRACEPARENT).is_ok());
+	// Later versions may add fields.
+	assert!(TraceContext::new("01-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra").is_ok());
+	for invalid in &[
+		"",
+		"00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7",
+		"00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra",
+		"ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+		"00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+		"00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01",
+		"00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01",
+	] {
+		assert!(TraceContext::new(invalid).is_err(), "{}", invalid);
+	}
+}
+
+#[test]
+fn headers_round_trip() {
+	let context = TraceContext::new(TRACEPARENT).unwrap().with_tracestate("congo=t61rcWkgMzE");
+	let mut headers = Headers::new();
+	headers.insert("other".into(), "value".into());
+	context.inject(&mut headers);
+	assert_eq!(headers["traceparent"], TRACEPARENT);
+	assert_eq!(TraceContext::extract(&headers), Some(context));
+
+	headers.insert("traceparent".into(), "garbage".into());
+	assert_eq!(TraceContext::extract(&headers), None);
+	assert_eq!(Message::new().trace_context(), None);
+}
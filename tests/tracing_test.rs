@@ -0,0 +1,92 @@
+#![cfg(feature = "tracing")]
+
+use rsmq::*;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing_subscriber::fmt::{format::FmtSpan, MakeWriter};
+
+// Collects the formatted events of a subscriber.
+#[derive(Clone, Default)]
+struct Output(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Output {
+	fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> { self.0.lock().unwrap().write(buf) }
+
+	fn flush(&mut self) -> std::io::Result<()> { Ok(()) }
+}
+
+impl<'a> MakeWriter<'a> for Output {
+	type Writer = Output;
+
+	fn make_writer(&'a self) -> Output { self.clone() }
+}
+
+impl Output {
+	fn text(&self) -> String { String::from_utf8(self.0.lock().unwrap().clone()).unwrap() }
+}
+
+fn subscribe() -> (Output, tracing::subscriber::DefaultGuard) {
+	let output = Output::default();
+	let subscriber = tracing_subscriber::fmt()
+		.with_max_level(tracing::Level::DEBUG)
+		.with_span_events(FmtSpan::CLOSE)
+		.with_ansi(false)
+		.with_writer(output.clone())
+		.finish();
+	(output, tracing::subscriber::set_default(subscriber))
+}
+
+#[tokio::test]
+async fn build_failure_is_traced() {
+	let (output, _guard) = subscribe();
+	let res = RsmqBuilder::new("redis://127.0.0.1:1/").name_space("traced").connection_timeout(Duration::from_millis(200)).build().await;
+	assert!(res.is_err());
+	let text = output.text();
+	assert!(text.contains("build{ns=traced}: rsmq::builder: error="), "{}", text);
+	assert!(text.contains("build{ns=traced}: rsmq::builder: close"), "{}", text);
+}
+
+#[tokio::test]
+async fn operations_are_traced() {
+	let rsmq = Rsmq::new("redis://127.0.0.1/", "test-tracing").await.unwrap();
+	rsmq.delete_queue("test-q").await.unwrap();
+	rsmq.create_queue(Queue::new("test-q", None, None, None)).await.unwrap();
+
+	let (output, _guard) = subscribe();
+	let id = rsmq.send_message("test-q", "hello", Some(0)).await.unwrap();
+	rsmq.receive_message("test-q", Some(30)).await.unwrap();
+	assert!(rsmq.receive_message("test-q", Some(30)).await.is_err());
+	let text = output.text();
+	assert!(text.contains(&format!("send_message{{ns=test-tracing qname=test-q delay=Some(0) size=5 id=\"{}\" round_trips=1 outcome=\"ok\"}}", id)), "{}", text);
+	assert!(text.contains(&format!("receive_message{{ns=test-tracing qname=test-q vt=Some(30) id=\"{}\" rc=1 round_trips=1 outcome=\"ok\"}}", id)), "{}", text);
+	assert!(text.contains("receive_message{ns=test-tracing qname=test-q vt=Some(30) round_trips=1 outcome=\"empty\"}"), "{}", text);
+}
+
+#[tokio::test]
+async fn round_trips_add_up_over_connections() {
+	let rsmq = Rsmq::new("redis://127.0.0.1/", "test-tracing-rt").await.unwrap();
+	rsmq.delete_queue("test-q").await.unwrap();
+	rsmq.create_queue(Queue::new("test-q", None, None, None)).await.unwrap();
+
+	// One connection sets the attributes, `get_queue_attributes` checks out another for TIME and HMGET.
+	let (output, _guard) = subscribe();
+	rsmq.set_queue_attributes("test-q", Some(60), None, None).await.unwrap();
+	let text = output.text();
+	assert!(text.contains("get_queue_attributes{ns=test-tracing-rt qname=test-q round_trips=2 outcome=\"ok\"}"), "{}", text);
+	assert!(text.contains("set_queue_attributes{ns=test-tracing-rt qname=test-q vt=Some(60) delay=None maxsize=None round_trips=3 outcome=\"ok\"}: rsmq: close"), "{}", text);
+}
+
+#[cfg(debug_assertions)]
+#[tokio::test]
+async fn unsettled_delivery_is_warned_about() {
+	let rsmq = Rsmq::new("redis://127.0.0.1/", "test-tracing-delivery").await.unwrap();
+	rsmq.delete_queue("test-q").await.unwrap();
+	rsmq.create_queue(Queue::new("test-q", None, None, None)).await.unwrap();
+	let id = rsmq.send_message("test-q", "job", None).await.unwrap();
+
+	let (output, _guard) = subscribe();
+	drop(rsmq.receive_delivery("test-q", None).await.unwrap());
+	let text = output.text();
+	assert!(text.contains(&format!("WARN rsmq::delivery: delivery dropped without ack, nack or dead_letter qname=test-q id={}", id)), "{}", text);
+}